@@ -8,8 +8,15 @@
 // Notes:
 //
 //  1. Configure GMP with --enable-fat so that built file is portable.
+//     For wasm32 targets, configure GMP with --disable-assembly instead,
+//     as there is no assembly for wasm32 and GMP cannot be fat without it.
+//     If GMP_MPFR_SYS_CPU is set, configure GMP for that CPU instead, for
+//     example with --build haswell-linux-gnu. With the c-sanitize-*
+//     features, configure GMP with --disable-assembly and CFLAGS with
+//     -fsanitize, which MPFR and MPC then obtain from gmp.h.
 //
 //  2. Configure GMP, MPFR and MPC with: --disable-shared --with-pic
+//     (or --enable-shared --disable-static with the c-shared feature)
 //
 //  3. Add symlinks to work around relative path issues in MPFR and MPC.
 //     In MPFR: ln -s ../gmp-build
@@ -18,22 +25,33 @@
 //  4. Unset CC and CFLAGS before building MPFR, so that both MPFR and MPC are
 //     left to their default behavior and obtain them from gmp.h.
 //
-//  5. Use relative paths for configure otherwise msys/mingw might be
+//  5. Append GMP_MPFR_SYS_<LIB>_CONFIGURE_ARGS to the configure line of
+//     each library, and GMP_MPFR_SYS_<LIB>_CFLAGS as a CFLAGS= argument.
+//
+//  6. Use relative paths for configure otherwise msys/mingw might be
 //     confused with drives and such.
+//
+//  7. Get the constants for the generated *_h.rs files by compiling a
+//     probe against each header, so that macros such as MPFR_PREC_MAX are
+//     evaluated by the C compiler. The probe prints the values if it can
+//     run on the build machine; otherwise it is compiled to assembly and
+//     the values are read from the assembly.
 
 #[cfg(unix)]
 use std::os::unix::fs as unix_fs;
 #[cfg(windows)]
 use std::os::windows::fs as windows_fs;
 use std::{
+    ascii,
     cmp::Ordering,
     env,
     ffi::{OsStr, OsString},
-    fs::{self, File},
-    io::{BufRead, BufReader, BufWriter, Result as IoResult, Write},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Result as IoResult, Write},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
-    str,
+    process::{self, Command, Stdio},
+    str, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 const GMP_DIR: &str = "gmp-6.2.1-c";
@@ -43,6 +61,26 @@ const GMP_VER: (i32, i32, i32) = (6, 2, 1);
 const MPFR_VER: (i32, i32, i32) = (4, 1, 0);
 const MPC_VER: (i32, i32, i32) = (1, 2, 1);
 
+// Cache lock and temporary directory timing.
+const LOCK_POLL: Duration = Duration::from_millis(100);
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const STALE_LOCK_AGE: Duration = Duration::from_secs(10 * 60);
+const STALE_TEMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// 64-bit FNV-1a parameters for cache keys and manifest hashes.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+#[derive(Clone, PartialEq)]
+enum GmpCpu {
+    // portable, configured with --enable-fat
+    Fat,
+    // a CPU name known to GMP, such as haswell
+    Named(String),
+    // the build CPU, which could not be detected
+    Native,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 enum Target {
     Mingw,
@@ -53,19 +91,35 @@ enum Target {
 struct Environment {
     rustc: OsString,
     c_compiler: OsString,
+    c_tools: Vec<(&'static str, OsString)>,
     target: Target,
+    host_triple: String,
     cross_target: Option<String>,
+    target_triple: String,
     c_no_tests: bool,
+    shared_libs: bool,
+    c_assert: bool,
+    gmp_cpu: GmpCpu,
+    sanitizers: Vec<&'static str>,
+    gmp_extra_args: String,
+    mpfr_extra_args: String,
+    mpc_extra_args: String,
     src_dir: PathBuf,
     out_dir: PathBuf,
     lib_dir: PathBuf,
     include_dir: PathBuf,
     build_dir: PathBuf,
+    log_dir: PathBuf,
+    cache_root: Option<PathBuf>,
     cache_dir: Option<PathBuf>,
+    cache_key: String,
+    cache_max_size: Option<u64>,
+    cache_max_age: Option<Duration>,
     jobs: OsString,
     version_prefix: String,
     version_patch: Option<u64>,
     use_system_libs: bool,
+    prebuilt_libs: Option<PrebuiltLibs>,
     workaround_47048: Workaround47048,
 }
 
@@ -77,13 +131,6 @@ enum Workaround47048 {
 
 fn main() {
     let rustc = cargo_env("RUSTC");
-    let cc = env::var_os("CC");
-    let cc_cache_dir = cc.as_ref().map(|cc| {
-        let mut dir = OsString::from("CC-");
-        dir.push(cc);
-        dir
-    });
-    let c_compiler = cc.unwrap_or_else(|| "gcc".into());
 
     let host = cargo_env("HOST")
         .into_string()
@@ -91,6 +138,27 @@ fn main() {
     let raw_target = cargo_env("TARGET")
         .into_string()
         .expect("env var TARGET having sensible characters");
+
+    let cc = target_tool_env("CC", &raw_target);
+    let c_compiler = cc
+        .clone()
+        .unwrap_or_else(|| default_c_compiler(&host, &raw_target));
+    let mut c_tools = Vec::new();
+    if let Some(cc) = cc {
+        c_tools.push(("CC", cc));
+    } else if is_wasm(&raw_target) {
+        // configure would look for <target>-gcc, which does not exist
+        c_tools.push(("CC", c_compiler.clone()));
+    }
+    if let Some(ar) = target_tool_env("AR", &raw_target) {
+        c_tools.push(("AR", ar));
+    } else if raw_target.contains("-emscripten") {
+        c_tools.push(("AR", "emar".into()));
+        c_tools.push(("RANLIB", "emranlib".into()));
+    }
+    if let Some(cflags) = target_tool_env("CFLAGS", &raw_target) {
+        c_tools.push(("CFLAGS", cflags));
+    }
     let force_cross = there_is_env("CARGO_FEATURE_FORCE_CROSS");
     if !force_cross && !compilation_target_allowed(&host, &raw_target) {
         panic!(
@@ -112,12 +180,26 @@ fn main() {
     } else {
         Some(raw_target)
     };
+    let target_triple = cross_target.as_ref().unwrap_or(&host).clone();
 
     let c_no_tests = there_is_env("CARGO_FEATURE_C_NO_TESTS");
+    let shared_libs = there_is_env("CARGO_FEATURE_C_SHARED");
+    let c_assert = there_is_env("CARGO_FEATURE_C_ASSERT");
 
     let src_dir = PathBuf::from(cargo_env("CARGO_MANIFEST_DIR"));
     let out_dir = PathBuf::from(cargo_env("OUT_DIR"));
 
+    let gmp_cpu = gmp_cpu(&src_dir, &out_dir, cross_target.as_ref(), &target_triple);
+    let sanitizers = sanitizers();
+    if !sanitizers.is_empty() {
+        if is_wasm(&target_triple) {
+            panic!("the c-sanitize-* features are not supported for wasm32 targets");
+        }
+        if gmp_cpu != GmpCpu::Fat {
+            panic!("GMP_MPFR_SYS_CPU cannot be used with the c-sanitize-* features");
+        }
+    }
+
     let (version_prefix, version_patch) = get_version();
 
     println!("cargo:rerun-if-env-changed=GMP_MPFR_SYS_CACHE");
@@ -126,39 +208,61 @@ fn main() {
         Some(c) => Some(PathBuf::from(c)),
         None => system_cache_dir().map(|c| c.join("gmp-mpfr-sys")),
     };
-    let cache_target = cross_target.as_ref().unwrap_or(&host);
+    let cache_root = cache_dir.clone();
     let cache_dir = cache_dir
         .map(|cache| cache.join(&version_prefix))
-        .map(|cache| cache.join(cache_target))
-        .map(|cache| match cc_cache_dir {
-            Some(dir) => cache.join(dir),
-            None => cache,
-        });
+        .map(|cache| cache.join(&target_triple));
+    let cache_max_size = cache_max_size();
+    let cache_max_age = cache_max_age();
+    // the key would not tell which CPU the libraries are tuned for
+    let (cache_root, cache_dir) = if gmp_cpu == GmpCpu::Native {
+        println!("$ #Not caching since the native CPU is unknown");
+        (None, None)
+    } else {
+        (cache_root, cache_dir)
+    };
 
     let use_system_libs = there_is_env("CARGO_FEATURE_USE_SYSTEM_LIBS");
-    if use_system_libs {
-        match target {
-            Target::Msvc => panic!("the use-system-libs feature is not supported on this target"),
-            Target::Mingw => mingw_pkg_config_libdir_or_panic(),
-            _ => {}
-        }
+    if use_system_libs && target == Target::Msvc {
+        panic!("the use-system-libs feature is not supported on this target");
     }
+    let prebuilt_libs = if use_system_libs {
+        None
+    } else {
+        prebuilt_libs()
+    };
     let mut env = Environment {
         rustc,
         c_compiler,
+        c_tools,
         target,
+        host_triple: host,
         cross_target,
+        target_triple,
         c_no_tests,
+        shared_libs,
+        c_assert,
+        gmp_cpu,
+        sanitizers,
+        gmp_extra_args: extra_configure_args("GMP"),
+        mpfr_extra_args: extra_configure_args("MPFR"),
+        mpc_extra_args: extra_configure_args("MPC"),
         src_dir,
         out_dir: out_dir.clone(),
         lib_dir: out_dir.join("lib"),
         include_dir: out_dir.join("include"),
         build_dir: out_dir.join("build"),
+        log_dir: out_dir.join("logs"),
+        cache_root,
         cache_dir,
+        cache_key: String::new(),
+        cache_max_size,
+        cache_max_age,
         jobs: cargo_env("NUM_JOBS"),
         version_prefix,
         version_patch,
         use_system_libs,
+        prebuilt_libs,
         workaround_47048: Workaround47048::No,
     };
     env.check_feature(
@@ -167,6 +271,10 @@ fn main() {
         None,
     );
     env.check_feature("unsafe_in_unsafe", TRY_UNSAFE_IN_UNSAFE, None);
+    env.check_feature("c_unwind", TRY_C_UNWIND, None);
+    if !target_has_file() {
+        println!("cargo:rustc-cfg=no_file");
+    }
 
     // make sure we have target directories
     create_dir_or_panic(&env.lib_dir);
@@ -174,13 +282,171 @@ fn main() {
 
     env.workaround_47048 = check_for_bug_47048(&env);
 
+    if !env.use_system_libs && env.prebuilt_libs.is_none() {
+        // keep builds with different inputs separate in the cache
+        env.cache_key = cache_key(&env);
+        let hash = format!("{:016x}", fnv1a(FNV_OFFSET_BASIS, env.cache_key.as_bytes()));
+        env.cache_dir = env.cache_dir.take().map(|cache| cache.join(hash));
+    }
+
     if env.use_system_libs {
         check_system_libs(&env);
+    } else if let Some(ref prebuilt_libs) = env.prebuilt_libs {
+        use_prebuilt_libs(&env, prebuilt_libs);
     } else {
         compile_libs(&env);
     }
 }
 
+// Directories of a prebuilt library and its header.
+struct PrebuiltDirs {
+    lib_dir: PathBuf,
+    include_dir: PathBuf,
+}
+
+struct PrebuiltLibs {
+    gmp: PrebuiltDirs,
+    mpfr: Option<PrebuiltDirs>,
+    mpc: Option<PrebuiltDirs>,
+}
+
+fn prebuilt_libs() -> Option<PrebuiltLibs> {
+    let gmp = prebuilt_dirs("GMP", None);
+    let mpfr_needed = there_is_env("CARGO_FEATURE_MPFR");
+    let mpc_needed = there_is_env("CARGO_FEATURE_MPC");
+    let mpfr = prebuilt_dirs("MPFR", gmp.as_ref());
+    let mpc = prebuilt_dirs("MPC", mpfr.as_ref().or(gmp.as_ref()));
+    let gmp = match gmp {
+        Some(gmp) => gmp,
+        None => {
+            if mpfr.is_some() || mpc.is_some() {
+                panic!("GMP_MPFR_SYS_GMP_LIB_DIR must be set to use prebuilt MPFR or MPC");
+            }
+            return None;
+        }
+    };
+    Some(PrebuiltLibs {
+        gmp,
+        mpfr: if mpfr_needed { mpfr } else { None },
+        mpc: if mpc_needed { mpc } else { None },
+    })
+}
+
+// Reads GMP_MPFR_SYS_<LIB>_LIB_DIR and GMP_MPFR_SYS_<LIB>_INCLUDE_DIR.
+// If the include directory is not set, it defaults to the include
+// directory next to the library directory. If neither is set, the
+// directories default to those of fallback.
+fn prebuilt_dirs(lib: &str, fallback: Option<&PrebuiltDirs>) -> Option<PrebuiltDirs> {
+    let lib_var = format!("GMP_MPFR_SYS_{}_LIB_DIR", lib);
+    let include_var = format!("GMP_MPFR_SYS_{}_INCLUDE_DIR", lib);
+    println!("cargo:rerun-if-env-changed={}", lib_var);
+    println!("cargo:rerun-if-env-changed={}", include_var);
+    let lib_dir = env::var_os(&lib_var).filter(|x| !x.is_empty());
+    let include_dir = env::var_os(&include_var).filter(|x| !x.is_empty());
+    match (lib_dir, include_dir) {
+        (Some(lib_dir), include_dir) => {
+            let lib_dir = PathBuf::from(lib_dir);
+            let include_dir = match include_dir {
+                Some(dir) => PathBuf::from(dir),
+                None => lib_dir.join("..").join("include"),
+            };
+            Some(PrebuiltDirs {
+                lib_dir,
+                include_dir,
+            })
+        }
+        (None, Some(_)) => panic!("{} is set but {} is not set", include_var, lib_var),
+        (None, None) => fallback.map(|dirs| PrebuiltDirs {
+            lib_dir: dirs.lib_dir.clone(),
+            include_dir: dirs.include_dir.clone(),
+        }),
+    }
+}
+
+fn use_prebuilt_libs(env: &Environment, prebuilt_libs: &PrebuiltLibs) {
+    // Copy all the headers before probing, as mpfr.h includes gmp.h.
+    let gmp_header = copy_prebuilt_header(env, &prebuilt_libs.gmp, "gmp");
+    let mpfr_header = prebuilt_libs
+        .mpfr
+        .as_ref()
+        .map(|mpfr| copy_prebuilt_header(env, mpfr, "mpfr"));
+    let mpc_header = prebuilt_libs
+        .mpc
+        .as_ref()
+        .map(|mpc| copy_prebuilt_header(env, mpc, "mpc"));
+
+    // Libraries are listed in link order.
+    let mut libs = Vec::new();
+    if let (Some(mpc), Some(header)) = (&prebuilt_libs.mpc, mpc_header) {
+        let out_file = env.out_dir.join("mpc_h.rs");
+        probe_header(env, "mpc", &header)
+            .and_then(|values| process_mpc_header(env, &values, Some(&out_file)))
+            .unwrap_or_else(|e| panic!("{}", e));
+        libs.push(prebuilt_lib(env, mpc, "mpc"));
+    }
+    if let (Some(mpfr), Some(header)) = (&prebuilt_libs.mpfr, mpfr_header) {
+        let out_file = env.out_dir.join("mpfr_h.rs");
+        probe_header(env, "mpfr", &header)
+            .and_then(|values| process_mpfr_header(env, &values, Some(&out_file)))
+            .unwrap_or_else(|e| panic!("{}", e));
+        libs.push(prebuilt_lib(env, mpfr, "mpfr"));
+    }
+    let out_file = env.out_dir.join("gmp_h.rs");
+    probe_header(env, "gmp", &gmp_header)
+        .and_then(|values| process_gmp_header(env, &values, Some(&out_file)))
+        .unwrap_or_else(|e| panic!("{}", e));
+    libs.push(prebuilt_lib(env, &prebuilt_libs.gmp, "gmp"));
+
+    write_link_info(
+        env,
+        prebuilt_libs.mpfr.is_some(),
+        prebuilt_libs.mpc.is_some(),
+        &libs,
+    );
+}
+
+// Copies the header into include_dir so that DEP_GMP_INCLUDE_DIR has
+// the same contents as when the libraries are built from source.
+fn copy_prebuilt_header(env: &Environment, dirs: &PrebuiltDirs, name: &str) -> PathBuf {
+    let filename = format!("{}.h", name);
+    let src = dirs.include_dir.join(&filename);
+    println!("cargo:rerun-if-changed={}", src.display());
+    let dst = env.include_dir.join(&filename);
+    copy_file_or_panic(&src, &dst);
+    dst
+}
+
+// Links statically if there is a static library, otherwise links
+// dynamically. Static libraries are copied into lib_dir.
+fn prebuilt_lib(env: &Environment, dirs: &PrebuiltDirs, name: &str) -> ExternalLib {
+    let static_name = format!("lib{}.a", name);
+    let static_lib = dirs.lib_dir.join(&static_name);
+    if static_lib.is_file() {
+        println!("cargo:rerun-if-changed={}", static_lib.display());
+        copy_file_or_panic(&static_lib, &env.lib_dir.join(&static_name));
+        return ExternalLib {
+            cflags: Vec::new(),
+            link_dirs: Vec::new(),
+            link_libs: vec![format!("static={}", name)],
+        };
+    }
+    for suffix in &[".so", ".dylib", ".dll.a"] {
+        let shared_lib = dirs.lib_dir.join(format!("lib{}{}", name, suffix));
+        if shared_lib.is_file() {
+            println!("cargo:rerun-if-changed={}", shared_lib.display());
+            return ExternalLib {
+                cflags: Vec::new(),
+                link_dirs: vec![dirs.lib_dir.clone()],
+                link_libs: vec![format!("dylib={}", name)],
+            };
+        }
+    }
+    panic!(
+        "Cannot find static or shared {} library in {:?}",
+        name, dirs.lib_dir
+    );
+}
+
 fn check_system_libs(env: &Environment) {
     let build_dir_existed = env.build_dir.exists();
     let try_dir = env.build_dir.join("system_libs");
@@ -188,77 +454,47 @@ fn check_system_libs(env: &Environment) {
     create_dir_or_panic(&try_dir);
     println!("$ cd {:?}", try_dir);
 
-    println!("$ #Check for system GMP");
-    create_file_or_panic(&try_dir.join("system_gmp.c"), SYSTEM_GMP_C);
-
-    let mut cmd = Command::new(&env.c_compiler);
-    cmd.current_dir(&try_dir)
-        .args(&["-fPIC", "system_gmp.c", "-lgmp", "-o", "system_gmp.exe"]);
-    execute(cmd);
-
-    cmd = Command::new(try_dir.join("system_gmp.exe"));
-    cmd.current_dir(&try_dir);
-    execute(cmd);
-    process_gmp_header(
-        env,
-        &try_dir.join("system_gmp.out"),
-        Some(&env.out_dir.join("gmp_h.rs")),
-    )
-    .unwrap_or_else(|e| panic!("{}", e));
-
     let feature_mpfr = there_is_env("CARGO_FEATURE_MPFR");
     let feature_mpc = there_is_env("CARGO_FEATURE_MPC");
 
-    if feature_mpfr {
-        println!("$ #Check for system MPFR");
-        create_file_or_panic(&try_dir.join("system_mpfr.c"), SYSTEM_MPFR_C);
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_LIBDIR");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_SYSROOT_DIR");
 
-        cmd = Command::new(&env.c_compiler);
-        cmd.current_dir(&try_dir).args(&[
-            "-fPIC",
-            "system_mpfr.c",
-            "-lmpfr",
-            "-lgmp",
-            "-o",
-            "system_mpfr.exe",
-        ]);
-        execute(cmd);
+    // Libraries are listed in link order, so that each library comes
+    // before the libraries it depends on.
+    let mut system_libs = Vec::new();
+    if feature_mpc {
+        system_libs.push(probe_system_lib(env, "mpc"));
+    }
+    if feature_mpfr {
+        system_libs.push(probe_system_lib(env, "mpfr"));
+    }
+    system_libs.push(probe_system_lib(env, "gmp"));
 
-        cmd = Command::new(try_dir.join("system_mpfr.exe"));
-        cmd.current_dir(&try_dir);
-        execute(cmd);
-        process_mpfr_header(
-            env,
-            &try_dir.join("system_mpfr.out"),
-            Some(&env.out_dir.join("mpfr_h.rs")),
-        )
+    println!("$ #Check for system GMP");
+    let gmp_libs = &system_libs[system_libs.len() - 1..];
+    let out_file = env.out_dir.join("gmp_h.rs");
+    run_probe(env, &try_dir, "gmp", &[], gmp_libs)
+        .and_then(|values| process_gmp_header(env, &values, Some(&out_file)))
         .unwrap_or_else(|e| panic!("{}", e));
+
+    if feature_mpfr {
+        println!("$ #Check for system MPFR");
+        let mpfr_libs = &system_libs[system_libs.len() - 2..];
+        let out_file = env.out_dir.join("mpfr_h.rs");
+        run_probe(env, &try_dir, "mpfr", &[], mpfr_libs)
+            .and_then(|values| process_mpfr_header(env, &values, Some(&out_file)))
+            .unwrap_or_else(|e| panic!("{}", e));
     }
 
     if feature_mpc {
         println!("$ #Check for system MPC");
-        create_file_or_panic(&try_dir.join("system_mpc.c"), SYSTEM_MPC_C);
-
-        cmd = Command::new(&env.c_compiler);
-        cmd.current_dir(&try_dir).args(&[
-            "-fPIC",
-            "system_mpc.c",
-            "-lmpc",
-            "-lgmp",
-            "-o",
-            "system_mpc.exe",
-        ]);
-        execute(cmd);
-
-        cmd = Command::new(try_dir.join("system_mpc.exe"));
-        cmd.current_dir(&try_dir);
-        execute(cmd);
-        process_mpc_header(
-            env,
-            &try_dir.join("system_mpc.out"),
-            Some(&env.out_dir.join("mpc_h.rs")),
-        )
-        .unwrap_or_else(|e| panic!("{}", e));
+        let out_file = env.out_dir.join("mpc_h.rs");
+        run_probe(env, &try_dir, "mpc", &[], &system_libs)
+            .and_then(|values| process_mpc_header(env, &values, Some(&out_file)))
+            .unwrap_or_else(|e| panic!("{}", e));
     }
 
     if !there_is_env("CARGO_FEATURE_CNODELETE") {
@@ -269,19 +505,89 @@ fn check_system_libs(env: &Environment) {
         }
     }
 
-    write_link_info(env, feature_mpfr, feature_mpc);
+    write_link_info(env, feature_mpfr, feature_mpc, &system_libs);
+}
+
+// Compiler and linker arguments for a library that is not built from
+// the bundled sources. The link_libs are passed to -l and to
+// cargo:rustc-link-lib, so they can only have a kind prefix such as
+// static= if they are not used to compile probes.
+struct ExternalLib {
+    cflags: Vec<String>,
+    link_dirs: Vec<PathBuf>,
+    link_libs: Vec<String>,
+}
+
+// Uses pkg-config if available, otherwise falls back to linking with
+// -l<name> and no extra search paths. On MinGW pkg-config is required.
+fn probe_system_lib(env: &Environment, name: &str) -> ExternalLib {
+    if let Some(lib) = pkg_config(name) {
+        return lib;
+    }
+    if env.target == Target::Mingw {
+        panic!(
+            "pkg-config is required to find the system {} library on this target",
+            name
+        );
+    }
+    println!("pkg-config cannot find {}, using -l{}", name, name);
+    ExternalLib {
+        cflags: Vec::new(),
+        link_dirs: Vec::new(),
+        link_libs: vec![name.to_string()],
+    }
+}
+
+fn pkg_config(name: &str) -> Option<ExternalLib> {
+    let pkg_config = env::var_os("PKG_CONFIG").unwrap_or_else(|| "pkg-config".into());
+    let query = |option: &str| -> Option<Vec<String>> {
+        let mut cmd = Command::new(&pkg_config);
+        cmd.arg(option).arg(name);
+        println!("$ {:?}", cmd);
+        let output = cmd.output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let output = String::from_utf8(output.stdout).ok()?;
+        Some(output.split_whitespace().map(String::from).collect())
+    };
+    let cflags = query("--cflags")?;
+    let link_dirs = query("--libs-only-L")?
+        .into_iter()
+        .filter(|arg| arg.starts_with("-L"))
+        .map(|arg| PathBuf::from(&arg[2..]))
+        .collect();
+    let mut link_libs = query("--libs-only-l")?
+        .into_iter()
+        .filter(|arg| arg.starts_with("-l"))
+        .map(|arg| arg[2..].to_string())
+        .collect::<Vec<_>>();
+    if link_libs.is_empty() {
+        link_libs.push(name.to_string());
+    }
+    Some(ExternalLib {
+        cflags,
+        link_dirs,
+        link_libs,
+    })
 }
 
 fn compile_libs(env: &Environment) {
-    let gmp_ah = (env.lib_dir.join("libgmp.a"), env.include_dir.join("gmp.h"));
+    let gmp_ah = (
+        env.lib_dir.join(lib_file(env, "gmp")),
+        env.include_dir.join("gmp.h"),
+    );
     let mpc_ah = if there_is_env("CARGO_FEATURE_MPC") {
-        Some((env.lib_dir.join("libmpc.a"), env.include_dir.join("mpc.h")))
+        Some((
+            env.lib_dir.join(lib_file(env, "mpc")),
+            env.include_dir.join("mpc.h"),
+        ))
     } else {
         None
     };
     let mpfr_ah = if mpc_ah.is_some() || there_is_env("CARGO_FEATURE_MPFR") {
         Some((
-            env.lib_dir.join("libmpfr.a"),
+            env.lib_dir.join(lib_file(env, "mpfr")),
             env.include_dir.join("mpfr.h"),
         ))
     } else {
@@ -292,44 +598,86 @@ fn compile_libs(env: &Environment) {
         gmp: compile_gmp,
         mpfr: compile_mpfr,
         mpc: compile_mpc,
+        cache_entry,
     } = need_compile(env, &gmp_ah, &mpfr_ah, &mpc_ah);
-    if compile_gmp {
+    let compile_any = compile_gmp || compile_mpfr || compile_mpc;
+    if compile_any {
         check_for_msvc(env);
+        for &(name, ref value) in &env.c_tools {
+            println!("$ export {}={:?}", name, value);
+            env::set_var(name, value);
+        }
         remove_dir_or_panic(&env.build_dir);
         create_dir_or_panic(&env.build_dir);
-        link_dir(&env.src_dir.join(GMP_DIR), &env.build_dir.join("gmp-src"));
-        let (ref a, ref h) = gmp_ah;
-        build_gmp(env, a, h);
+        remove_dir_or_panic(&env.log_dir);
+        create_dir_or_panic(&env.log_dir);
+        if compile_gmp {
+            link_dir(&env.src_dir.join(GMP_DIR), &env.build_dir.join("gmp-src"));
+            let (ref a, ref h) = gmp_ah;
+            build_gmp(env, a, h);
+        } else {
+            prepare_built_gmp(env, &gmp_ah);
+        }
+        if env.shared_libs {
+            // MPFR and MPC tests and configure checks need to find
+            // the uninstalled shared libraries at run time.
+            let dirs = [
+                env.build_dir.join("gmp-build").join(".libs"),
+                env.build_dir.join("mpfr-build").join("src").join(".libs"),
+            ];
+            let path = prepend_dyld_path(&dirs).expect("cannot set shared library path");
+            println!("$ export {}={:?}", DYLD_PATH_VAR, path);
+            env::set_var(DYLD_PATH_VAR, path);
+        }
     }
     if compile_mpfr {
         link_dir(&env.src_dir.join(MPFR_DIR), &env.build_dir.join("mpfr-src"));
         let (ref a, ref h) = *mpfr_ah.as_ref().unwrap();
-        build_mpfr(env, a, h);
+        build_mpfr(env, a, h, compile_gmp);
+    } else if compile_mpc {
+        prepare_built_mpfr(env, mpfr_ah.as_ref().unwrap());
     }
     if compile_mpc {
         link_dir(&env.src_dir.join(MPC_DIR), &env.build_dir.join("mpc-src"));
         let (ref a, ref h) = *mpc_ah.as_ref().unwrap();
         build_mpc(env, a, h);
     }
-    if compile_gmp {
+    if compile_any {
         if !there_is_env("CARGO_FEATURE_CNODELETE") {
+            // logs are only kept after a failure
             remove_dir_or_panic(&env.build_dir);
+            remove_dir_or_panic(&env.log_dir);
         }
-        if save_cache(env, &gmp_ah, &mpfr_ah, &mpc_ah) {
+        let added = match cache_entry {
+            Some(ref entry) if !compile_gmp => {
+                let new_mpfr = if compile_mpfr { &mpfr_ah } else { &None };
+                add_to_cache(env, entry, new_mpfr, &mpc_ah)
+            }
+            _ => None,
+        };
+        let saved = added.or_else(|| save_cache(env, &gmp_ah, &mpfr_ah, &mpc_ah));
+        if let Some(dir) = saved {
             clear_cache_redundancies(env, mpfr_ah.is_some(), mpc_ah.is_some());
+            evict_cache(env, &dir);
         }
     }
-    process_gmp_header(env, &gmp_ah.1, Some(&env.out_dir.join("gmp_h.rs")))
+    let out_file = env.out_dir.join("gmp_h.rs");
+    probe_header(env, "gmp", &gmp_ah.1)
+        .and_then(|values| process_gmp_header(env, &values, Some(&out_file)))
         .unwrap_or_else(|e| panic!("{}", e));
     if let Some(ref mpfr_ah) = mpfr_ah {
-        process_mpfr_header(env, &mpfr_ah.1, Some(&env.out_dir.join("mpfr_h.rs")))
+        let out_file = env.out_dir.join("mpfr_h.rs");
+        probe_header(env, "mpfr", &mpfr_ah.1)
+            .and_then(|values| process_mpfr_header(env, &values, Some(&out_file)))
             .unwrap_or_else(|e| panic!("{}", e));
     }
     if let Some(ref mpc_ah) = mpc_ah {
-        process_mpc_header(env, &mpc_ah.1, Some(&env.out_dir.join("mpc_h.rs")))
+        let out_file = env.out_dir.join("mpc_h.rs");
+        probe_header(env, "mpc", &mpc_ah.1)
+            .and_then(|values| process_mpc_header(env, &values, Some(&out_file)))
             .unwrap_or_else(|e| panic!("{}", e));
     }
-    write_link_info(env, mpfr_ah.is_some(), mpc_ah.is_some());
+    write_link_info(env, mpfr_ah.is_some(), mpc_ah.is_some(), &[]);
 }
 
 fn get_version() -> (String, Option<u64>) {
@@ -356,6 +704,8 @@ struct NeedCompile {
     gmp: bool,
     mpfr: bool,
     mpc: bool,
+    // the cache entry from which some of the libraries were loaded
+    cache_entry: Option<PathBuf>,
 }
 
 fn need_compile(
@@ -374,15 +724,17 @@ fn need_compile(
         None => true,
     };
     if gmp_fine && mpfr_fine && mpc_fine {
-        if should_save_cache(env, mpfr_ah.is_some(), mpc_ah.is_some())
-            && save_cache(env, gmp_ah, mpfr_ah, mpc_ah)
-        {
-            clear_cache_redundancies(env, mpfr_ah.is_some(), mpc_ah.is_some());
+        if should_save_cache(env, mpfr_ah.is_some(), mpc_ah.is_some()) {
+            if let Some(dir) = save_cache(env, gmp_ah, mpfr_ah, mpc_ah) {
+                clear_cache_redundancies(env, mpfr_ah.is_some(), mpc_ah.is_some());
+                evict_cache(env, &dir);
+            }
         }
         return NeedCompile {
             gmp: false,
             mpfr: false,
             mpc: false,
+            cache_entry: None,
         };
     } else if load_cache(env, gmp_ah, mpfr_ah, mpc_ah) {
         // if loading cache works, we're done
@@ -390,15 +742,31 @@ fn need_compile(
             gmp: false,
             mpfr: false,
             mpc: false,
+            cache_entry: None,
         };
     }
-    let need_mpc = !mpc_fine;
-    let need_mpfr = need_mpc || !mpfr_fine;
-    let need_gmp = need_mpfr || !gmp_fine;
+    // MPFR and MPC can be built against GMP and MPFR libraries that
+    // were built before, but GMP cannot be rebuilt under them.
+    let mut cache_entry = None;
+    let (gmp_fine, mpfr_fine) = if gmp_fine && mpfr_fine {
+        (true, true)
+    } else {
+        match load_cache_partial(env, gmp_ah, mpfr_ah) {
+            Some((dir, mpfr_loaded)) => {
+                cache_entry = Some(dir);
+                (true, mpfr_loaded)
+            }
+            None => (gmp_fine, mpfr_fine && gmp_fine),
+        }
+    };
+    let need_gmp = !gmp_fine;
+    let need_mpfr = mpfr_ah.is_some() && (need_gmp || !mpfr_fine);
+    let need_mpc = mpc_ah.is_some() && (need_mpfr || !mpc_fine);
     NeedCompile {
         gmp: need_gmp,
         mpfr: need_mpfr,
         mpc: need_mpc,
+        cache_entry,
     }
 }
 
@@ -407,35 +775,570 @@ fn save_cache(
     gmp_ah: &(PathBuf, PathBuf),
     mpfr_ah: &Option<(PathBuf, PathBuf)>,
     mpc_ah: &Option<(PathBuf, PathBuf)>,
-) -> bool {
-    let cache_dir = match env.cache_dir {
-        Some(ref s) => s,
-        None => return false,
-    };
+) -> Option<PathBuf> {
+    let cache_dir = env.cache_dir.as_ref()?;
+    let dir = cache_entry_dir(env, cache_dir);
+
+    // Copy into a temporary directory first, and then rename it into
+    // place, so that other builds never see a partially written cache.
+    let tmp_dir = cache_temp_dir(cache_dir);
+    let mut ok = create_dir(&tmp_dir).is_ok();
+    let (ref a, ref h) = *gmp_ah;
+    ok = ok && copy_lib(env, "gmp", a, &tmp_dir.join(lib_file(env, "gmp"))).is_ok();
+    ok = ok && copy_file(h, &tmp_dir.join("gmp.h")).is_ok();
+    if let Some((ref a, ref h)) = *mpfr_ah {
+        ok = ok && copy_lib(env, "mpfr", a, &tmp_dir.join(lib_file(env, "mpfr"))).is_ok();
+        ok = ok && copy_file(h, &tmp_dir.join("mpfr.h")).is_ok();
+    }
+    if let Some((ref a, ref h)) = *mpc_ah {
+        ok = ok && copy_lib(env, "mpc", a, &tmp_dir.join(lib_file(env, "mpc"))).is_ok();
+        ok = ok && copy_file(h, &tmp_dir.join("mpc.h")).is_ok();
+    }
+    ok = ok && fs::write(tmp_dir.join(CACHE_KEY), &env.cache_key).is_ok();
+    ok = ok && write_manifest(&tmp_dir).is_ok();
+    if ok {
+        ok = match CacheLock::acquire(cache_dir) {
+            Some(_lock) => replace_cache_dir(cache_dir, &tmp_dir, &dir).is_ok(),
+            None => false,
+        };
+    }
+    if tmp_dir.exists() {
+        let _ = remove_dir(&tmp_dir);
+    }
+    if ok {
+        record_cache_use(&dir);
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+// The cache entry that a build saves to, which is the version
+// directory, or its c-no-tests subdirectory if the C tests were not
+// run.
+fn cache_entry_dir(env: &Environment, cache_dir: &Path) -> PathBuf {
     let version_dir = match env.version_patch {
         None => cache_dir.join(&env.version_prefix),
         Some(patch) => cache_dir.join(format!("{}.{}", env.version_prefix, patch)),
     };
-    let mut ok = create_dir(&version_dir).is_ok();
-    let dir = if env.c_no_tests {
-        let no_tests_dir = version_dir.join("c-no-tests");
-        ok = ok && create_dir(&no_tests_dir).is_ok();
-        no_tests_dir
+    if env.c_no_tests {
+        version_dir.join("c-no-tests")
     } else {
         version_dir
+    }
+}
+
+// Adds newly built MPFR and MPC libraries to the cache entry that GMP,
+// and possibly MPFR, were loaded from. Entries that were tested are
+// only added to if the new libraries were tested too; otherwise a new
+// entry is saved.
+fn add_to_cache(
+    env: &Environment,
+    entry: &Path,
+    mpfr_ah: &Option<(PathBuf, PathBuf)>,
+    mpc_ah: &Option<(PathBuf, PathBuf)>,
+) -> Option<PathBuf> {
+    let cache_dir = env.cache_dir.as_ref()?;
+    if entry != cache_entry_dir(env, cache_dir) {
+        return None;
+    }
+    let _lock = CacheLock::acquire(cache_dir)?;
+    // the entry may have been replaced or evicted meanwhile
+    let manifest = read_valid_manifest(entry)?;
+    let listed = |name: &str| manifest.iter().any(|e| e.name == name);
+    let mut ok = true;
+    let new_libs = [("mpfr", mpfr_ah), ("mpc", mpc_ah)];
+    for &(name, ah) in &new_libs {
+        let (ref a, ref h) = match *ah {
+            Some(ref ah) => ah,
+            None => continue,
+        };
+        let (lib, header) = (lib_file(env, name), format!("{}.h", name));
+        if listed(&lib) {
+            continue;
+        }
+        // Copy under temporary names and rename into place, so that
+        // builds loading the entry meanwhile never see partial files.
+        let tmp_dir = cache_temp_dir(cache_dir);
+        ok = ok && create_dir(&tmp_dir).is_ok();
+        ok = ok && copy_lib(env, name, a, &tmp_dir.join(&lib)).is_ok();
+        ok = ok && copy_file(h, &tmp_dir.join(&header)).is_ok();
+        if ok {
+            let files = fs::read_dir(&tmp_dir).map(|dir| {
+                dir.filter_map(Result::ok)
+                    .map(|e| e.file_name())
+                    .collect::<Vec<_>>()
+            });
+            ok = match files {
+                Ok(files) => files
+                    .iter()
+                    .all(|file| rename(&tmp_dir.join(file), &entry.join(file)).is_ok()),
+                Err(_) => false,
+            };
+        }
+        if tmp_dir.exists() {
+            let _ = remove_dir(&tmp_dir);
+        }
+    }
+    // the manifest is replaced last, so it never lists missing files
+    ok = ok && write_manifest(entry).is_ok();
+    if ok {
+        record_cache_use(entry);
+        Some(entry.to_path_buf())
+    } else {
+        None
+    }
+}
+
+// Replaces dst with src using renames. If dst is a version directory
+// with a c-no-tests subdirectory, the subdirectory is kept. The cache
+// lock must be held.
+fn replace_cache_dir(cache_dir: &Path, src: &Path, dst: &Path) -> IoResult<()> {
+    if !dst.exists() {
+        if let Some(parent) = dst.parent() {
+            create_dir(parent)?;
+        }
+        return rename(src, dst);
+    }
+    let no_tests_dir = dst.join("c-no-tests");
+    if no_tests_dir.is_dir() {
+        rename(&no_tests_dir, &src.join("c-no-tests"))?;
+    }
+    let old_dir = cache_temp_dir(cache_dir);
+    rename(dst, &old_dir)?;
+    rename(src, dst)?;
+    remove_dir(&old_dir)
+}
+
+// A unique temporary directory inside the cache directory, so that it
+// is on the same file system and can be renamed into place. The name
+// starts with a dot so that it is never taken for a version directory.
+fn cache_temp_dir(cache_dir: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    cache_dir.join(format!(".tmp-{}-{}", process::id(), nanos))
+}
+
+// Removes temporary directories left behind by interrupted builds. The
+// cache lock must be held.
+fn clear_cache_temp_dirs(cache_dir: &Path) {
+    let dir = match fs::read_dir(cache_dir) {
+        Ok(dir) => dir,
+        Err(_) => return,
     };
-    let (ref a, ref h) = *gmp_ah;
-    ok = ok && copy_file(a, &dir.join("libgmp.a")).is_ok();
-    ok = ok && copy_file(h, &dir.join("gmp.h")).is_ok();
-    if let Some((ref a, ref h)) = *mpfr_ah {
-        ok = ok && copy_file(a, &dir.join("libmpfr.a")).is_ok();
-        ok = ok && copy_file(h, &dir.join("mpfr.h")).is_ok();
+    for entry in dir.filter_map(Result::ok) {
+        let path = entry.path();
+        let is_temp = entry.file_name().to_string_lossy().starts_with(".tmp-");
+        if is_temp && file_age(&path).unwrap_or_default() > STALE_TEMP_AGE {
+            let _ = remove_dir(&path);
+        }
     }
-    if let Some((ref a, ref h)) = *mpc_ah {
-        ok = ok && copy_file(a, &dir.join("libmpc.a")).is_ok();
-        ok = ok && copy_file(h, &dir.join("mpc.h")).is_ok();
+}
+
+// A lock file that serializes changes to the cache directory, and
+// loads from it, by concurrent builds. Eviction takes the lock of the
+// cache root, and then the lock of the cache directory of each entry
+// it removes. The lock is released when dropped.
+struct CacheLock {
+    path: PathBuf,
+}
+
+impl CacheLock {
+    // Returns None if the lock cannot be acquired in reasonable time, in
+    // which case the cache is left alone.
+    fn acquire(cache_dir: &Path) -> Option<CacheLock> {
+        create_dir(cache_dir).ok()?;
+        let path = cache_dir.join(".lock");
+        let mut waited = Duration::from_secs(0);
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = writeln!(file, "{}", process::id());
+                    return Some(CacheLock { path });
+                }
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(_) => return None,
+            }
+            // a lock left behind by a killed build
+            if file_age(&path).unwrap_or_default() > STALE_LOCK_AGE {
+                println!("$ rm {:?}", path);
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if waited >= LOCK_TIMEOUT {
+                println!("$ #Timed out waiting for {:?}", path);
+                return None;
+            }
+            thread::sleep(LOCK_POLL);
+            waited += LOCK_POLL;
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn file_age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+// The time of the last build that used a cache entry, in seconds since
+// the Unix epoch. This is not part of the manifest as it is updated on
+// every use.
+const LAST_USE: &str = "last-use.txt";
+
+fn record_cache_use(dir: &Path) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let _ = fs::write(dir.join(LAST_USE), format!("{}\n", secs));
+}
+
+fn cache_last_use(dir: &Path) -> SystemTime {
+    let recorded = fs::read_to_string(dir.join(LAST_USE))
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    // entries saved before last-use times were recorded, or before
+    // manifests were written
+    let modified = |file: &str| fs::metadata(dir.join(file)).and_then(|m| m.modified()).ok();
+    recorded
+        .or_else(|| modified(MANIFEST))
+        .or_else(|| modified("gmp.h"))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+// Parses GMP_MPFR_SYS_CACHE_MAX_SIZE, which is a number of bytes with
+// an optional suffix K, M or G.
+fn cache_max_size() -> Option<u64> {
+    println!("cargo:rerun-if-env-changed=GMP_MPFR_SYS_CACHE_MAX_SIZE");
+    let val = env::var("GMP_MPFR_SYS_CACHE_MAX_SIZE").ok()?;
+    let trimmed = val.trim();
+    let (num, mult) = match trimmed.chars().last() {
+        Some('K') | Some('k') => (&trimmed[..trimmed.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    match num.trim().parse::<u64>() {
+        Ok(n) => Some(n.saturating_mul(mult)),
+        Err(_) => panic!("Cannot parse GMP_MPFR_SYS_CACHE_MAX_SIZE: {:?}", val),
+    }
+}
+
+// Parses GMP_MPFR_SYS_CACHE_MAX_AGE, which is a number of days, or a
+// number with a suffix s, m, h or d.
+fn cache_max_age() -> Option<Duration> {
+    println!("cargo:rerun-if-env-changed=GMP_MPFR_SYS_CACHE_MAX_AGE");
+    let val = env::var("GMP_MPFR_SYS_CACHE_MAX_AGE").ok()?;
+    let trimmed = val.trim();
+    let (num, mult) = match trimmed.chars().last() {
+        Some('s') => (&trimmed[..trimmed.len() - 1], 1),
+        Some('m') => (&trimmed[..trimmed.len() - 1], 60),
+        Some('h') => (&trimmed[..trimmed.len() - 1], 60 * 60),
+        Some('d') => (&trimmed[..trimmed.len() - 1], 24 * 60 * 60),
+        _ => (trimmed, 24 * 60 * 60),
+    };
+    match num.trim().parse::<u64>() {
+        Ok(n) => Some(Duration::from_secs(n.saturating_mul(mult))),
+        Err(_) => panic!("Cannot parse GMP_MPFR_SYS_CACHE_MAX_AGE: {:?}", val),
+    }
+}
+
+struct CacheEntry {
+    dir: PathBuf,
+    size: u64,
+    last_use: SystemTime,
+}
+
+// Finds the cache entries under dir, that is the directories with a
+// manifest, for all versions and targets. Entries saved by versions
+// of the crate that did not write manifests are recognized by their
+// gmp.h header. A c-no-tests subdirectory is part of the entry of its
+// version directory.
+fn find_cache_entries(dir: &Path, entries: &mut Vec<CacheEntry>) {
+    if dir.join(MANIFEST).is_file() || dir.join("gmp.h").is_file() {
+        entries.push(CacheEntry {
+            dir: dir.to_path_buf(),
+            size: dir_size(dir),
+            last_use: cache_last_use(dir),
+        });
+        return;
+    }
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+    for entry in read_dir.filter_map(Result::ok) {
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if is_dir && !entry.file_name().to_string_lossy().starts_with('.') {
+            find_cache_entries(&entry.path(), entries);
+        }
+    }
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return 0,
+    };
+    let mut size = 0;
+    for entry in read_dir.filter_map(Result::ok) {
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => size += dir_size(&entry.path()),
+            Ok(_) => size += entry.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => {}
+        }
+    }
+    size
+}
+
+// Evicts cache entries that were last used longer ago than the maximum
+// age, and then the least recently used entries until the cache is not
+// larger than the maximum size. The entry in use is kept.
+fn evict_cache(env: &Environment, keep: &Path) {
+    if env.cache_max_size.is_none() && env.cache_max_age.is_none() {
+        return;
+    }
+    let root = match env.cache_root {
+        Some(ref s) => s,
+        None => return,
+    };
+    let _lock = match CacheLock::acquire(root) {
+        Some(lock) => lock,
+        None => return,
+    };
+    let mut entries = Vec::new();
+    find_cache_entries(root, &mut entries);
+    entries.sort_by_key(|e| e.last_use);
+    let mut total = entries.iter().map(|e| e.size).sum::<u64>();
+    let now = SystemTime::now();
+    for entry in entries {
+        if keep.starts_with(&entry.dir) {
+            continue;
+        }
+        let too_old = match env.cache_max_age {
+            Some(max_age) => now.duration_since(entry.last_use).unwrap_or_default() > max_age,
+            None => false,
+        };
+        let too_large = match env.cache_max_size {
+            Some(max_size) => total > max_size,
+            None => false,
+        };
+        if !too_old && !too_large {
+            continue;
+        }
+        // The entry is changed and loaded under the lock of the cache
+        // directory that owns it, not the root lock, so take that lock
+        // too, and skip the entry if it was used meanwhile.
+        let removed = match CacheLock::acquire(cache_entry_owner(&entry.dir)) {
+            Some(_entry_lock) => {
+                cache_last_use(&entry.dir) == entry.last_use && remove_dir(&entry.dir).is_ok()
+            }
+            None => false,
+        };
+        if removed {
+            total -= entry.size;
+            remove_empty_parents(&entry.dir, root);
+        }
+    }
+}
+
+// The cache directory that contains a cache entry, which holds the lock
+// for the entry. A c-no-tests entry is inside its version directory.
+fn cache_entry_owner(dir: &Path) -> &Path {
+    let version_dir = if dir.ends_with("c-no-tests") {
+        dir.parent().unwrap_or(dir)
+    } else {
+        dir
+    };
+    version_dir.parent().unwrap_or(version_dir)
+}
+
+// Removes directories between dir and root that have become empty.
+fn remove_empty_parents(dir: &Path, root: &Path) {
+    let mut dir = dir.parent();
+    while let Some(d) = dir {
+        if d == root || !d.starts_with(root) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+// The inputs that were hashed for the cache directory name, for
+// debugging.
+const CACHE_KEY: &str = "key.txt";
+
+// Everything that affects the built C libraries, one item per line.
+// The version directories inside the cache directory keep different
+// versions of gmp-mpfr-sys apart, so the version is not included.
+fn cache_key(env: &Environment) -> String {
+    let mut key = String::new();
+    key.push_str(&format!("target: {}\n", env.target_triple));
+    key.push_str(&format!("host: {}\n", env.host_triple));
+    key.push_str(&format!("compiler: {}\n", env.c_compiler.to_string_lossy()));
+    key.push_str(&format!("compiler version: {}\n", c_compiler_version(env)));
+    for &(name, ref value) in &env.c_tools {
+        key.push_str(&format!("{}: {}\n", name, value.to_string_lossy()));
+    }
+    let mut features = Vec::new();
+    if env.shared_libs {
+        features.push("c-shared");
+    }
+    if env.c_assert {
+        features.push("c-assert");
+    }
+    for sanitizer in &env.sanitizers {
+        features.push(match *sanitizer {
+            "address" => "c-sanitize-address",
+            "memory" => "c-sanitize-memory",
+            _ => "c-sanitize-undefined",
+        });
+    }
+    key.push_str(&format!("features: {}\n", features.join(" ")));
+    let cpu = match env.gmp_cpu {
+        GmpCpu::Fat => "fat",
+        GmpCpu::Named(ref cpu) => cpu,
+        GmpCpu::Native => "native",
+    };
+    key.push_str(&format!("gmp cpu: {}\n", cpu));
+    key.push_str(&format!("sources: {} {} {}\n", GMP_DIR, MPFR_DIR, MPC_DIR));
+    key.push_str(&format!("gmp configure: {}\n", gmp_configure_line(env)));
+    key.push_str(&format!(
+        "mpfr configure: {}\n",
+        mpfr_configure_line(env, true)
+    ));
+    key.push_str(&format!("mpc configure: {}\n", mpc_configure_line(env)));
+    key
+}
+
+// The first line of the output of the C compiler with --version, which
+// identifies both the compiler and its version.
+fn c_compiler_version(env: &Environment) -> String {
+    let output = Command::new(&env.c_compiler)
+        .arg("--version")
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(ref output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_string(),
+        _ => String::from("unknown"),
+    }
+}
+
+// The manifest lists the files of a cache directory with their sizes
+// and hashes, so that truncated or corrupted files are not loaded.
+const MANIFEST: &str = "manifest.txt";
+
+struct ManifestEntry {
+    name: String,
+    size: u64,
+    hash: u64,
+}
+
+fn write_manifest(dir: &Path) -> IoResult<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            if name != MANIFEST && name != LAST_USE && !name.starts_with('.') {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    let mut content = String::new();
+    for name in names {
+        let (size, hash) = file_size_hash(&dir.join(&name))?;
+        content.push_str(&format!("{:016x} {} {}\n", hash, size, name));
+    }
+    // write and rename, as the manifest of a cache entry in use can be
+    // replaced when libraries are added to the entry
+    let manifest = dir.join(MANIFEST);
+    let tmp = dir.join(format!(".{}.tmp", MANIFEST));
+    println!("$ #Writing {:?}", manifest);
+    fs::write(&tmp, content)?;
+    rename(&tmp, &manifest)
+}
+
+fn read_manifest(dir: &Path) -> Option<Vec<ManifestEntry>> {
+    let content = fs::read_to_string(dir.join(MANIFEST)).ok()?;
+    let mut entries = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.splitn(3, ' ');
+        let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let size = fields.next()?.parse().ok()?;
+        let name = fields.next()?.to_string();
+        entries.push(ManifestEntry { name, size, hash });
+    }
+    Some(entries)
+}
+
+// Checks that the manifest lists name and that file matches it.
+fn manifest_matches(manifest: &[ManifestEntry], name: &str, file: &Path) -> bool {
+    let entry = match manifest.iter().find(|e| e.name == name) {
+        Some(e) => e,
+        None => return false,
+    };
+    match file_size_hash(file) {
+        Ok((size, hash)) => size == entry.size && hash == entry.hash,
+        Err(_) => false,
+    }
+}
+
+// Reads the manifest of dir and checks all the files it lists.
+fn read_valid_manifest(dir: &Path) -> Option<Vec<ManifestEntry>> {
+    let manifest = read_manifest(dir)?;
+    if manifest
+        .iter()
+        .all(|e| manifest_matches(&manifest, &e.name, &dir.join(&e.name)))
+    {
+        Some(manifest)
+    } else {
+        None
+    }
+}
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Returns the size and the 64-bit FNV-1a hash of the file contents.
+fn file_size_hash(file: &Path) -> IoResult<(u64, u64)> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let mut size = 0u64;
+    let mut hash = FNV_OFFSET_BASIS;
+    loop {
+        let len = {
+            let buf = reader.fill_buf()?;
+            hash = fnv1a(hash, buf);
+            buf.len()
+        };
+        if len == 0 {
+            return Ok((size, hash));
+        }
+        size += len as u64;
+        reader.consume(len);
     }
-    ok
 }
 
 fn clear_cache_redundancies(env: &Environment, mpfr: bool, mpc: bool) {
@@ -443,6 +1346,16 @@ fn clear_cache_redundancies(env: &Environment, mpfr: bool, mpc: bool) {
         Some(ref s) => s,
         None => return,
     };
+    let _lock = match CacheLock::acquire(cache_dir) {
+        Some(lock) => lock,
+        None => return,
+    };
+    clear_cache_temp_dirs(cache_dir);
+    let (libgmp, libmpfr, libmpc) = (
+        lib_file(env, "gmp"),
+        lib_file(env, "mpfr"),
+        lib_file(env, "mpc"),
+    );
     let cache_dirs = cache_directories(env, cache_dir)
         .into_iter()
         .rev()
@@ -457,8 +1370,8 @@ fn clear_cache_redundancies(env: &Environment, mpfr: bool, mpc: bool) {
         if version_patch == env.version_patch {
             // but if we tested and c-no-tests directory doesn't have more libs, remove it
             if !env.c_no_tests
-                && (mpc || !no_tests_dir.join("libmpc.a").is_file())
-                && (mpfr || !no_tests_dir.join("libmpfr.a").is_file())
+                && (mpc || !no_tests_dir.join(&libmpc).is_file())
+                && (mpfr || !no_tests_dir.join(&libmpfr).is_file())
             {
                 let _ = remove_dir(&no_tests_dir);
             }
@@ -469,8 +1382,8 @@ fn clear_cache_redundancies(env: &Environment, mpfr: bool, mpc: bool) {
         // Do not clear cache with more libraries than newly saved cache.
 
         // First check c-no-tests subdirectory for more libs.
-        if (!mpc && no_tests_dir.join("libmpc.a").is_file())
-            || (!mpfr && no_tests_dir.join("libmpfr.a").is_file())
+        if (!mpc && no_tests_dir.join(&libmpc).is_file())
+            || (!mpfr && no_tests_dir.join(&libmpfr).is_file())
         {
             continue;
         }
@@ -479,11 +1392,11 @@ fn clear_cache_redundancies(env: &Environment, mpfr: bool, mpc: bool) {
 
         let delete_version_dir_condition = if env.c_no_tests {
             // We did not test, so version_dir must not contain any libs at all.
-            !version_dir.join("libgmp.a").is_file()
+            !version_dir.join(&libgmp).is_file()
         } else {
             // We did test, so delete if it does not contain more libs.
-            (mpc || !version_dir.join("libmpc.a").is_file())
-                && (mpfr || !version_dir.join("libmpfr.a").is_file())
+            (mpc || !version_dir.join(&libmpc).is_file())
+                && (mpfr || !version_dir.join(&libmpfr).is_file())
         };
         if delete_version_dir_condition {
             let _ = remove_dir(&version_dir);
@@ -558,7 +1471,12 @@ fn load_cache(
         // we need tests, so do not try to load from c-no-tests
         &[None]
     };
-    for suffix in suffixes {
+    // The lock keeps the entry from being evicted while it is loaded.
+    // If it cannot be acquired, for example in a read-only cache, the
+    // entry is still loaded, as the copies are checked anyway.
+    let lock = CacheLock::acquire(cache_dir);
+    let mut loaded = None;
+    'search: for suffix in suffixes {
         for (version_dir, _) in &cache_dirs {
             let joined;
             let dir = if let Some(suffix) = suffix {
@@ -567,31 +1485,148 @@ fn load_cache(
             } else {
                 version_dir
             };
+            // reject entries that are incomplete or corrupted
+            let manifest = match read_valid_manifest(dir) {
+                Some(manifest) => manifest,
+                None => continue,
+            };
             let mut ok = true;
             if let Some((ref a, ref h)) = *mpc_ah {
-                ok = ok && copy_file(&dir.join("libmpc.a"), a).is_ok();
+                let lib = lib_file(env, "mpc");
+                ok = ok && copy_lib(env, "mpc", &dir.join(&lib), a).is_ok();
                 let header = dir.join("mpc.h");
-                ok = ok && process_mpc_header(env, &header, None).is_ok();
+                ok = ok
+                    && probe_header(env, "mpc", &header)
+                        .and_then(|values| process_mpc_header(env, &values, None))
+                        .is_ok();
                 ok = ok && copy_file(&header, h).is_ok();
+                ok = ok && manifest_matches(&manifest, &lib, a);
+                ok = ok && manifest_matches(&manifest, "mpc.h", h);
             }
             if let Some((ref a, ref h)) = *mpfr_ah {
-                ok = ok && copy_file(&dir.join("libmpfr.a"), a).is_ok();
+                let lib = lib_file(env, "mpfr");
+                ok = ok && copy_lib(env, "mpfr", &dir.join(&lib), a).is_ok();
                 let header = dir.join("mpfr.h");
-                ok = ok && process_mpfr_header(env, &header, None).is_ok();
+                ok = ok
+                    && probe_header(env, "mpfr", &header)
+                        .and_then(|values| process_mpfr_header(env, &values, None))
+                        .is_ok();
                 ok = ok && copy_file(&header, h).is_ok();
+                ok = ok && manifest_matches(&manifest, &lib, a);
+                ok = ok && manifest_matches(&manifest, "mpfr.h", h);
             }
             let (ref a, ref h) = *gmp_ah;
-            ok = ok && copy_file(&dir.join("libgmp.a"), a).is_ok();
+            let lib = lib_file(env, "gmp");
+            ok = ok && copy_lib(env, "gmp", &dir.join(&lib), a).is_ok();
             let header = dir.join("gmp.h");
-            ok = ok && process_gmp_header(env, &header, None).is_ok();
+            ok = ok
+                && probe_header(env, "gmp", &header)
+                    .and_then(|values| process_gmp_header(env, &values, None))
+                    .is_ok();
             ok = ok && copy_file(&header, h).is_ok();
+            // check the copies too, in case the entry was replaced meanwhile
+            ok = ok && manifest_matches(&manifest, &lib, a);
+            ok = ok && manifest_matches(&manifest, "gmp.h", h);
 
             if ok {
-                return true;
+                record_cache_use(dir);
+                loaded = Some(dir.clone());
+                break 'search;
             }
         }
     }
-    false
+    drop(lock);
+    match loaded {
+        Some(dir) => {
+            evict_cache(env, &dir);
+            true
+        }
+        None => false,
+    }
+}
+
+// Loads GMP, and MPFR if possible, from a cache entry that does not
+// have all the required libraries. Returns the entry and whether MPFR
+// was loaded.
+fn load_cache_partial(
+    env: &Environment,
+    gmp_ah: &(PathBuf, PathBuf),
+    mpfr_ah: &Option<(PathBuf, PathBuf)>,
+) -> Option<(PathBuf, bool)> {
+    let cache_dir = env.cache_dir.as_ref()?;
+    let cache_dirs = cache_directories(env, cache_dir)
+        .into_iter()
+        .rev()
+        .filter(|x| match env.version_patch {
+            None => x.1.is_none(),
+            Some(patch) => x.1.map(|p| p >= patch).unwrap_or(false),
+        })
+        .collect::<Vec<_>>();
+    let suffixes: &[Option<&str>] = if env.c_no_tests {
+        &[None, Some("c-no-tests")]
+    } else {
+        // we need tests, so do not try to load from c-no-tests
+        &[None]
+    };
+    // prefer entries that have MPFR too
+    let with_mpfr: &[bool] = if mpfr_ah.is_some() {
+        &[true, false]
+    } else {
+        &[false]
+    };
+    // the lock keeps the entry from being evicted while it is loaded
+    let _lock = CacheLock::acquire(cache_dir);
+    for &want_mpfr in with_mpfr {
+        for suffix in suffixes {
+            for (version_dir, _) in &cache_dirs {
+                let dir = match suffix {
+                    Some(suffix) => version_dir.join(suffix),
+                    None => version_dir.clone(),
+                };
+                let manifest = match read_valid_manifest(&dir) {
+                    Some(manifest) => manifest,
+                    None => continue,
+                };
+                let listed = |name: &str| manifest.iter().any(|e| e.name == name);
+                if !listed(&lib_file(env, "gmp")) || !listed("gmp.h") {
+                    continue;
+                }
+                if want_mpfr && (!listed(&lib_file(env, "mpfr")) || !listed("mpfr.h")) {
+                    continue;
+                }
+                let mut ok = true;
+                if want_mpfr {
+                    let (ref a, ref h) = *mpfr_ah.as_ref().unwrap();
+                    let lib = lib_file(env, "mpfr");
+                    ok = ok && copy_lib(env, "mpfr", &dir.join(&lib), a).is_ok();
+                    let header = dir.join("mpfr.h");
+                    ok = ok
+                        && probe_header(env, "mpfr", &header)
+                            .and_then(|values| process_mpfr_header(env, &values, None))
+                            .is_ok();
+                    ok = ok && copy_file(&header, h).is_ok();
+                    ok = ok && manifest_matches(&manifest, &lib, a);
+                    ok = ok && manifest_matches(&manifest, "mpfr.h", h);
+                }
+                let (ref a, ref h) = *gmp_ah;
+                let lib = lib_file(env, "gmp");
+                ok = ok && copy_lib(env, "gmp", &dir.join(&lib), a).is_ok();
+                let header = dir.join("gmp.h");
+                ok = ok
+                    && probe_header(env, "gmp", &header)
+                        .and_then(|values| process_gmp_header(env, &values, None))
+                        .is_ok();
+                ok = ok && copy_file(&header, h).is_ok();
+                ok = ok && manifest_matches(&manifest, &lib, a);
+                ok = ok && manifest_matches(&manifest, "gmp.h", h);
+                if ok {
+                    record_cache_use(&dir);
+                    return Some((dir, want_mpfr));
+                }
+            }
+        }
+    }
+    None
 }
 
 fn should_save_cache(env: &Environment, mpfr: bool, mpc: bool) -> bool {
@@ -622,17 +1657,23 @@ fn should_save_cache(env: &Environment, mpfr: bool, mpc: bool) -> bool {
             } else {
                 version_dir
             };
+            // an incomplete or corrupted entry should be replaced
+            let manifest = match read_valid_manifest(dir) {
+                Some(manifest) => manifest,
+                None => continue,
+            };
+            let listed = |name: &str| manifest.iter().any(|e| e.name == name);
             let mut ok = true;
             if mpc {
-                ok = ok && dir.join("libmpc.a").is_file();
-                ok = ok && dir.join("mpc.h").is_file();
+                ok = ok && listed(&lib_file(env, "mpc"));
+                ok = ok && listed("mpc.h");
             }
             if mpfr {
-                ok = ok && dir.join("libmpfr.a").is_file();
-                ok = ok && dir.join("mpfr.h").is_file();
+                ok = ok && listed(&lib_file(env, "mpfr"));
+                ok = ok && listed("mpfr.h");
             }
-            ok = ok && dir.join("libgmp.a").is_file();
-            ok = ok && dir.join("gmp.h").is_file();
+            ok = ok && listed(&lib_file(env, "gmp"));
+            ok = ok && listed("gmp.h");
             if ok {
                 return false;
             }
@@ -641,101 +1682,245 @@ fn should_save_cache(env: &Environment, mpfr: bool, mpc: bool) -> bool {
     true
 }
 
+fn gmp_configure_line(env: &Environment) -> String {
+    let mut conf = String::from("../gmp-src/configure");
+    if is_wasm(&env.target_triple) || !env.sanitizers.is_empty() {
+        // sanitizers cannot instrument assembly
+        conf.push_str(" --disable-assembly");
+    } else if env.gmp_cpu == GmpCpu::Fat {
+        conf.push_str(" --enable-fat");
+    }
+    conf.push_str(shared_static_args(env));
+    if env.c_assert {
+        conf.push_str(" --enable-assert");
+    }
+    conf.push_str(" --with-pic");
+    conf.push_str(&gmp_cross_args(env));
+    conf.push_str(&sanitize_args(env));
+    conf.push_str(&env.gmp_extra_args);
+    conf
+}
+
 fn build_gmp(env: &Environment, lib: &Path, header: &Path) {
     let build_dir = env.build_dir.join("gmp-build");
     create_dir_or_panic(&build_dir);
     println!("$ cd {:?}", build_dir);
-    let mut conf = String::from("../gmp-src/configure --enable-fat --disable-shared --with-pic");
-    if let Some(cross_target) = env.cross_target.as_ref() {
-        conf.push_str(" --build ");
-        conf.push_str(cross_target);
-    }
-    configure(&build_dir, &OsString::from(conf));
-    make_and_check(env, &build_dir);
-    let build_lib = build_dir.join(".libs").join("libgmp.a");
-    copy_file_or_panic(&build_lib, lib);
+    configure(
+        env,
+        &build_dir,
+        &OsString::from(gmp_configure_line(env)),
+        "gmp",
+    );
+    make_and_check(env, &build_dir, "gmp");
+    let build_lib = build_dir.join(".libs").join(lib_file(env, "gmp"));
+    copy_lib_or_panic(env, "gmp", &build_lib, lib);
     let build_header = build_dir.join("gmp.h");
     copy_file_or_panic(&build_header, header);
 }
 
-fn compatible_version(
-    env: &Environment,
-    major: i32,
-    minor: i32,
-    patchlevel: i32,
-    expected: (i32, i32, i32),
-) -> bool {
-    (major == expected.0 && minor >= expected.1)
-        && (minor > expected.1 || patchlevel >= expected.2 || env.use_system_libs)
+fn compatible_version(
+    env: &Environment,
+    major: i64,
+    minor: i64,
+    patchlevel: i64,
+    expected: (i32, i32, i32),
+) -> bool {
+    let expected = (
+        i64::from(expected.0),
+        i64::from(expected.1),
+        i64::from(expected.2),
+    );
+    (major == expected.0 && minor >= expected.1)
+        && (minor > expected.1 || patchlevel >= expected.2 || env.use_system_libs)
+}
+
+// Values printed by a header probe, such as GMP_PROBE_C.
+struct ProbeValues {
+    ints: Vec<(String, i64)>,
+    strs: Vec<(String, String)>,
+}
+
+impl ProbeValues {
+    // Parses lines such as "int GMP_LIMB_BITS 64". Lines that do not
+    // start with "int " or "str " are ignored, so this can read both
+    // the output of the probe and the assembly from PROBE_ASM, where
+    // the lines start with "@@@probe ". String values are kept
+    // verbatim, including any trailing whitespace.
+    fn parse(output: &str) -> ProbeValues {
+        let mut values = ProbeValues {
+            ints: Vec::new(),
+            strs: Vec::new(),
+        };
+        for line in output.lines() {
+            let line = match line.find("@@@probe ") {
+                Some(start) => &line[start + 9..],
+                None => line,
+            };
+            let mut parts = line.splitn(3, ' ');
+            let (kind, name, value) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kind), Some(name), Some(value)) => (kind, name.to_string(), value),
+                (Some("str"), Some(name), None) => ("str", name.to_string(), ""),
+                _ => continue,
+            };
+            match kind {
+                "int" => {
+                    // assemblers can prefix immediates with $ or #
+                    let value = value.trim().trim_start_matches(&['$', '#'][..]);
+                    if let Ok(value) = value.parse() {
+                        values.ints.push((name, value));
+                    }
+                }
+                "str" => values.strs.push((name, value.to_string())),
+                _ => {}
+            }
+        }
+        values
+    }
+
+    fn int(&self, name: &str) -> Result<i64, String> {
+        self.ints
+            .iter()
+            .find(|v| v.0 == name)
+            .map(|v| v.1)
+            .ok_or_else(|| format!("Cannot determine {}", name))
+    }
+
+    fn string(&self, name: &str) -> Result<&str, String> {
+        self.strs
+            .iter()
+            .find(|v| v.0 == name)
+            .map(|v| &*v.1)
+            .ok_or_else(|| format!("Cannot determine {}", name))
+    }
+
+    // Returns the string escaped for use inside a byte string literal.
+    fn escaped_string(&self, name: &str) -> Result<String, String> {
+        let value = self.string(name)?;
+        let escaped = value.bytes().flat_map(ascii::escape_default);
+        Ok(escaped.map(char::from).collect())
+    }
+}
+
+// Runs the probe for the header, which must be next to the headers it
+// includes.
+fn probe_header(env: &Environment, lib: &str, header: &Path) -> Result<ProbeValues, String> {
+    let include_dir = header.parent().expect("header has no parent directory");
+    let probe_dir = env.out_dir.join("probe");
+    create_dir_or_panic(&probe_dir);
+    let cflags = [format!("-I{}", include_dir.display())];
+    run_probe(env, &probe_dir, lib, &cflags, &[])
+}
+
+// Compiles the probe for lib in dir and returns the values it prints.
+// If the target cannot run on the build machine, the probe is only
+// compiled to assembly, where PROBE_ASM places the values.
+fn run_probe(
+    env: &Environment,
+    dir: &Path,
+    lib: &str,
+    cflags: &[String],
+    libs: &[ExternalLib],
+) -> Result<ProbeValues, String> {
+    let name = format!("{}_probe", lib);
+    let source = match lib {
+        "gmp" => GMP_PROBE_C,
+        "mpfr" => MPFR_PROBE_C,
+        "mpc" => MPC_PROBE_C,
+        _ => unreachable!(),
+    };
+    create_file_or_panic(&dir.join("probe.h"), PROBE_H);
+    create_file_or_panic(&dir.join(format!("{}.c", name)), source);
+
+    let mut cmd = Command::new(&env.c_compiler);
+    cmd.current_dir(dir).args(probe_cflags(env)).args(cflags);
+    for lib in libs {
+        cmd.args(&lib.cflags);
+    }
+    let run = probe_can_run(env);
+    if run {
+        cmd.arg("-fPIC").arg(format!("{}.c", name));
+        for lib in libs {
+            for dir in &lib.link_dirs {
+                cmd.arg(format!("-L{}", dir.display()));
+            }
+        }
+        for lib in libs {
+            for link_lib in &lib.link_libs {
+                cmd.arg(format!("-l{}", link_lib));
+            }
+        }
+        cmd.arg("-o").arg(format!("{}.exe", name));
+    } else {
+        cmd.arg("-DPROBE_ASM")
+            .arg("-S")
+            .arg(format!("{}.c", name))
+            .arg("-o")
+            .arg(format!("{}.s", name));
+    }
+    probe_output(cmd)?;
+
+    if !run {
+        let asm = dir.join(format!("{}.s", name));
+        let asm = fs::read_to_string(&asm).map_err(|_| format!("Cannot read file: {:?}", asm))?;
+        return Ok(ProbeValues::parse(&asm));
+    }
+    let mut cmd = Command::new(dir.join(format!("{}.exe", name)));
+    cmd.current_dir(dir);
+    let link_dirs = libs.iter().flat_map(|lib| lib.link_dirs.iter());
+    if let Some(var) = prepend_dyld_path(link_dirs) {
+        cmd.env(DYLD_PATH_VAR, var);
+    }
+    let output = probe_output(cmd)?;
+    Ok(ProbeValues::parse(&output))
+}
+
+fn probe_output(mut command: Command) -> Result<String, String> {
+    println!("$ {:?}", command);
+    let output = command
+        .output()
+        .map_err(|_| format!("Unable to execute: {:?}", command))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Program failed: {:?}\n{}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|_| format!("Output is not UTF-8: {:?}", command))
+}
+
+// Probes can run when building for the host, and when building for
+// i686 on x86_64 with -m32.
+fn probe_can_run(env: &Environment) -> bool {
+    match env.cross_target {
+        None => true,
+        Some(ref target) => compilation_target_allowed(&env.host_triple, target),
+    }
+}
+
+fn probe_cflags(env: &Environment) -> Vec<OsString> {
+    let mut cflags = Vec::new();
+    if env.cross_target.is_some() && probe_can_run(env) {
+        cflags.push("-m32".into());
+    }
+    for &(name, ref value) in &env.c_tools {
+        if name == "CFLAGS" {
+            if let Some(value) = value.to_str() {
+                cflags.extend(value.split_whitespace().map(OsString::from));
+            }
+        }
+    }
+    cflags
 }
 
 fn process_gmp_header(
     env: &Environment,
-    header: &Path,
+    values: &ProbeValues,
     out_file: Option<&Path>,
 ) -> Result<(), String> {
-    let mut major = None;
-    let mut minor = None;
-    let mut patchlevel = None;
-    let mut limb_bits = None;
-    let mut nail_bits = None;
-    let mut long_long_limb = None;
-    let mut cc = None;
-    let mut cflags = None;
-    let mut reader = open(header);
-    let mut buf = String::new();
-    while read_line(&mut reader, &mut buf, header) > 0 {
-        let s = "#define __GNU_MP_VERSION ";
-        if let Some(start) = buf.find(s) {
-            major = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define __GNU_MP_VERSION_MINOR ";
-        if let Some(start) = buf.find(s) {
-            minor = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define __GNU_MP_VERSION_PATCHLEVEL ";
-        if let Some(start) = buf.find(s) {
-            patchlevel = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        if buf.contains("#undef _LONG_LONG_LIMB") {
-            long_long_limb = Some(false);
-        }
-        if buf.contains("#define _LONG_LONG_LIMB 1") {
-            long_long_limb = Some(true);
-        }
-        let s = "#define GMP_LIMB_BITS ";
-        if let Some(start) = buf.find(s) {
-            limb_bits = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define GMP_NAIL_BITS ";
-        if let Some(start) = buf.find(s) {
-            nail_bits = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define __GMP_CC ";
-        if let Some(start) = buf.find(s) {
-            cc = Some(
-                buf[(start + s.len())..]
-                    .trim()
-                    .trim_matches('"')
-                    .to_string(),
-            );
-        }
-        let s = "#define __GMP_CFLAGS ";
-        if let Some(start) = buf.find(s) {
-            cflags = Some(
-                buf[(start + s.len())..]
-                    .trim()
-                    .trim_matches('"')
-                    .to_string(),
-            );
-        }
-        buf.clear();
-    }
-    drop(reader);
-
-    let major = major.expect("Cannot determine __GNU_MP_VERSION");
-    let minor = minor.expect("Cannot determine __GNU_MP_VERSION_MINOR");
-    let patchlevel = patchlevel.expect("Cannot determine __GNU_MP_VERSION_PATCHLEVEL");
+    let major = values.int("__GNU_MP_VERSION")?;
+    let minor = values.int("__GNU_MP_VERSION_MINOR")?;
+    let patchlevel = values.int("__GNU_MP_VERSION_PATCHLEVEL")?;
     if !compatible_version(env, major, minor, patchlevel, GMP_VER) {
         return Err(format!(
             "This version of gmp-mpfr-sys supports GMP {}.{}.{}, but {}.{}.{} was found",
@@ -743,23 +1928,34 @@ fn process_gmp_header(
         ));
     }
 
-    let limb_bits = limb_bits.expect("Cannot determine GMP_LIMB_BITS");
+    let limb_bits = values.int("GMP_LIMB_BITS")?;
     println!("cargo:limb_bits={}", limb_bits);
 
-    let nail_bits = nail_bits.expect("Cannot determine GMP_NAIL_BITS");
+    let nail_bits = values.int("GMP_NAIL_BITS")?;
     if nail_bits > 0 {
         println!("cargo:rustc-cfg=nails");
     }
 
-    let long_long_limb = long_long_limb.expect("Cannot determine _LONG_LONG_LIMB");
+    let long_long_limb = values.int("_LONG_LONG_LIMB")? != 0;
+    let limb_type_bits = if long_long_limb {
+        64
+    } else {
+        i64::from(target_c_ulong_bits())
+    };
+    if limb_bits != limb_type_bits || values.int("sizeof(mp_limb_t)")? * 8 != limb_bits {
+        return Err(format!(
+            "GMP_LIMB_BITS is {}, but the limb type has {} bits on this target",
+            limb_bits, limb_type_bits
+        ));
+    }
     let long_long_limb = if long_long_limb {
         println!("cargo:rustc-cfg=long_long_limb");
         "libc::c_ulonglong"
     } else {
         "c_ulong"
     };
-    let cc = cc.expect("Cannot determine __GMP_CC");
-    let cflags = cflags.expect("Cannot determine __GMP_CFLAGS");
+    let cc = values.escaped_string("__GMP_CC")?;
+    let cflags = values.escaped_string("__GMP_CFLAGS")?;
 
     let content = format!(
         concat!(
@@ -770,9 +1966,26 @@ fn process_gmp_header(
             "const GMP_NAIL_BITS: c_int = {};\n",
             "type GMP_LIMB_T = {};\n",
             "const GMP_CC: *const c_char = b\"{}\\0\".as_ptr() as _;\n",
-            "const GMP_CFLAGS: *const c_char = b\"{}\\0\".as_ptr() as _;\n"
+            "const GMP_CFLAGS: *const c_char = b\"{}\\0\".as_ptr() as _;\n",
+            "const GMP_SIZEOF_MPZ_T: usize = {};\n",
+            "const GMP_SIZEOF_MPQ_T: usize = {};\n",
+            "const GMP_OFFSETOF_MPQ_DEN: usize = {};\n",
+            "const GMP_SIZEOF_MPF_T: usize = {};\n",
+            "const GMP_SIZEOF_RANDSTATE_T: usize = {};\n"
         ),
-        major, minor, patchlevel, limb_bits, nail_bits, long_long_limb, cc, cflags
+        major,
+        minor,
+        patchlevel,
+        limb_bits,
+        nail_bits,
+        long_long_limb,
+        cc,
+        cflags,
+        values.int("sizeof(mpz_t)")?,
+        values.int("sizeof(mpq_t)")?,
+        values.int("offsetof(__mpq_struct,_mp_den)")?,
+        values.int("sizeof(mpf_t)")?,
+        values.int("sizeof(gmp_randstate_t)")?,
     );
     if let Some(out_file) = out_file {
         let mut rs = create(out_file);
@@ -783,44 +1996,12 @@ fn process_gmp_header(
 
 fn process_mpfr_header(
     env: &Environment,
-    header: &Path,
+    values: &ProbeValues,
     out_file: Option<&Path>,
 ) -> Result<(), String> {
-    let mut major = None;
-    let mut minor = None;
-    let mut patchlevel = None;
-    let mut version = None;
-    let mut reader = open(header);
-    let mut buf = String::new();
-    while read_line(&mut reader, &mut buf, header) > 0 {
-        let s = "#define MPFR_VERSION_MAJOR ";
-        if let Some(start) = buf.find(s) {
-            major = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define MPFR_VERSION_MINOR ";
-        if let Some(start) = buf.find(s) {
-            minor = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define MPFR_VERSION_PATCHLEVEL ";
-        if let Some(start) = buf.find(s) {
-            patchlevel = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define MPFR_VERSION_STRING ";
-        if let Some(start) = buf.find(s) {
-            version = Some(
-                buf[(start + s.len())..]
-                    .trim()
-                    .trim_matches('"')
-                    .to_string(),
-            );
-        }
-        buf.clear();
-    }
-    drop(reader);
-
-    let major = major.expect("Cannot determine MPFR_VERSION_MAJOR");
-    let minor = minor.expect("Cannot determine MPFR_VERSION_MINOR");
-    let patchlevel = patchlevel.expect("Cannot determine MPFR_VERSION_PATCHLEVEL");
+    let major = values.int("MPFR_VERSION_MAJOR")?;
+    let minor = values.int("MPFR_VERSION_MINOR")?;
+    let patchlevel = values.int("MPFR_VERSION_PATCHLEVEL")?;
     if !compatible_version(env, major, minor, patchlevel, MPFR_VER) {
         return Err(format!(
             "This version of gmp-mpfr-sys supports MPFR {}.{}.{}, but {}.{}.{} was found",
@@ -828,16 +2009,49 @@ fn process_mpfr_header(
         ));
     }
 
-    let version = version.expect("Cannot determine MPFR_VERSION_STRING");
+    let version = values.escaped_string("MPFR_VERSION_STRING")?;
 
     let content = format!(
         concat!(
             "const MPFR_VERSION_MAJOR: c_int = {};\n",
             "const MPFR_VERSION_MINOR: c_int = {};\n",
             "const MPFR_VERSION_PATCHLEVEL: c_int = {};\n",
-            "const MPFR_VERSION_STRING: *const c_char = b\"{}\\0\".as_ptr() as _;\n"
+            "const MPFR_VERSION_STRING: *const c_char = b\"{}\\0\".as_ptr() as _;\n",
+            "const MPFR_PREC_MIN: prec_t = {};\n",
+            "const MPFR_PREC_MAX: prec_t = {};\n",
+            "const MPFR_EMIN_DEFAULT: exp_t = {};\n",
+            "const MPFR_EMAX_DEFAULT: exp_t = {};\n",
+            "const MPFR_FLAGS_UNDERFLOW: flags_t = {};\n",
+            "const MPFR_FLAGS_OVERFLOW: flags_t = {};\n",
+            "const MPFR_FLAGS_NAN: flags_t = {};\n",
+            "const MPFR_FLAGS_INEXACT: flags_t = {};\n",
+            "const MPFR_FLAGS_ERANGE: flags_t = {};\n",
+            "const MPFR_FLAGS_DIVBY0: flags_t = {};\n",
+            "const MPFR_FLAGS_ALL: flags_t = {};\n",
+            "const MPFR_SIZEOF_PREC_T: usize = {};\n",
+            "const MPFR_SIZEOF_EXP_T: usize = {};\n",
+            "const MPFR_SIZEOF_FLAGS_T: usize = {};\n",
+            "const MPFR_SIZEOF_MPFR_T: usize = {};\n"
         ),
-        major, minor, patchlevel, version
+        major,
+        minor,
+        patchlevel,
+        version,
+        values.int("MPFR_PREC_MIN")?,
+        values.int("MPFR_PREC_MAX")?,
+        values.int("MPFR_EMIN_DEFAULT")?,
+        values.int("MPFR_EMAX_DEFAULT")?,
+        values.int("MPFR_FLAGS_UNDERFLOW")?,
+        values.int("MPFR_FLAGS_OVERFLOW")?,
+        values.int("MPFR_FLAGS_NAN")?,
+        values.int("MPFR_FLAGS_INEXACT")?,
+        values.int("MPFR_FLAGS_ERANGE")?,
+        values.int("MPFR_FLAGS_DIVBY0")?,
+        values.int("MPFR_FLAGS_ALL")?,
+        values.int("sizeof(mpfr_prec_t)")?,
+        values.int("sizeof(mpfr_exp_t)")?,
+        values.int("sizeof(mpfr_flags_t)")?,
+        values.int("sizeof(mpfr_t)")?,
     );
     if let Some(out_file) = out_file {
         let mut rs = create(out_file);
@@ -848,44 +2062,12 @@ fn process_mpfr_header(
 
 fn process_mpc_header(
     env: &Environment,
-    header: &Path,
+    values: &ProbeValues,
     out_file: Option<&Path>,
 ) -> Result<(), String> {
-    let mut major = None;
-    let mut minor = None;
-    let mut patchlevel = None;
-    let mut version = None;
-    let mut reader = open(header);
-    let mut buf = String::new();
-    while read_line(&mut reader, &mut buf, header) > 0 {
-        let s = "#define MPC_VERSION_MAJOR ";
-        if let Some(start) = buf.find(s) {
-            major = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define MPC_VERSION_MINOR ";
-        if let Some(start) = buf.find(s) {
-            minor = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define MPC_VERSION_PATCHLEVEL ";
-        if let Some(start) = buf.find(s) {
-            patchlevel = buf[(start + s.len())..].trim().parse::<i32>().ok();
-        }
-        let s = "#define MPC_VERSION_STRING ";
-        if let Some(start) = buf.find(s) {
-            version = Some(
-                buf[(start + s.len())..]
-                    .trim()
-                    .trim_matches('"')
-                    .to_string(),
-            );
-        }
-        buf.clear();
-    }
-    drop(reader);
-
-    let major = major.expect("Cannot determine MPC_VERSION_MAJOR");
-    let minor = minor.expect("Cannot determine MPC_VERSION_MINOR");
-    let patchlevel = patchlevel.expect("Cannot determine MPC_VERSION_PATCHLEVEL");
+    let major = values.int("MPC_VERSION_MAJOR")?;
+    let minor = values.int("MPC_VERSION_MINOR")?;
+    let patchlevel = values.int("MPC_VERSION_PATCHLEVEL")?;
     if !compatible_version(env, major, minor, patchlevel, MPC_VER) {
         return Err(format!(
             "This version of gmp-mpfr-sys supports MPC {}.{}.{}, but {}.{}.{} was found",
@@ -893,16 +2075,23 @@ fn process_mpc_header(
         ));
     }
 
-    let version = version.expect("Cannot determine MPC_VERSION_STRING");
+    let version = values.escaped_string("MPC_VERSION_STRING")?;
 
     let content = format!(
         concat!(
             "const MPC_VERSION_MAJOR: c_int = {};\n",
             "const MPC_VERSION_MINOR: c_int = {};\n",
             "const MPC_VERSION_PATCHLEVEL: c_int = {};\n",
-            "const MPC_VERSION_STRING: *const c_char = b\"{}\\0\".as_ptr() as _;\n"
+            "const MPC_VERSION_STRING: *const c_char = b\"{}\\0\".as_ptr() as _;\n",
+            "const MPC_SIZEOF_MPC_T: usize = {};\n",
+            "const MPC_OFFSETOF_IM: usize = {};\n"
         ),
-        major, minor, patchlevel, version
+        major,
+        minor,
+        patchlevel,
+        version,
+        values.int("sizeof(mpc_t)")?,
+        values.int("offsetof(__mpc_struct,im)")?,
     );
     if let Some(out_file) = out_file {
         let mut rs = create(out_file);
@@ -911,7 +2100,29 @@ fn process_mpc_header(
     Ok(())
 }
 
-fn build_mpfr(env: &Environment, lib: &Path, header: &Path) {
+// If gmp_built is false, the gmp-build directory only has the GMP
+// library and header, not the internals from the GMP build.
+fn mpfr_configure_line(env: &Environment, gmp_built: bool) -> String {
+    let mut conf = String::from("../mpfr-src/configure --enable-thread-safe");
+    conf.push_str(shared_static_args(env));
+    if env.c_assert {
+        // also check the assertions that are expensive
+        conf.push_str(" --enable-assert=full");
+    }
+    if env.shared_libs || !gmp_built {
+        // GMP internals are not part of the interface of the shared
+        // GMP library, so do not use them.
+        conf.push_str(" --with-gmp-include=../gmp-build --with-gmp-lib=../gmp-build/.libs");
+    } else {
+        conf.push_str(" --with-gmp-build=../gmp-build");
+    }
+    conf.push_str(" --with-pic");
+    conf.push_str(&mpfr_cross_args(env));
+    conf.push_str(&env.mpfr_extra_args);
+    conf
+}
+
+fn build_mpfr(env: &Environment, lib: &Path, header: &Path, gmp_built: bool) {
     let build_dir = env.build_dir.join("mpfr-build");
     create_dir_or_panic(&build_dir);
     println!("$ cd {:?}", build_dir);
@@ -920,31 +2131,73 @@ fn build_mpfr(env: &Environment, lib: &Path, header: &Path) {
         &build_dir.join("gmp-build"),
     );
 
-    // unset CC and CFLAGS since both MPFR and MPC will use CC and CFLAGS from gmp.h by default
+    unset_cc_cflags();
+    configure(
+        env,
+        &build_dir,
+        &OsString::from(mpfr_configure_line(env, gmp_built)),
+        "mpfr",
+    );
+    make_and_check(env, &build_dir, "mpfr");
+    let build_lib = build_dir
+        .join("src")
+        .join(".libs")
+        .join(lib_file(env, "mpfr"));
+    copy_lib_or_panic(env, "mpfr", &build_lib, lib);
+    let src_header = env.build_dir.join("mpfr-src").join("src").join("mpfr.h");
+    copy_file_or_panic(&src_header, header);
+}
+
+// unset CC and CFLAGS since both MPFR and MPC will use CC and CFLAGS from gmp.h by default
+fn unset_cc_cflags() {
     println!("$ unset CC CFLAGS");
     std::env::remove_var("CC");
     std::env::remove_var("CFLAGS");
+}
 
-    let mut conf = String::from(
-        "../mpfr-src/configure --enable-thread-safe --disable-shared \
-         --with-gmp-build=../gmp-build --with-pic",
+// Sets up a gmp-build directory with the GMP library and header that
+// were built before, so that MPFR and MPC can be built against them.
+fn prepare_built_gmp(env: &Environment, gmp_ah: &(PathBuf, PathBuf)) {
+    let build_dir = env.build_dir.join("gmp-build");
+    let libs_dir = build_dir.join(".libs");
+    create_dir_or_panic(&libs_dir);
+    let (ref a, ref h) = *gmp_ah;
+    copy_lib_or_panic(env, "gmp", a, &libs_dir.join(lib_file(env, "gmp")));
+    copy_file_or_panic(h, &build_dir.join("gmp.h"));
+}
+
+// Sets up mpfr-src and mpfr-build directories with the MPFR library
+// and header that were built before, so that MPC can be built against
+// them.
+fn prepare_built_mpfr(env: &Environment, mpfr_ah: &(PathBuf, PathBuf)) {
+    let src_dir = env.build_dir.join("mpfr-src").join("src");
+    let build_dir = env.build_dir.join("mpfr-build");
+    let libs_dir = build_dir.join("src").join(".libs");
+    create_dir_or_panic(&src_dir);
+    create_dir_or_panic(&libs_dir);
+    let (ref a, ref h) = *mpfr_ah;
+    copy_lib_or_panic(env, "mpfr", a, &libs_dir.join(lib_file(env, "mpfr")));
+    copy_file_or_panic(h, &src_dir.join("mpfr.h"));
+    // build_mpc moves this link like it does after build_mpfr
+    link_dir(
+        &env.build_dir.join("gmp-build"),
+        &build_dir.join("gmp-build"),
     );
-    if let Some(cross_target) = env.cross_target.as_ref() {
-        conf.push_str(" --build ");
+    unset_cc_cflags();
+}
 
-        // hax because the buildscript sucks balls
-        if cross_target == "wasm32-unknown-emscripten" {
-            conf.push_str("wasm32-unknown-none");
-        } else {
-            conf.push_str(cross_target);
-        }
-    }
-    configure(&build_dir, &OsString::from(conf));
-    make_and_check(env, &build_dir);
-    let build_lib = build_dir.join("src").join(".libs").join("libmpfr.a");
-    copy_file_or_panic(&build_lib, lib);
-    let src_header = env.build_dir.join("mpfr-src").join("src").join("mpfr.h");
-    copy_file_or_panic(&src_header, header);
+fn mpc_configure_line(env: &Environment) -> String {
+    let mut conf = String::from("../mpc-src/configure");
+    conf.push_str(shared_static_args(env));
+    conf.push_str(
+        " --with-mpfr-include=../mpfr-src/src \
+         --with-mpfr-lib=../mpfr-build/src/.libs \
+         --with-gmp-include=../gmp-build \
+         --with-gmp-lib=../gmp-build/.libs --with-pic",
+    );
+    conf.push_str(&cross_args(env));
+    conf.push_str(&env.mpc_extra_args);
+    conf
 }
 
 fn build_mpc(env: &Environment, lib: &Path, header: &Path) {
@@ -959,26 +2212,28 @@ fn build_mpc(env: &Environment, lib: &Path, header: &Path) {
         &env.build_dir.join("mpfr-build"),
         &build_dir.join("mpfr-build"),
     );
-    let mut conf = String::from(
-        "../mpc-src/configure --disable-shared \
-         --with-mpfr-include=../mpfr-src/src \
-         --with-mpfr-lib=../mpfr-build/src/.libs \
-         --with-gmp-include=../gmp-build \
-         --with-gmp-lib=../gmp-build/.libs --with-pic",
+    configure(
+        env,
+        &build_dir,
+        &OsString::from(mpc_configure_line(env)),
+        "mpc",
     );
-    if let Some(cross_target) = env.cross_target.as_ref() {
-        conf.push_str(" --build ");
-        conf.push_str(cross_target);
-    }
-    configure(&build_dir, &OsString::from(conf));
-    make_and_check(env, &build_dir);
-    let build_lib = build_dir.join("src").join(".libs").join("libmpc.a");
-    copy_file_or_panic(&build_lib, lib);
+    make_and_check(env, &build_dir, "mpc");
+    let build_lib = build_dir
+        .join("src")
+        .join(".libs")
+        .join(lib_file(env, "mpc"));
+    copy_lib_or_panic(env, "mpc", &build_lib, lib);
     let src_header = env.build_dir.join("mpc-src").join("src").join("mpc.h");
     copy_file_or_panic(&src_header, header);
 }
 
-fn write_link_info(env: &Environment, feature_mpfr: bool, feature_mpc: bool) {
+fn write_link_info(
+    env: &Environment,
+    feature_mpfr: bool,
+    feature_mpc: bool,
+    external_libs: &[ExternalLib],
+) {
     let out_str = env.out_dir.to_str().unwrap_or_else(|| {
         panic!(
             "Path contains unsupported characters, can only make {}",
@@ -1001,14 +2256,37 @@ fn write_link_info(env: &Environment, feature_mpfr: bool, feature_mpc: bool) {
     println!("cargo:lib_dir={}", lib_str);
     println!("cargo:include_dir={}", include_str);
     println!("cargo:rustc-link-search=native={}", lib_str);
-    let maybe_static = if env.use_system_libs { "" } else { "static=" };
-    if feature_mpc {
-        println!("cargo:rustc-link-lib={}mpc", maybe_static);
-    }
-    if feature_mpfr {
-        println!("cargo:rustc-link-lib={}mpfr", maybe_static);
+    if !external_libs.is_empty() {
+        let mut link_dirs = Vec::<&Path>::new();
+        let mut link_libs = Vec::<&str>::new();
+        for lib in external_libs {
+            for dir in &lib.link_dirs {
+                if !link_dirs.contains(&&**dir) {
+                    link_dirs.push(dir);
+                }
+            }
+            for link_lib in &lib.link_libs {
+                if !link_libs.contains(&&**link_lib) {
+                    link_libs.push(link_lib);
+                }
+            }
+        }
+        for dir in link_dirs {
+            println!("cargo:rustc-link-search=native={}", dir.display());
+        }
+        for link_lib in link_libs {
+            println!("cargo:rustc-link-lib={}", link_lib);
+        }
+    } else {
+        let kind = if env.shared_libs { "dylib" } else { "static" };
+        if feature_mpc {
+            println!("cargo:rustc-link-lib={}=mpc", kind);
+        }
+        if feature_mpfr {
+            println!("cargo:rustc-link-lib={}=mpfr", kind);
+        }
+        println!("cargo:rustc-link-lib={}=gmp", kind);
     }
-    println!("cargo:rustc-link-lib={}gmp", maybe_static);
     if env.target == Target::Mingw && env.workaround_47048 == Workaround47048::Yes {
         println!("cargo:rustc-link-lib=static=workaround_47048");
     }
@@ -1188,17 +2466,6 @@ fn check_for_bug_47048(env: &Environment) -> Workaround47048 {
     need_workaround
 }
 
-fn mingw_pkg_config_libdir_or_panic() {
-    let mut cmd = Command::new("pkg-config");
-    cmd.args(&["--libs-only-L", "gmp"]);
-    let output = execute_stdout(cmd);
-    if output.len() < 2 || &output[0..2] != b"-L" {
-        panic!("expected pkg-config output to begin with \"-L\"");
-    }
-    let libdir = str::from_utf8(&output[2..]).expect("output from pkg-config not utf-8");
-    println!("cargo:rustc-link-search=native={}", libdir);
-}
-
 fn remove_dir(dir: &Path) -> IoResult<()> {
     if !dir.exists() {
         return Ok(());
@@ -1234,30 +2501,135 @@ fn copy_file(src: &Path, dst: &Path) -> IoResult<u64> {
     fs::copy(src, dst)
 }
 
+fn rename(src: &Path, dst: &Path) -> IoResult<()> {
+    println!("$ mv {:?} {:?}", src, dst);
+    fs::rename(src, dst)
+}
+
 fn copy_file_or_panic(src: &Path, dst: &Path) {
     copy_file(src, dst).unwrap_or_else(|_| {
         panic!("Unable to copy {:?} -> {:?}", src, dst);
     });
 }
 
-fn configure(build_dir: &Path, conf_line: &OsStr) {
+// The name of the library file that is linked, for example libgmp.a.
+fn lib_file(env: &Environment, name: &str) -> String {
+    if !env.shared_libs {
+        format!("lib{}.a", name)
+    } else if env.target == Target::Mingw {
+        format!("lib{}.dll.a", name)
+    } else if env.target_triple.contains("-apple-") {
+        format!("lib{}.dylib", name)
+    } else {
+        format!("lib{}.so", name)
+    }
+}
+
+// Whether file is a versioned file of the shared library name, for
+// example libgmp.so.10, libgmp.10.dylib or libgmp-10.dll for gmp.
+fn is_versioned_lib_file(file: &str, name: &str) -> bool {
+    let (so, dylib, dll) = (
+        format!("lib{}.so.", name),
+        format!("lib{}.", name),
+        format!("lib{}-", name),
+    );
+    file.starts_with(&so)
+        || (file.starts_with(&dylib) && file.ends_with(".dylib") && file.len() > dylib.len() + 5)
+        || (file.starts_with(&dll) && file.ends_with(".dll"))
+}
+
+// Copies the library src to dst. For shared libraries, the versioned
+// files next to src are copied next to dst as well; symbolic links
+// are copied as regular files.
+fn copy_lib(env: &Environment, name: &str, src: &Path, dst: &Path) -> IoResult<()> {
+    copy_file(src, dst)?;
+    if !env.shared_libs {
+        return Ok(());
+    }
+    let (src_dir, dst_dir) = match (src.parent(), dst.parent()) {
+        (Some(s), Some(d)) => (s, d),
+        _ => return Ok(()),
+    };
+    for entry in fs::read_dir(src_dir)? {
+        let file_name = entry?.file_name();
+        if let Some(file) = file_name.to_str() {
+            if is_versioned_lib_file(file, name) {
+                copy_file(&src_dir.join(file), &dst_dir.join(file))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn copy_lib_or_panic(env: &Environment, name: &str, src: &Path, dst: &Path) {
+    copy_lib(env, name, src, dst).unwrap_or_else(|_| {
+        panic!("Unable to copy {:?} -> {:?}", src, dst);
+    });
+}
+
+fn shared_static_args(env: &Environment) -> &'static str {
+    if env.shared_libs {
+        " --enable-shared --disable-static"
+    } else {
+        " --disable-shared"
+    }
+}
+
+// Reads GMP_MPFR_SYS_<LIB>_CONFIGURE_ARGS, which is appended to the
+// configure line as is, and GMP_MPFR_SYS_<LIB>_CFLAGS, which is quoted
+// and passed as CFLAGS. Since the result is part of the configure
+// line, it is part of the cache key too.
+fn extra_configure_args(lib: &str) -> String {
+    let args_var = format!("GMP_MPFR_SYS_{}_CONFIGURE_ARGS", lib);
+    let cflags_var = format!("GMP_MPFR_SYS_{}_CFLAGS", lib);
+    println!("cargo:rerun-if-env-changed={}", args_var);
+    println!("cargo:rerun-if-env-changed={}", cflags_var);
+    let read = |var: &str| {
+        env::var_os(var).filter(|x| !x.is_empty()).map(|x| {
+            x.into_string()
+                .unwrap_or_else(|x| panic!("Cannot parse {}: {:?}", var, x))
+        })
+    };
+    let mut extra = String::new();
+    if let Some(args) = read(&args_var) {
+        extra.push(' ');
+        extra.push_str(args.trim());
+    }
+    if let Some(cflags) = read(&cflags_var) {
+        extra.push_str(" CFLAGS=");
+        extra.push_str(&shell_quote(cflags.trim()));
+    }
+    extra
+}
+
+// Quotes s for sh so that it is a single word.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn configure(env: &Environment, build_dir: &Path, conf_line: &OsStr, name: &str) {
     let mut conf = Command::new("sh");
     conf.current_dir(&build_dir).arg("-c").arg(conf_line);
-    execute(conf);
+    let log = env.log_dir.join(format!("{}-configure.log", name));
+    execute_logged(conf, &log, build_dir);
 }
 
-fn make_and_check(env: &Environment, build_dir: &Path) {
+fn make_and_check(env: &Environment, build_dir: &Path, name: &str) {
     let mut make = Command::new("make");
     make.current_dir(build_dir).arg("-j").arg(&env.jobs);
-    execute(make);
-    if !env.c_no_tests && env.cross_target.is_none() {
+    let log = env.log_dir.join(format!("{}-make.log", name));
+    execute_logged(make, &log, build_dir);
+    if env.cross_target.is_some() {
+        println!("$ #Skipping make check since the target is not the host");
+    } else if !env.c_no_tests {
         let mut make_check = Command::new("make");
         make_check
             .current_dir(build_dir)
             .arg("-j")
             .arg(&env.jobs)
             .arg("check");
-        execute(make_check);
+        let log = env.log_dir.join(format!("{}-check.log", name));
+        execute_logged(make_check, &log, build_dir);
     }
 }
 
@@ -1301,24 +2673,105 @@ fn execute(mut command: Command) {
     }
 }
 
-fn execute_stdout(mut command: Command) -> Vec<u8> {
-    println!("$ {:?}", command);
-    let output = command
-        .output()
+// Like execute, but writes the output of the command to log instead of
+// stdout. If the command fails, the panic message contains the end of
+// the log and the tests that failed inside build_dir.
+fn execute_logged(mut command: Command, log: &Path, build_dir: &Path) {
+    println!("$ {:?} > {:?} 2>&1", command, log);
+    let file = File::create(log).unwrap_or_else(|_| panic!("Cannot create file: {:?}", log));
+    let file_err = file
+        .try_clone()
+        .unwrap_or_else(|_| panic!("Cannot create file: {:?}", log));
+    command.stdout(file).stderr(file_err);
+    let status = command
+        .status()
         .unwrap_or_else(|_| panic!("Unable to execute: {:?}", command));
-    if !output.status.success() {
-        if let Some(code) = output.status.code() {
-            panic!("Program failed with code {}: {:?}", code, command);
-        } else {
-            panic!("Program failed: {:?}", command);
+    if status.success() {
+        return;
+    }
+    let mut msg = match status.code() {
+        Some(code) => format!("Program failed with code {}: {:?}", code, command),
+        None => format!("Program failed: {:?}", command),
+    };
+    let tail = log_tail(log, LOG_TAIL_LINES);
+    if !tail.is_empty() {
+        msg.push_str(&format!("\n\nLast lines of {:?}:\n", log));
+        for line in tail {
+            msg.push_str(&line);
+            msg.push('\n');
         }
     }
-    output.stdout
+    let mut failed = Vec::new();
+    find_failed_tests(build_dir, build_dir, &mut failed);
+    if !failed.is_empty() {
+        msg.push_str(&format!("\nFailed tests: {}\n", failed.join(" ")));
+    }
+    msg.push_str(&format!("\nFull log: {}", log.display()));
+    panic!("{}", msg);
+}
+
+// The number of lines of a failed log that are quoted when panicking.
+const LOG_TAIL_LINES: usize = 30;
+
+fn log_tail(log: &Path, lines: usize) -> Vec<String> {
+    let content = match fs::read(log) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    let content = String::from_utf8_lossy(&content);
+    let all = content.lines().collect::<Vec<_>>();
+    let start = all.len().saturating_sub(lines);
+    all[start..].iter().map(|line| line.to_string()).collect()
 }
 
-fn open(name: &Path) -> BufReader<File> {
-    let file = File::open(name).unwrap_or_else(|_| panic!("Cannot open file: {:?}", name));
-    BufReader::new(file)
+// Finds the tests that failed in the test-suite.log files written by
+// the automake test harness in dir and its subdirectories. A failed
+// test has a line such as "FAIL: t-add" underlined with equal signs.
+// Symbolic links are not followed, so that the GMP build directory
+// linked from the MPFR build directory is not searched.
+fn find_failed_tests(build_dir: &Path, dir: &Path, failed: &mut Vec<String>) {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+    let mut entries = read_dir.filter_map(Result::ok).collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_type = match entry.file_type() {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            find_failed_tests(build_dir, &entry.path(), failed);
+            continue;
+        }
+        if !file_type.is_file() || entry.file_name() != "test-suite.log" {
+            continue;
+        }
+        let content = match fs::read(entry.path()) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let content = String::from_utf8_lossy(&content);
+        let prefix = dir.strip_prefix(build_dir).unwrap_or(dir);
+        let mut lines = content.lines().peekable();
+        while let Some(line) = lines.next() {
+            let underlined = lines
+                .peek()
+                .map(|next| !next.is_empty() && next.chars().all(|c| c == '='))
+                .unwrap_or(false);
+            if !underlined {
+                continue;
+            }
+            let name = ["FAIL: ", "ERROR: ", "XPASS: "]
+                .iter()
+                .find(|result| line.starts_with(*result))
+                .map(|result| &line[result.len()..]);
+            if let Some(name) = name {
+                failed.push(prefix.join(name.trim()).display().to_string());
+            }
+        }
+    }
 }
 
 fn create(name: &Path) -> BufWriter<File> {
@@ -1326,12 +2779,6 @@ fn create(name: &Path) -> BufWriter<File> {
     BufWriter::new(file)
 }
 
-fn read_line(reader: &mut BufReader<File>, buf: &mut String, name: &Path) -> usize {
-    reader
-        .read_line(buf)
-        .unwrap_or_else(|_| panic!("Cannot read from: {:?}", name))
-}
-
 fn write_flush(writer: &mut BufWriter<File>, buf: &str, name: &Path) {
     writer
         .write_all(buf.as_bytes())
@@ -1341,6 +2788,30 @@ fn write_flush(writer: &mut BufWriter<File>, buf: &str, name: &Path) {
         .unwrap_or_else(|_| panic!("Cannot write to: {:?}", name));
 }
 
+// The environment variable used to find shared libraries at run time.
+#[cfg(target_os = "windows")]
+const DYLD_PATH_VAR: &str = "PATH";
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const DYLD_PATH_VAR: &str = "DYLD_LIBRARY_PATH";
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "ios")))]
+const DYLD_PATH_VAR: &str = "LD_LIBRARY_PATH";
+
+// Returns the value of DYLD_PATH_VAR with dirs prepended, or None if
+// dirs is empty.
+fn prepend_dyld_path<'a, I>(dirs: I) -> Option<OsString>
+where
+    I: IntoIterator<Item = &'a PathBuf>,
+{
+    let mut paths = dirs.into_iter().cloned().collect::<Vec<_>>();
+    if paths.is_empty() {
+        return None;
+    }
+    if let Some(old) = env::var_os(DYLD_PATH_VAR) {
+        paths.extend(env::split_paths(&old));
+    }
+    env::join_paths(paths).ok()
+}
+
 fn system_cache_dir() -> Option<PathBuf> {
     #[cfg(target_os = "windows")]
     {
@@ -1385,6 +2856,239 @@ fn system_cache_dir() -> Option<PathBuf> {
     }
 }
 
+// Reads a C tool setting for the target, trying <NAME>_<target>,
+// <NAME>_<target_with_underscores>, TARGET_<NAME> and <NAME> in turn,
+// like the cc crate.
+fn target_tool_env(name: &str, target: &str) -> Option<OsString> {
+    let vars = [
+        format!("{}_{}", name, target),
+        format!("{}_{}", name, target.replace('-', "_")),
+        format!("TARGET_{}", name),
+        name.to_string(),
+    ];
+    for var in &vars {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+    vars.iter()
+        .filter_map(env::var_os)
+        .find(|value| !value.is_empty())
+}
+
+// Converts a Rust target triple to a triple understood by the
+// config.sub scripts of GMP, MPFR and MPC, and used as the prefix of
+// the cross tools, for example aarch64-linux-gnu-gcc.
+fn autoconf_triple(rust_triple: &str) -> String {
+    let mut parts = rust_triple.split('-');
+    let arch = match parts.next().unwrap_or("") {
+        "riscv64gc" | "riscv64imac" => "riscv64",
+        "riscv32imac" | "riscv32imc" | "riscv32i" => "riscv32",
+        "armv7" if rust_triple.ends_with("-androideabi") => "armv7a",
+        arch => arch,
+    };
+    let rest = parts.collect::<Vec<_>>();
+    match &*rest {
+        ["pc", "windows", "gnu"] => format!("{}-w64-mingw32", arch),
+        ["unknown", "linux", env] => format!("{}-linux-{}", arch, env),
+        _ => {
+            let mut triple = arch.to_string();
+            for part in rest {
+                triple.push('-');
+                triple.push_str(part);
+            }
+            triple
+        }
+    }
+}
+
+// Configure arguments for --build and --host.
+fn cross_args(env: &Environment) -> String {
+    let target = match env.cross_target {
+        Some(ref t) => t,
+        None => return String::new(),
+    };
+    if compilation_target_allowed(&env.host_triple, target) {
+        // Building for i686 on x86_64 only needs -m32, which GMP adds
+        // itself, so build as if natively and keep using the host tools.
+        format!(" --build {}", autoconf_triple(target))
+    } else {
+        format!(
+            " --build {} --host {}",
+            autoconf_triple(&env.host_triple),
+            autoconf_triple(target)
+        )
+    }
+}
+
+// MPFR ships a config.sub that does not know about emscripten and
+// wasi, but the operating system does not matter for MPFR itself.
+fn mpfr_cross_args(env: &Environment) -> String {
+    if env.cross_target.is_some() && is_wasm(&env.target_triple) {
+        format!(
+            " --build {} --host wasm32-unknown-none",
+            autoconf_triple(&env.host_triple)
+        )
+    } else {
+        cross_args(env)
+    }
+}
+
+// Like cross_args, but with the CPU of the triple that GMP is built
+// for replaced by the CPU from GMP_MPFR_SYS_CPU, so that GMP selects
+// the mpn assembly and the gmp-mparam.h tuning for that CPU.
+fn gmp_cross_args(env: &Environment) -> String {
+    let cpu = match env.gmp_cpu {
+        GmpCpu::Named(ref cpu) => cpu,
+        _ => return cross_args(env),
+    };
+    let with_cpu = |triple: &str| {
+        let triple = autoconf_triple(triple);
+        match triple.find('-') {
+            Some(dash) => format!("{}{}", cpu, &triple[dash..]),
+            None => cpu.clone(),
+        }
+    };
+    match env.cross_target {
+        None => format!(" --build {}", with_cpu(&env.host_triple)),
+        Some(ref target) if compilation_target_allowed(&env.host_triple, target) => {
+            // the CPU name no longer tells GMP to use -m32
+            format!(" --build {} ABI=32", with_cpu(target))
+        }
+        Some(ref target) => format!(
+            " --build {} --host {}",
+            autoconf_triple(&env.host_triple),
+            with_cpu(target)
+        ),
+    }
+}
+
+// Reads GMP_MPFR_SYS_CPU. The value native is replaced by the CPU
+// detected by the config.guess script of GMP, so that libraries tuned
+// for different CPUs are cached separately.
+fn gmp_cpu(
+    src_dir: &Path,
+    out_dir: &Path,
+    cross_target: Option<&String>,
+    target_triple: &str,
+) -> GmpCpu {
+    println!("cargo:rerun-if-env-changed=GMP_MPFR_SYS_CPU");
+    let cpu = match env::var_os("GMP_MPFR_SYS_CPU") {
+        Some(ref c) if c.is_empty() => return GmpCpu::Fat,
+        Some(c) => c
+            .into_string()
+            .unwrap_or_else(|c| panic!("Cannot parse GMP_MPFR_SYS_CPU: {:?}", c)),
+        None => return GmpCpu::Fat,
+    };
+    if is_wasm(target_triple) {
+        panic!("GMP_MPFR_SYS_CPU is not supported for wasm32 targets");
+    }
+    if cpu != "native" {
+        return GmpCpu::Named(cpu);
+    }
+    if cross_target.is_some() {
+        panic!("GMP_MPFR_SYS_CPU cannot be native when cross compiling");
+    }
+    let mut cmd = Command::new("sh");
+    cmd.current_dir(out_dir)
+        .arg(src_dir.join(GMP_DIR).join("config.guess"))
+        .stderr(Stdio::null());
+    println!("$ {:?}", cmd);
+    let guess = match cmd.output() {
+        Ok(ref output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .split('-')
+            .next()
+            .unwrap_or("")
+            .to_string(),
+        _ => String::new(),
+    };
+    if guess.is_empty() {
+        GmpCpu::Native
+    } else {
+        println!("$ #Native CPU is {}", guess);
+        GmpCpu::Named(guess)
+    }
+}
+
+// The sanitizers selected by the c-sanitize-* features.
+fn sanitizers() -> Vec<&'static str> {
+    let features = [
+        ("CARGO_FEATURE_C_SANITIZE_ADDRESS", "address"),
+        ("CARGO_FEATURE_C_SANITIZE_MEMORY", "memory"),
+        ("CARGO_FEATURE_C_SANITIZE_UNDEFINED", "undefined"),
+    ];
+    let sanitizers = features
+        .iter()
+        .filter(|&&(feature, _)| there_is_env(feature))
+        .map(|&(_, sanitizer)| sanitizer)
+        .collect::<Vec<_>>();
+    if sanitizers.contains(&"address") && sanitizers.contains(&"memory") {
+        panic!("the c-sanitize-address and c-sanitize-memory features cannot be used together");
+    }
+    sanitizers
+}
+
+// The CFLAGS configure argument for GMP with the sanitizers, based on
+// the CFLAGS environment variable if it is set. MPFR and MPC use the
+// flags from gmp.h, so they are instrumented too.
+fn sanitize_args(env: &Environment) -> String {
+    if env.sanitizers.is_empty() {
+        return String::new();
+    }
+    let mut cflags = env
+        .c_tools
+        .iter()
+        .find(|&&(name, _)| name == "CFLAGS")
+        .map(|(_, value)| value.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("-O1"));
+    cflags.push_str(" -g -fno-omit-frame-pointer");
+    for sanitizer in &env.sanitizers {
+        cflags.push_str(" -fsanitize=");
+        cflags.push_str(sanitizer);
+    }
+    format!(" CFLAGS={}", shell_quote(&cflags))
+}
+
+fn is_wasm(target: &str) -> bool {
+    target.starts_with("wasm32-")
+}
+
+fn default_c_compiler(host: &str, target: &str) -> OsString {
+    if compilation_target_allowed(host, target) {
+        "gcc".into()
+    } else if target.contains("-emscripten") {
+        "emcc".into()
+    } else if is_wasm(target) {
+        // wasi-sdk provides for example wasm32-wasip1-clang
+        format!("{}-clang", autoconf_triple(target)).into()
+    } else {
+        format!("{}-gcc", autoconf_triple(target)).into()
+    }
+}
+
+// Whether the libc crate has FILE for the target, which is not the
+// case for targets without an operating system such as
+// wasm32-unknown-unknown.
+fn target_has_file() -> bool {
+    match env::var("CARGO_CFG_TARGET_OS") {
+        Ok(os) => os != "unknown" && os != "none",
+        Err(_) => true,
+    }
+}
+
+// The number of bits in c_ulong, which is 32 on Windows even for
+// 64-bit targets.
+fn target_c_ulong_bits() -> i32 {
+    match env::var("CARGO_CFG_TARGET_OS") {
+        Ok(ref os) if os == "windows" => return 32,
+        _ => {}
+    }
+    cargo_env("CARGO_CFG_TARGET_POINTER_WIDTH")
+        .into_string()
+        .ok()
+        .and_then(|width| width.parse().ok())
+        .expect("env var CARGO_CFG_TARGET_POINTER_WIDTH having a sensible value")
+}
+
 fn compilation_target_allowed(host: &str, target: &str) -> bool {
     if host == target {
         return true;
@@ -1444,78 +3148,100 @@ typedef FILE *__cdecl (*_f__acrt_iob_func)(unsigned index);
 _f__acrt_iob_func __MINGW_IMP_SYMBOL(__acrt_iob_func) = __acrt_iob_func;
 "#;
 
-// prints part of the header
-const SYSTEM_GMP_C: &str = r##"/* system_gmp.c */
-#include <gmp.h>
+// Included by the header probes. Each PROBE_INT or PROBE_STR prints a
+// line such as "int GMP_LIMB_BITS 64". With PROBE_ASM, the probe is
+// only compiled to assembly, and the lines are placed in the assembly
+// with a "@@@probe " prefix, so that no target program has to run.
+const PROBE_H: &str = r##"/* probe.h */
+#include <stddef.h>
 #include <stdio.h>
 
-#define STRINGIFY(x) #x
-#define DEFINE_STR(x) ("#define " #x " " STRINGIFY(x) "\n")
-
-int main(void) {
-    FILE *f = fopen("system_gmp.out", "w");
-
-#ifdef _LONG_LONG_LIMB
-    fputs(DEFINE_STR(_LONG_LONG_LIMB), f);
+#ifdef PROBE_ASM
+#define PROBE_INT(name, val) \
+    __asm__ volatile ("\n@@@probe int " name " %0" : : "i" ((long long) (val)))
+#define PROBE_STR(name, val) __asm__ volatile ("\n@@@probe str " name " " val)
+#define PROBE_MAIN void probe(void)
+#define PROBE_END
 #else
-    fputs("#undef _LONG_LONG_LIMB\n", f);
+#define PROBE_INT(name, val) printf("int %s %lld\n", name, (long long) (val))
+#define PROBE_STR(name, val) printf("str %s %s\n", name, val)
+#define PROBE_MAIN int main(void)
+#define PROBE_END return 0
 #endif
 
-    fputs(DEFINE_STR(__GNU_MP_VERSION), f);
-    fputs(DEFINE_STR(__GNU_MP_VERSION_MINOR), f);
-    fputs(DEFINE_STR(__GNU_MP_VERSION_PATCHLEVEL), f);
-    fputs(DEFINE_STR(GMP_LIMB_BITS), f);
-    fputs(DEFINE_STR(GMP_NAIL_BITS), f);
-    fputs(DEFINE_STR(__GMP_CC), f);
-    fputs(DEFINE_STR(__GMP_CFLAGS), f);
+#define PROBE_MACRO_INT(x) PROBE_INT(#x, x)
+#define PROBE_MACRO_STR(x) PROBE_STR(#x, x)
+#define PROBE_SIZEOF(t) PROBE_INT("sizeof(" #t ")", sizeof(t))
+#define PROBE_OFFSETOF(t, m) PROBE_INT("offsetof(" #t "," #m ")", offsetof(t, m))
+"##;
 
-    fclose(f);
+const GMP_PROBE_C: &str = r##"/* gmp_probe.c */
+#include "probe.h"
+#include <gmp.h>
 
-    return 0;
+PROBE_MAIN {
+#ifdef _LONG_LONG_LIMB
+    PROBE_INT("_LONG_LONG_LIMB", 1);
+#else
+    PROBE_INT("_LONG_LONG_LIMB", 0);
+#endif
+    PROBE_MACRO_INT(__GNU_MP_VERSION);
+    PROBE_MACRO_INT(__GNU_MP_VERSION_MINOR);
+    PROBE_MACRO_INT(__GNU_MP_VERSION_PATCHLEVEL);
+    PROBE_MACRO_INT(GMP_LIMB_BITS);
+    PROBE_MACRO_INT(GMP_NAIL_BITS);
+    PROBE_MACRO_STR(__GMP_CC);
+    PROBE_MACRO_STR(__GMP_CFLAGS);
+    PROBE_SIZEOF(mp_limb_t);
+    PROBE_SIZEOF(mpz_t);
+    PROBE_SIZEOF(mpq_t);
+    PROBE_OFFSETOF(__mpq_struct, _mp_den);
+    PROBE_SIZEOF(mpf_t);
+    PROBE_SIZEOF(gmp_randstate_t);
+    PROBE_END;
 }
 "##;
 
-// prints part of the header
-const SYSTEM_MPFR_C: &str = r##"/* system_mpfr.c */
+const MPFR_PROBE_C: &str = r##"/* mpfr_probe.c */
+#include "probe.h"
 #include <mpfr.h>
-#include <stdio.h>
-
-#define STRINGIFY(x) #x
-#define DEFINE_STR(x) ("#define " #x " " STRINGIFY(x) "\n")
 
-int main(void) {
-    FILE *f = fopen("system_mpfr.out", "w");
-
-    fputs(DEFINE_STR(MPFR_VERSION_MAJOR), f);
-    fputs(DEFINE_STR(MPFR_VERSION_MINOR), f);
-    fputs(DEFINE_STR(MPFR_VERSION_PATCHLEVEL), f);
-    fputs(DEFINE_STR(MPFR_VERSION_STRING), f);
-
-    fclose(f);
-
-    return 0;
+PROBE_MAIN {
+    PROBE_MACRO_INT(MPFR_VERSION_MAJOR);
+    PROBE_MACRO_INT(MPFR_VERSION_MINOR);
+    PROBE_MACRO_INT(MPFR_VERSION_PATCHLEVEL);
+    PROBE_MACRO_STR(MPFR_VERSION_STRING);
+    PROBE_MACRO_INT(MPFR_PREC_MIN);
+    PROBE_MACRO_INT(MPFR_PREC_MAX);
+    PROBE_MACRO_INT(MPFR_EMIN_DEFAULT);
+    PROBE_MACRO_INT(MPFR_EMAX_DEFAULT);
+    PROBE_MACRO_INT(MPFR_FLAGS_UNDERFLOW);
+    PROBE_MACRO_INT(MPFR_FLAGS_OVERFLOW);
+    PROBE_MACRO_INT(MPFR_FLAGS_NAN);
+    PROBE_MACRO_INT(MPFR_FLAGS_INEXACT);
+    PROBE_MACRO_INT(MPFR_FLAGS_ERANGE);
+    PROBE_MACRO_INT(MPFR_FLAGS_DIVBY0);
+    PROBE_MACRO_INT(MPFR_FLAGS_ALL);
+    PROBE_SIZEOF(mpfr_prec_t);
+    PROBE_SIZEOF(mpfr_exp_t);
+    PROBE_SIZEOF(mpfr_flags_t);
+    PROBE_SIZEOF(mpfr_t);
+    PROBE_END;
 }
 "##;
 
-// prints part of the header
-const SYSTEM_MPC_C: &str = r##"/* system_mpc.c */
+const MPC_PROBE_C: &str = r##"/* mpc_probe.c */
+#include "probe.h"
 #include <mpc.h>
-#include <stdio.h>
-
-#define STRINGIFY(x) #x
-#define DEFINE_STR(x) ("#define " #x " " STRINGIFY(x) "\n")
-
-int main(void) {
-    FILE *f = fopen("system_mpc.out", "w");
-
-    fputs(DEFINE_STR(MPC_VERSION_MAJOR), f);
-    fputs(DEFINE_STR(MPC_VERSION_MINOR), f);
-    fputs(DEFINE_STR(MPC_VERSION_PATCHLEVEL), f);
-    fputs(DEFINE_STR(MPC_VERSION_STRING), f);
 
-    fclose(f);
-
-    return 0;
+PROBE_MAIN {
+    PROBE_MACRO_INT(MPC_VERSION_MAJOR);
+    PROBE_MACRO_INT(MPC_VERSION_MINOR);
+    PROBE_MACRO_INT(MPC_VERSION_PATCHLEVEL);
+    PROBE_MACRO_STR(MPC_VERSION_STRING);
+    PROBE_SIZEOF(mpc_t);
+    PROBE_OFFSETOF(__mpc_struct, im);
+    PROBE_END;
 }
 "##;
 
@@ -1530,3 +3256,10 @@ const TRY_UNSAFE_IN_UNSAFE: &str = r#"// try_unsafe_in_unsafe.rs
 #![warn(unsafe_op_in_unsafe_fn)]
 fn main() {}
 "#;
+
+const TRY_C_UNWIND: &str = r#"// try_c_unwind.rs
+extern "C-unwind" fn f() {}
+fn main() {
+    f();
+}
+"#;
@@ -0,0 +1,44 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+// This module is only compiled if the C-unwind ABI is supported, as
+// older compilers cannot even parse it. Otherwise, the macros are
+// defined in lib.rs using the C ABI.
+
+// Declares C library functions that can call the memory functions,
+// so that a panic in a memory function set using
+// gmp::set_memory_functions_unwind can unwind through them.
+macro_rules! extern_c {
+    ($($decl:tt)*) => {
+        extern "C-unwind" {
+            $($decl)*
+        }
+    };
+}
+
+// Defines functions that call C library functions that can call the
+// memory functions, so that they can unwind too.
+macro_rules! extern_c_fn {
+    ($(
+        $(#[$attr:meta])*
+        pub unsafe fn $name:ident($($arg:tt)*) $(-> $ret:ty)? $body:block
+    )*) => {
+        $(
+            $(#[$attr])*
+            pub unsafe extern "C-unwind" fn $name($($arg)*) $(-> $ret)? $body
+        )*
+    };
+}
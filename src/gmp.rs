@@ -38,13 +38,17 @@ unsafe {
 #![allow(non_camel_case_types, non_snake_case)]
 #![allow(clippy::cmp_owned)]
 
+#[cfg(feature = "rust-alloc")]
+use alloc::alloc::Layout;
 use core::{
     cmp::Ordering,
     fmt::{Debug, Formatter, Result as FmtResult},
-    mem::MaybeUninit,
+    mem::{self, MaybeUninit},
     ptr::NonNull,
 };
-use libc::{c_char, c_int, c_long, c_uchar, c_uint, c_ulong, c_ushort, c_void, FILE};
+#[cfg(not(no_file))]
+use libc::FILE;
+use libc::{c_char, c_int, c_long, c_uchar, c_uint, c_ulong, c_ushort, c_void};
 
 include!(concat!(env!("OUT_DIR"), "/gmp_h.rs"));
 
@@ -252,6 +256,14 @@ pub struct randfnptr_t {
     pub iset: unsafe extern "C" fn(dst: *mut randstate_t, src: *const randstate_t),
 }
 
+// Compile-time checks that the layouts match the sizes and offsets
+// found in gmp.h by the build script.
+const _: [(); GMP_SIZEOF_MPZ_T] = [(); mem::size_of::<mpz_t>()];
+const _: [(); GMP_SIZEOF_MPQ_T] = [(); mem::size_of::<mpq_t>()];
+const _: [(); GMP_OFFSETOF_MPQ_DEN] = [(); mem::size_of::<mpz_t>()];
+const _: [(); GMP_SIZEOF_MPF_T] = [(); mem::size_of::<mpf_t>()];
+const _: [(); GMP_SIZEOF_RANDSTATE_T] = [(); mem::size_of::<randstate_t>()];
+
 // Types for function declarations in this file.
 
 type mpz_srcptr = *const mpz_t;
@@ -269,7 +281,7 @@ type randstate_ptr = *mut randstate_t;
 
 // Initialization Functions
 
-extern "C" {
+extern_c! {
     /// See: [`mpz_init`](../C/GMP/constant.Integer_Functions.html#index-mpz_005finit)
     #[link_name = "__gmpz_init"]
     pub fn mpz_init(x: mpz_ptr);
@@ -304,12 +316,14 @@ extern "C" {
     #[link_name = "__gmpz_set_d"]
     pub fn mpz_set_d(rop: mpz_ptr, op: f64);
 }
-/// See: [`mpz_set_q`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fset_005fq)
-#[inline]
-pub unsafe extern "C" fn mpz_set_q(rop: mpz_ptr, op: mpq_srcptr) {
-    unsafe { mpz_tdiv_q(rop, mpq_numref_const(op), mpq_denref_const(op)) }
+extern_c_fn! {
+    /// See: [`mpz_set_q`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fset_005fq)
+    #[inline]
+    pub unsafe fn mpz_set_q(rop: mpz_ptr, op: mpq_srcptr) {
+        unsafe { mpz_tdiv_q(rop, mpq_numref_const(op), mpq_denref_const(op)) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_set_f`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fset_005ff)
     #[link_name = "__gmpz_set_f"]
     pub fn mpz_set_f(rop: mpz_ptr, op: mpf_srcptr);
@@ -368,7 +382,7 @@ pub unsafe extern "C" fn mpz_get_ui(op: mpz_srcptr) -> c_ulong {
         unsafe { *p + ((*(p.offset(1))) << NUMB_BITS) }
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_get_si`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fget_005fsi)
     #[link_name = "__gmpz_get_si"]
     pub fn mpz_get_si(op: mpz_srcptr) -> c_long;
@@ -424,32 +438,34 @@ extern "C" {
     #[link_name = "__gmpz_mul_2exp"]
     pub fn mpz_mul_2exp(rop: mpz_ptr, op1: mpz_srcptr, op2: bitcnt_t);
 }
-/// See: [`mpz_neg`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fneg)
-#[inline]
-pub unsafe extern "C" fn mpz_neg(rop: mpz_ptr, op: mpz_srcptr) {
-    if rop as mpz_srcptr != op {
+extern_c_fn! {
+    /// See: [`mpz_neg`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fneg)
+    #[inline]
+    pub unsafe fn mpz_neg(rop: mpz_ptr, op: mpz_srcptr) {
+        if rop as mpz_srcptr != op {
+            unsafe {
+                mpz_set(rop, op);
+            }
+        }
         unsafe {
-            mpz_set(rop, op);
+            (*rop).size = -(*rop).size;
         }
     }
-    unsafe {
-        (*rop).size = -(*rop).size;
-    }
-}
-/// See: [`mpz_abs`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fabs)
-#[inline]
-pub unsafe extern "C" fn mpz_abs(rop: mpz_ptr, op: mpz_srcptr) {
-    unsafe {
-        if rop as mpz_srcptr != op {
-            mpz_set(rop, op);
+    /// See: [`mpz_abs`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fabs)
+    #[inline]
+    pub unsafe fn mpz_abs(rop: mpz_ptr, op: mpz_srcptr) {
+        unsafe {
+            if rop as mpz_srcptr != op {
+                mpz_set(rop, op);
+            }
+            (*rop).size = (*rop).size.abs();
         }
-        (*rop).size = (*rop).size.abs();
     }
 }
 
 // Division Functions
 
-extern "C" {
+extern_c! {
     /// See: [`mpz_cdiv_q`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fcdiv_005fq)
     #[link_name = "__gmpz_cdiv_q"]
     pub fn mpz_cdiv_q(q: mpz_ptr, n: mpz_srcptr, d: mpz_srcptr);
@@ -535,12 +551,14 @@ extern "C" {
     #[link_name = "__gmpz_mod"]
     pub fn mpz_mod(r: mpz_ptr, n: mpz_srcptr, d: mpz_srcptr);
 }
-/// See: [`mpz_mod_ui`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fmod_005fui)
-#[inline]
-pub unsafe extern "C" fn mpz_mod_ui(r: mpz_ptr, n: mpz_srcptr, d: c_ulong) -> c_ulong {
-    unsafe { mpz_fdiv_r_ui(r, n, d) }
+extern_c_fn! {
+    /// See: [`mpz_mod_ui`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fmod_005fui)
+    #[inline]
+    pub unsafe fn mpz_mod_ui(r: mpz_ptr, n: mpz_srcptr, d: c_ulong) -> c_ulong {
+        unsafe { mpz_fdiv_r_ui(r, n, d) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_divexact`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fdivexact)
     #[link_name = "__gmpz_divexact"]
     pub fn mpz_divexact(q: mpz_ptr, n: mpz_srcptr, d: mpz_srcptr);
@@ -602,22 +620,24 @@ extern "C" {
     #[link_name = "__gmpz_perfect_power_p"]
     pub fn mpz_perfect_power_p(op: mpz_srcptr) -> c_int;
 }
-/// See: [`mpz_perfect_square_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fperfect_005fsquare_005fp)
-#[inline]
-pub unsafe extern "C" fn mpz_perfect_square_p(op: mpz_srcptr) -> c_int {
-    let op_size = unsafe { (*op).size };
-    if op_size > 0 {
-        unsafe { mpn_perfect_square_p((*op).d.as_ptr(), op_size.into()) }
-    } else if op_size >= 0 {
-        1
-    } else {
-        0
+extern_c_fn! {
+    /// See: [`mpz_perfect_square_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fperfect_005fsquare_005fp)
+    #[inline]
+    pub unsafe fn mpz_perfect_square_p(op: mpz_srcptr) -> c_int {
+        let op_size = unsafe { (*op).size };
+        if op_size > 0 {
+            unsafe { mpn_perfect_square_p((*op).d.as_ptr(), op_size.into()) }
+        } else if op_size >= 0 {
+            1
+        } else {
+            0
+        }
     }
 }
 
 // Number Theoretic Functions
 
-extern "C" {
+extern_c! {
     /// See: [`mpz_probab_prime_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fprobab_005fprime_005fp)
     #[link_name = "__gmpz_probab_prime_p"]
     pub fn mpz_probab_prime_p(n: mpz_srcptr, reps: c_int) -> c_int;
@@ -646,17 +666,19 @@ extern "C" {
     #[link_name = "__gmpz_jacobi"]
     pub fn mpz_jacobi(a: mpz_srcptr, b: mpz_srcptr) -> c_int;
 }
-/// See: [`mpz_legendre`](../C/GMP/constant.Integer_Functions.html#index-mpz_005flegendre)
-#[inline]
-pub unsafe extern "C" fn mpz_legendre(a: mpz_srcptr, p: mpz_srcptr) -> c_int {
-    unsafe { mpz_jacobi(a, p) }
-}
-/// See: [`mpz_kronecker`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fkronecker)
-#[inline]
-pub unsafe extern "C" fn mpz_kronecker(a: mpz_srcptr, b: mpz_srcptr) -> c_int {
-    unsafe { mpz_jacobi(a, b) }
+extern_c_fn! {
+    /// See: [`mpz_legendre`](../C/GMP/constant.Integer_Functions.html#index-mpz_005flegendre)
+    #[inline]
+    pub unsafe fn mpz_legendre(a: mpz_srcptr, p: mpz_srcptr) -> c_int {
+        unsafe { mpz_jacobi(a, p) }
+    }
+    /// See: [`mpz_kronecker`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fkronecker)
+    #[inline]
+    pub unsafe fn mpz_kronecker(a: mpz_srcptr, b: mpz_srcptr) -> c_int {
+        unsafe { mpz_jacobi(a, b) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_kronecker_si`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fkronecker_005fsi)
     #[link_name = "__gmpz_kronecker_si"]
     pub fn mpz_kronecker_si(a: mpz_srcptr, b: c_long) -> c_int;
@@ -736,7 +758,7 @@ pub unsafe extern "C" fn mpz_sgn(op: mpz_srcptr) -> c_int {
         Ordering::Greater => 1,
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_and`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fand)
     #[link_name = "__gmpz_and"]
     pub fn mpz_and(rop: mpz_ptr, op1: mpz_srcptr, op2: mpz_srcptr);
@@ -750,17 +772,19 @@ extern "C" {
     #[link_name = "__gmpz_com"]
     pub fn mpz_com(rop: mpz_ptr, op: mpz_srcptr);
 }
-/// See: [`mpz_popcount`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fpopcount)
-#[inline]
-pub unsafe extern "C" fn mpz_popcount(op: mpz_srcptr) -> bitcnt_t {
-    let size = unsafe { (*op).size };
-    match size.cmp(&0) {
-        Ordering::Less => !0,
-        Ordering::Equal => 0,
-        Ordering::Greater => unsafe { mpn_popcount((*op).d.as_ptr(), size.into()) },
+extern_c_fn! {
+    /// See: [`mpz_popcount`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fpopcount)
+    #[inline]
+    pub unsafe fn mpz_popcount(op: mpz_srcptr) -> bitcnt_t {
+        let size = unsafe { (*op).size };
+        match size.cmp(&0) {
+            Ordering::Less => !0,
+            Ordering::Equal => 0,
+            Ordering::Greater => unsafe { mpn_popcount((*op).d.as_ptr(), size.into()) },
+        }
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_hamdist`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fhamdist)
     #[link_name = "__gmpz_hamdist"]
     pub fn mpz_hamdist(op1: mpz_srcptr, op2: mpz_srcptr) -> bitcnt_t;
@@ -785,15 +809,19 @@ extern "C" {
 
     // Input and Ouput Functions
 
+    #[cfg(not(no_file))]
     /// See: [`mpz_out_str`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fout_005fstr)
     #[link_name = "__gmpz_out_str"]
     pub fn mpz_out_str(stream: *mut FILE, base: c_int, op: mpz_srcptr) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpz_inp_str`](../C/GMP/constant.Integer_Functions.html#index-mpz_005finp_005fstr)
     #[link_name = "__gmpz_inp_str"]
     pub fn mpz_inp_str(rop: mpz_ptr, stream: *mut FILE, base: c_int) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpz_out_raw`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fout_005fraw)
     #[link_name = "__gmpz_out_raw"]
     pub fn mpz_out_raw(stream: *mut FILE, op: mpz_srcptr) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpz_inp_raw`](../C/GMP/constant.Integer_Functions.html#index-mpz_005finp_005fraw)
     #[link_name = "__gmpz_inp_raw"]
     pub fn mpz_inp_raw(rop: mpz_ptr, stream: *mut FILE) -> usize;
@@ -877,7 +905,7 @@ mpz_fits! {
     /// See: [`mpz_fits_ulong_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005ffits_005fulong_005fp)
     fn mpz_fits_ulong_p(c_ulong::max_value());
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_fits_slong_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005ffits_005fslong_005fp)
     #[link_name = "__gmpz_fits_slong_p"]
     pub fn mpz_fits_slong_p(op: mpz_srcptr) -> c_int;
@@ -886,7 +914,7 @@ mpz_fits! {
     /// See: [`mpz_fits_uint_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005ffits_005fuint_005fp)
     fn mpz_fits_uint_p(c_uint::max_value());
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_fits_sint_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005ffits_005fsint_005fp)
     #[link_name = "__gmpz_fits_sint_p"]
     pub fn mpz_fits_sint_p(op: mpz_srcptr) -> c_int;
@@ -895,7 +923,7 @@ mpz_fits! {
     /// See: [`mpz_fits_ushort_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005ffits_005fushort_005fp)
     fn mpz_fits_ushort_p(c_ushort::max_value());
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_fits_sshort_p`](../C/GMP/constant.Integer_Functions.html#index-mpz_005ffits_005fsshort_005fp)
     #[link_name = "__gmpz_fits_sshort_p"]
     pub fn mpz_fits_sshort_p(op: mpz_srcptr) -> c_int;
@@ -918,7 +946,7 @@ pub unsafe extern "C" fn mpz_even_p(op: mpz_srcptr) -> c_int {
         0
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_sizeinbase`](../C/GMP/constant.Integer_Functions.html#index-mpz_005fsizeinbase)
     #[link_name = "__gmpz_sizeinbase"]
     pub fn mpz_sizeinbase(arg1: mpz_srcptr, arg2: c_int) -> usize;
@@ -943,7 +971,7 @@ pub unsafe extern "C" fn mpz_getlimbn(op: mpz_srcptr, n: size_t) -> limb_t {
 pub unsafe extern "C" fn mpz_size(op: mpz_srcptr) -> usize {
     unsafe { (*op).size }.abs() as usize
 }
-extern "C" {
+extern_c! {
     /// See: [`mpz_limbs_read`](../C/GMP/constant.Integer_Functions.html#index-mpz_005flimbs_005fread)
     #[link_name = "__gmpz_limbs_read"]
     pub fn mpz_limbs_read(x: mpz_srcptr) -> mp_srcptr;
@@ -977,7 +1005,7 @@ pub const unsafe fn MPZ_ROINIT_N(xp: mp_ptr, xs: size_t) -> mpz_t {
 
 // Rational numbers
 
-extern "C" {
+extern_c! {
     /// See: [`mpq_canonicalize`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fcanonicalize)
     #[link_name = "__gmpq_canonicalize"]
     pub fn mpq_canonicalize(op: mpq_ptr);
@@ -1051,29 +1079,31 @@ extern "C" {
     #[link_name = "__gmpq_div_2exp"]
     pub fn mpq_div_2exp(rop: mpq_ptr, op1: mpq_srcptr, op2: bitcnt_t);
 }
-/// See: [`mpq_neg`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fneg)
-#[inline]
-pub unsafe extern "C" fn mpq_neg(negated_operand: mpq_ptr, operand: mpq_srcptr) {
-    if negated_operand as mpq_srcptr != operand {
-        unsafe { mpq_set(negated_operand, operand) };
-    }
-    unsafe {
-        (*negated_operand).num.size = -(*negated_operand).num.size;
-    }
-}
-/// See: [`mpq_abs`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fabs)
-#[inline]
-pub unsafe extern "C" fn mpq_abs(rop: mpq_ptr, op: mpq_srcptr) {
-    if rop as mpq_srcptr != op {
+extern_c_fn! {
+    /// See: [`mpq_neg`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fneg)
+    #[inline]
+    pub unsafe fn mpq_neg(negated_operand: mpq_ptr, operand: mpq_srcptr) {
+        if negated_operand as mpq_srcptr != operand {
+            unsafe { mpq_set(negated_operand, operand) };
+        }
         unsafe {
-            mpq_set(rop, op);
+            (*negated_operand).num.size = -(*negated_operand).num.size;
         }
     }
-    unsafe {
-        (*rop).num.size = (*rop).num.size.abs();
+    /// See: [`mpq_abs`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fabs)
+    #[inline]
+    pub unsafe fn mpq_abs(rop: mpq_ptr, op: mpq_srcptr) {
+        if rop as mpq_srcptr != op {
+            unsafe {
+                mpq_set(rop, op);
+            }
+        }
+        unsafe {
+            (*rop).num.size = (*rop).num.size.abs();
+        }
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpq_inv`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005finv)
     #[link_name = "__gmpq_inv"]
     pub fn mpq_inv(inverted_number: mpq_ptr, number: mpq_srcptr);
@@ -1102,7 +1132,7 @@ pub unsafe extern "C" fn mpq_sgn(op: mpq_srcptr) -> c_int {
         Ordering::Greater => 1,
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpq_equal`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fequal)
     #[link_name = "__gmpq_equal"]
     pub fn mpq_equal(op1: mpq_srcptr, op2: mpq_srcptr) -> c_int;
@@ -1130,7 +1160,7 @@ pub unsafe extern "C" fn mpq_denref(op: mpq_ptr) -> mpz_ptr {
 pub unsafe extern "C" fn mpq_denref_const(op: mpq_srcptr) -> mpz_srcptr {
     unsafe { (op as mpz_srcptr).offset(1) }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpq_get_num`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fget_005fnum)
     #[link_name = "__gmpq_get_num"]
     pub fn mpq_get_num(numerator: mpz_ptr, rational: mpq_srcptr);
@@ -1146,9 +1176,11 @@ extern "C" {
 
     // Input and Output Functions
 
+    #[cfg(not(no_file))]
     /// See: [`mpq_out_str`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005fout_005fstr)
     #[link_name = "__gmpq_out_str"]
     pub fn mpq_out_str(stream: *mut FILE, base: c_int, op: mpq_srcptr) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpq_inp_str`](../C/GMP/constant.Rational_Number_Functions.html#index-mpq_005finp_005fstr)
     #[link_name = "__gmpq_inp_str"]
     pub fn mpq_inp_str(rop: mpq_ptr, stream: *mut FILE, base: c_int) -> usize;
@@ -1158,7 +1190,7 @@ extern "C" {
 
 // Initialization Functions
 
-extern "C" {
+extern_c! {
     /// See: [`mpf_set_default_prec`](../C/GMP/constant.Floating_point_Functions.html#index-mpf_005fset_005fdefault_005fprec)
     #[link_name = "__gmpf_set_default_prec"]
     pub fn mpf_set_default_prec(prec: bitcnt_t);
@@ -1349,10 +1381,12 @@ pub unsafe extern "C" fn mpf_sgn(op: mpf_srcptr) -> c_int {
 
 // Input and Output Functions
 
-extern "C" {
+extern_c! {
+    #[cfg(not(no_file))]
     /// See: [`mpf_out_str`](../C/GMP/constant.Floating_point_Functions.html#index-mpf_005fout_005fstr)
     #[link_name = "__gmpf_out_str"]
     pub fn mpf_out_str(stream: *mut FILE, base: c_int, n_digits: usize, op: mpf_srcptr) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpf_inp_str`](../C/GMP/constant.Floating_point_Functions.html#index-mpf_005finp_005fstr)
     #[link_name = "__gmpf_inp_str"]
     pub fn mpf_inp_str(rop: mpf_ptr, stream: *mut FILE, base: c_int) -> usize;
@@ -1399,7 +1433,7 @@ extern "C" {
 
 // Low-Level Functions
 
-extern "C" {
+extern_c! {
     /// See: [`mpn_add_n`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fadd_005fn)
     #[link_name = "__gmpn_add_n"]
     pub fn mpn_add_n(rp: mp_ptr, s1p: mp_srcptr, s2p: mp_srcptr, n: size_t) -> limb_t;
@@ -1460,27 +1494,31 @@ extern "C" {
         s3limb: limb_t,
     ) -> limb_t;
 }
-/// See: [`mpn_divmod_1`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fdivmod_005f1)
-#[inline]
-pub unsafe extern "C" fn mpn_divmod_1(
-    r1p: mp_ptr,
-    s2p: mp_srcptr,
-    s2n: size_t,
-    s3limb: limb_t,
-) -> limb_t {
-    unsafe { mpn_divrem_1(r1p, 0, s2p, s2n, s3limb) }
+extern_c_fn! {
+    /// See: [`mpn_divmod_1`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fdivmod_005f1)
+    #[inline]
+    pub unsafe fn mpn_divmod_1(
+        r1p: mp_ptr,
+        s2p: mp_srcptr,
+        s2n: size_t,
+        s3limb: limb_t,
+    ) -> limb_t {
+        unsafe { mpn_divrem_1(r1p, 0, s2p, s2n, s3limb) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpn_divexact_1`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fdivexact_005f1)
     #[link_name = "__gmpn_divexact_1"]
     pub fn mpn_divexact_1(rp: mp_ptr, sp: mp_srcptr, n: size_t, d: limb_t);
 }
-/// See: [`mpn_divexact_by3`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fdivexact_005fby3)
-#[inline]
-pub unsafe extern "C" fn mpn_divexact_by3(rp: mp_ptr, sp: mp_srcptr, n: size_t) -> limb_t {
-    unsafe { mpn_divexact_by3c(rp, sp, n, 0) }
+extern_c_fn! {
+    /// See: [`mpn_divexact_by3`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fdivexact_005fby3)
+    #[inline]
+    pub unsafe fn mpn_divexact_by3(rp: mp_ptr, sp: mp_srcptr, n: size_t) -> limb_t {
+        unsafe { mpn_divexact_by3c(rp, sp, n, 0) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpn_divexact_by3c`](../C/GMP/constant.Low_level_Functions.html#index-mpn_005fdivexact_005fby3c)
     #[link_name = "__gmpn_divexact_by3c"]
     pub fn mpn_divexact_by3c(rp: mp_ptr, sp: mp_srcptr, n: size_t, carry: limb_t) -> limb_t;
@@ -1702,7 +1740,7 @@ extern "C" {
 
 // Random State Initialization
 
-extern "C" {
+extern_c! {
     /// See: [`gmp_randinit_default`](../C/GMP/constant.Random_Number_Functions.html#index-gmp_005frandinit_005fdefault)
     #[link_name = "__gmp_randinit_default"]
     pub fn randinit_default(state: randstate_ptr);
@@ -1743,10 +1781,11 @@ extern "C" {
 
 // Formatted Output
 
-extern "C" {
+extern_c! {
     /// See: [`gmp_printf`](../C/GMP/constant.Formatted_Output.html#index-gmp_005fprintf)
     #[link_name = "__gmp_printf"]
     pub fn printf(fmt: *const c_char, ...) -> c_int;
+    #[cfg(not(no_file))]
     /// See: [`gmp_fprintf`](../C/GMP/constant.Formatted_Output.html#index-gmp_005ffprintf)
     #[link_name = "__gmp_fprintf"]
     pub fn fprintf(fp: *mut FILE, fmt: *const c_char, ...) -> c_int;
@@ -1763,10 +1802,11 @@ extern "C" {
 
 // Formatted Input
 
-extern "C" {
+extern_c! {
     /// See: [`gmp_scanf`](../C/GMP/constant.Formatted_Input.html#index-gmp_005fscanf)
     #[link_name = "__gmp_scanf"]
     pub fn scanf(fmt: *const c_char, ...) -> c_int;
+    #[cfg(not(no_file))]
     /// See: [`gmp_fscanf`](../C/GMP/constant.Formatted_Input.html#index-gmp_005ffscanf)
     #[link_name = "__gmp_fscanf"]
     pub fn fscanf(fp: *mut FILE, fmt: *const c_char, ...) -> c_int;
@@ -1805,7 +1845,7 @@ pub type reallocate_function =
 /// changed to `unsafe extern "C" fn(ptr: *mut c_void, size: usize)`,
 /// that is it will no longer be an [`Option`].
 pub type free_function = Option<unsafe extern "C" fn(ptr: *mut c_void, size: usize)>;
-extern "C" {
+extern_c! {
     /// See: [`mp_set_memory_functions`](../C/GMP/constant.Custom_Allocation.html#index-mp_005fset_005fmemory_005ffunctions)
     ///
     /// # Planned change
@@ -1831,6 +1871,173 @@ extern "C" {
     );
 }
 
+#[cfg(c_unwind)]
+mod c_unwind;
+#[cfg(c_unwind)]
+pub use self::c_unwind::{
+    allocate_function_unwind, free_function_unwind, get_memory_functions_unwind,
+    reallocate_function_unwind, set_memory_functions_unwind,
+};
+
+// The alignment of blocks allocated by the Rust memory functions. This
+// is at least the alignment guaranteed by malloc, which is what the C
+// libraries expect.
+#[cfg(feature = "rust-alloc")]
+const RUST_ALLOC_ALIGN: usize = 16;
+
+// Zero-sized allocations are not allowed by the global allocator, so
+// blocks have a size of at least one byte. A size that is too large for
+// a layout is handled as a failure to allocate the largest layout, as
+// the memory functions must not unwind.
+#[cfg(feature = "rust-alloc")]
+#[inline]
+fn rust_alloc_layout(size: usize) -> Layout {
+    let size = if size == 0 { 1 } else { size };
+    match Layout::from_size_align(size, RUST_ALLOC_ALIGN) {
+        Ok(layout) => layout,
+        Err(_) => {
+            // the largest multiple of the alignment up to isize::MAX
+            let max = (!0usize >> 1) - (RUST_ALLOC_ALIGN - 1);
+            let max_layout = unsafe { Layout::from_size_align_unchecked(max, RUST_ALLOC_ALIGN) };
+            alloc::alloc::handle_alloc_error(max_layout)
+        }
+    }
+}
+
+/// An [`allocate_function`] that allocates memory using Rust’s global
+/// allocator.
+///
+/// This function is only available when the experimental `rust-alloc`
+/// feature is enabled. It is usually installed using
+/// [`set_rust_memory_functions`].
+///
+/// If the allocation fails, or if the size is too large,
+/// [`handle_alloc_error`] is called.
+///
+/// [`allocate_function`]: type.allocate_function.html
+/// [`handle_alloc_error`]: https://doc.rust-lang.org/alloc/alloc/fn.handle_alloc_error.html
+/// [`set_rust_memory_functions`]: fn.set_rust_memory_functions.html
+#[cfg(feature = "rust-alloc")]
+pub extern "C" fn rust_allocate(alloc_size: usize) -> *mut c_void {
+    let layout = rust_alloc_layout(alloc_size);
+    let ptr = unsafe { alloc::alloc::alloc(layout) };
+    if ptr.is_null() {
+        alloc::alloc::handle_alloc_error(layout);
+    }
+    ptr as *mut c_void
+}
+
+/// A [`reallocate_function`] that reallocates memory using Rust’s
+/// global allocator.
+///
+/// This function is only available when the experimental `rust-alloc`
+/// feature is enabled. It is usually installed using
+/// [`set_rust_memory_functions`].
+///
+/// If the allocation fails, or if the size is too large,
+/// [`handle_alloc_error`] is called.
+///
+/// # Safety
+///
+/// `ptr` must have been allocated by [`rust_allocate`] or
+/// [`rust_reallocate`] with a size of `old_size` bytes.
+///
+/// [`handle_alloc_error`]: https://doc.rust-lang.org/alloc/alloc/fn.handle_alloc_error.html
+/// [`reallocate_function`]: type.reallocate_function.html
+/// [`rust_allocate`]: fn.rust_allocate.html
+/// [`rust_reallocate`]: fn.rust_reallocate.html
+/// [`set_rust_memory_functions`]: fn.set_rust_memory_functions.html
+#[cfg(feature = "rust-alloc")]
+pub unsafe extern "C" fn rust_reallocate(
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+) -> *mut c_void {
+    let old_layout = rust_alloc_layout(old_size);
+    let new_layout = rust_alloc_layout(new_size);
+    let new_ptr = unsafe { alloc::alloc::realloc(ptr as *mut u8, old_layout, new_layout.size()) };
+    if new_ptr.is_null() {
+        alloc::alloc::handle_alloc_error(new_layout);
+    }
+    new_ptr as *mut c_void
+}
+
+/// A [`free_function`] that frees memory using Rust’s global
+/// allocator.
+///
+/// This function is only available when the experimental `rust-alloc`
+/// feature is enabled. It is usually installed using
+/// [`set_rust_memory_functions`].
+///
+/// # Safety
+///
+/// `ptr` must have been allocated by [`rust_allocate`] or
+/// [`rust_reallocate`] with a size of `size` bytes.
+///
+/// [`free_function`]: type.free_function.html
+/// [`rust_allocate`]: fn.rust_allocate.html
+/// [`rust_reallocate`]: fn.rust_reallocate.html
+/// [`set_rust_memory_functions`]: fn.set_rust_memory_functions.html
+#[cfg(feature = "rust-alloc")]
+pub unsafe extern "C" fn rust_free(ptr: *mut c_void, size: usize) {
+    unsafe { alloc::alloc::dealloc(ptr as *mut u8, rust_alloc_layout(size)) }
+}
+
+/// Sets the memory functions of [GMP], and therefore of MPFR and MPC,
+/// to [`rust_allocate`], [`rust_reallocate`] and [`rust_free`], so
+/// that all the memory of the C libraries is allocated using Rust’s
+/// global allocator.
+///
+/// This function is only available when the experimental `rust-alloc`
+/// feature is enabled.
+///
+/// # Safety
+///
+/// The memory functions are global, and memory allocated by the
+/// previous memory functions cannot be freed by the new ones. This
+/// function should be called once at startup, before any other thread
+/// is started and before any [GMP], MPFR or MPC object is initialized.
+/// In particular, no object or cache allocated before this call may be
+/// reallocated or freed after it, including the caches freed by
+/// `mpfr::free_cache`.
+///
+/// This function cannot be safe even when it is called once at startup,
+/// as it has no way to check that nothing was allocated before, for
+/// example by a static initializer or another library that uses [GMP].
+///
+/// # Examples
+///
+/// ```rust
+/// use core::mem::MaybeUninit;
+/// use gmp_mpfr_sys::gmp;
+/// unsafe {
+///     gmp::set_rust_memory_functions();
+///     let mut z = MaybeUninit::uninit();
+///     gmp::mpz_init(z.as_mut_ptr());
+///     let mut z = z.assume_init();
+///     gmp::mpz_ui_pow_ui(&mut z, 10, 100);
+///     assert_eq!(gmp::mpz_sizeinbase(&z, 10), 101);
+///     gmp::mpz_clear(&mut z);
+/// }
+/// ```
+///
+/// [GMP]: https://gmplib.org/
+/// [`rust_allocate`]: fn.rust_allocate.html
+/// [`rust_free`]: fn.rust_free.html
+/// [`rust_reallocate`]: fn.rust_reallocate.html
+#[cfg(feature = "rust-alloc")]
+#[inline]
+pub unsafe fn set_rust_memory_functions() {
+    unsafe {
+        set_memory_functions(Some(rust_allocate), Some(rust_reallocate), Some(rust_free));
+    }
+}
+
+#[cfg(feature = "accounting")]
+pub mod accounting;
+
+pub mod display;
+
 #[cfg(test)]
 mod tests {
     use crate::gmp;
@@ -0,0 +1,379 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+/*!
+Memory accounting and limits for the allocations of the C libraries.
+
+This module is only available when the experimental `accounting`
+feature is enabled.
+
+[`install`] wraps the current [GMP] memory functions, which are also
+used by MPFR and MPC, with functions that count the live bytes, the
+peak live bytes and the number of allocations, both for the whole
+process and for each thread. A limit can be set on the live bytes of
+the process using [`set_limit`]; when an allocation would exceed the
+limit, the [`Action`] set using [`set_action`] is taken.
+
+# Examples
+
+```rust
+use core::mem::MaybeUninit;
+use gmp_mpfr_sys::gmp::{self, accounting};
+unsafe {
+    accounting::install();
+}
+let (_, usage) = accounting::measure(|| unsafe {
+    let mut z = MaybeUninit::uninit();
+    gmp::mpz_init(z.as_mut_ptr());
+    let mut z = z.assume_init();
+    gmp::mpz_ui_pow_ui(&mut z, 10, 10_000);
+    gmp::mpz_clear(&mut z);
+});
+// 10^10000 needs more than 4000 bytes
+assert!(usage.peak > 4000);
+assert!(usage.count >= 1);
+assert_eq!(usage.live, 0);
+```
+
+[GMP]: https://gmplib.org/
+[`Action`]: enum.Action.html
+[`install`]: fn.install.html
+[`set_action`]: fn.set_action.html
+[`set_limit`]: fn.set_limit.html
+*/
+
+use crate::gmp::{self, allocate_function, free_function, reallocate_function};
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use libc::c_void;
+use std::thread::LocalKey;
+
+#[cfg(c_unwind)]
+mod unwind;
+
+/// The memory usage counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Usage {
+    /// The number of bytes that are currently allocated.
+    pub live: usize,
+    /// The largest number of bytes that were allocated at the same
+    /// time.
+    pub peak: usize,
+    /// The number of calls to the allocation function; reallocations
+    /// are not counted.
+    pub count: usize,
+}
+
+/// The action taken when an allocation would exceed the limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Prints a message to the standard error and aborts the process.
+    Abort,
+    /// Panics with a message, unwinding through the C libraries, so
+    /// that the panic can be caught using [`catch_unwind`].
+    ///
+    /// This needs the `C-unwind` ABI, which is supported by Rust 1.71
+    /// and later; with older compilers, the process is aborted as with
+    /// [`Abort`](#variant.Abort). Unwinding through the C libraries
+    /// leaks any temporary memory they allocated during the
+    /// interrupted operation, and can leave the variable being written
+    /// to invalid; see [`set_memory_functions_unwind`] for how to
+    /// recover without corrupting any variables.
+    ///
+    /// [`catch_unwind`]: https://doc.rust-lang.org/std/panic/fn.catch_unwind.html
+    /// [`set_memory_functions_unwind`]: ../fn.set_memory_functions_unwind.html
+    Panic,
+}
+
+impl Action {
+    /// Returns `true` if the action is supported by the compiler.
+    ///
+    /// This is always `true` for
+    /// [`Abort`](enum.Action.html#variant.Abort), and `true` for
+    /// [`Panic`](enum.Action.html#variant.Panic) if the `C-unwind` ABI
+    /// is supported.
+    #[inline]
+    pub fn is_supported(self) -> bool {
+        match self {
+            Action::Abort => true,
+            Action::Panic => cfg!(c_unwind),
+        }
+    }
+}
+
+static GLOBAL_LIVE: AtomicUsize = AtomicUsize::new(0);
+static GLOBAL_PEAK: AtomicUsize = AtomicUsize::new(0);
+static GLOBAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::max_value());
+static PANIC: AtomicBool = AtomicBool::new(false);
+
+std::thread_local! {
+    static THREAD_USAGE: Cell<Usage> = Cell::new(Usage::default());
+}
+
+// The memory functions that were set before install, which do the
+// actual allocations. They are only written by install.
+static mut PREV_ALLOCATE: allocate_function = None;
+static mut PREV_REALLOCATE: reallocate_function = None;
+static mut PREV_FREE: free_function = None;
+
+/// Wraps the current memory functions of [GMP], and therefore of MPFR
+/// and MPC, with functions that keep the memory usage counters and
+/// check the limit.
+///
+/// The allocations themselves are still done by the current memory
+/// functions, so this can be combined with for example
+/// [`set_rust_memory_functions`](../fn.set_rust_memory_functions.html)
+/// if that is called first. Memory allocated before the call is freed
+/// correctly, but is not counted, and freeing it does not reduce the
+/// counters below zero.
+///
+/// # Safety
+///
+/// The memory functions are global, so this function should be called
+/// once at startup, before any other thread is started. It must not be
+/// called again, and the memory functions must not be changed
+/// afterwards.
+///
+/// [GMP]: https://gmplib.org/
+pub unsafe fn install() {
+    let mut prev_allocate = None;
+    let mut prev_reallocate = None;
+    let mut prev_free = None;
+    unsafe {
+        gmp::get_memory_functions(&mut prev_allocate, &mut prev_reallocate, &mut prev_free);
+        PREV_ALLOCATE = prev_allocate;
+        PREV_REALLOCATE = prev_reallocate;
+        PREV_FREE = prev_free;
+        set_functions();
+    }
+}
+
+#[cfg(c_unwind)]
+unsafe fn set_functions() {
+    unsafe { unwind::set_functions() }
+}
+
+#[cfg(not(c_unwind))]
+unsafe fn set_functions() {
+    extern "C" fn c_allocate(alloc_size: usize) -> *mut c_void {
+        allocate(alloc_size)
+    }
+    unsafe extern "C" fn c_reallocate(
+        ptr: *mut c_void,
+        old_size: usize,
+        new_size: usize,
+    ) -> *mut c_void {
+        unsafe { reallocate(ptr, old_size, new_size) }
+    }
+    unsafe extern "C" fn c_free(ptr: *mut c_void, size: usize) {
+        unsafe { free(ptr, size) }
+    }
+    unsafe {
+        gmp::set_memory_functions(Some(c_allocate), Some(c_reallocate), Some(c_free));
+    }
+}
+
+/// Sets the limit on the number of bytes allocated by the process.
+///
+/// `None` removes the limit, which is the default.
+///
+/// The limit only affects new allocations; if more memory is already
+/// allocated, nothing happens until the next allocation.
+#[inline]
+pub fn set_limit(limit: Option<usize>) {
+    LIMIT.store(limit.unwrap_or(usize::max_value()), Ordering::Relaxed);
+}
+
+/// Returns the limit on the number of bytes allocated by the process.
+#[inline]
+pub fn limit() -> Option<usize> {
+    match LIMIT.load(Ordering::Relaxed) {
+        l if l == usize::max_value() => None,
+        l => Some(l),
+    }
+}
+
+/// Sets the action taken when an allocation would exceed the limit.
+///
+/// The default is [`Action::Abort`](enum.Action.html#variant.Abort).
+#[inline]
+pub fn set_action(action: Action) {
+    PANIC.store(action == Action::Panic, Ordering::Relaxed);
+}
+
+/// Returns the action taken when an allocation would exceed the limit.
+#[inline]
+pub fn action() -> Action {
+    if PANIC.load(Ordering::Relaxed) {
+        Action::Panic
+    } else {
+        Action::Abort
+    }
+}
+
+/// Returns the memory usage counters of the process.
+#[inline]
+pub fn global_usage() -> Usage {
+    Usage {
+        live: GLOBAL_LIVE.load(Ordering::Relaxed),
+        peak: GLOBAL_PEAK.load(Ordering::Relaxed),
+        count: GLOBAL_COUNT.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the memory usage counters of the current thread.
+///
+/// Memory allocated in one thread and freed in another is subtracted
+/// from the live bytes of the second thread, which are not reduced
+/// below zero.
+#[inline]
+pub fn thread_usage() -> Usage {
+    THREAD_USAGE.with(Cell::get)
+}
+
+/// Calls a closure and returns its return value together with the
+/// memory it used in the current thread.
+///
+/// In the returned [`Usage`](struct.Usage.html), `live` is the number of bytes allocated
+/// by the closure that are still allocated, `peak` is the largest
+/// number of bytes allocated by the closure at the same time, and
+/// `count` is the number of allocations made by the closure.
+///
+/// The counters of the thread are restored even if the closure panics,
+/// except that the allocations of the closure are kept.
+///
+/// See the [module documentation](index.html) for an example.
+pub fn measure<R, F: FnOnce() -> R>(f: F) -> (R, Usage) {
+    struct PeakRestorer(Usage);
+    impl Drop for PeakRestorer {
+        fn drop(&mut self) {
+            let start = self.0;
+            update(&THREAD_USAGE, |usage| {
+                if usage.peak < start.peak {
+                    usage.peak = start.peak;
+                }
+            });
+        }
+    }
+
+    let start = thread_usage();
+    update(&THREAD_USAGE, |usage| usage.peak = usage.live);
+    let restorer = PeakRestorer(start);
+    let ret = f();
+    let end = thread_usage();
+    drop(restorer);
+    let usage = Usage {
+        live: end.live.saturating_sub(start.live),
+        peak: end.peak - start.live,
+        count: end.count.wrapping_sub(start.count),
+    };
+    (ret, usage)
+}
+
+// Thread-local storage may already be destroyed when the thread exits,
+// in which case the thread counters are not updated.
+fn update<F: FnOnce(&mut Usage)>(key: &'static LocalKey<Cell<Usage>>, f: F) {
+    let _ = key.try_with(|cell| {
+        let mut usage = cell.get();
+        f(&mut usage);
+        cell.set(usage);
+    });
+}
+
+fn reserve(size: usize, count: bool) {
+    let live = GLOBAL_LIVE
+        .fetch_add(size, Ordering::Relaxed)
+        .wrapping_add(size);
+    let limit = LIMIT.load(Ordering::Relaxed);
+    if live > limit || live < size {
+        GLOBAL_LIVE.fetch_sub(size, Ordering::Relaxed);
+        exceeded(size, limit);
+    }
+    let mut peak = GLOBAL_PEAK.load(Ordering::Relaxed);
+    while live > peak {
+        match GLOBAL_PEAK.compare_exchange_weak(peak, live, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(p) => peak = p,
+        }
+    }
+    if count {
+        GLOBAL_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    update(&THREAD_USAGE, |usage| {
+        usage.live = usage.live.wrapping_add(size);
+        if usage.live > usage.peak {
+            usage.peak = usage.live;
+        }
+        if count {
+            usage.count = usage.count.wrapping_add(1);
+        }
+    });
+}
+
+fn release(size: usize) {
+    let mut live = GLOBAL_LIVE.load(Ordering::Relaxed);
+    loop {
+        let new = live.saturating_sub(size);
+        match GLOBAL_LIVE.compare_exchange_weak(live, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(l) => live = l,
+        }
+    }
+    update(&THREAD_USAGE, |usage| {
+        usage.live = usage.live.saturating_sub(size);
+    });
+}
+
+#[cold]
+fn exceeded(size: usize, limit: usize) -> ! {
+    if PANIC.load(Ordering::Relaxed) && cfg!(c_unwind) {
+        panic!(
+            "GMP allocation of {} bytes exceeds the memory limit of {} bytes",
+            size, limit
+        );
+    }
+    std::eprintln!(
+        "GMP allocation of {} bytes exceeds the memory limit of {} bytes",
+        size,
+        limit
+    );
+    std::process::abort();
+}
+
+fn allocate(alloc_size: usize) -> *mut c_void {
+    reserve(alloc_size, true);
+    let prev = unsafe { PREV_ALLOCATE }.expect("memory functions not installed");
+    prev(alloc_size)
+}
+
+unsafe fn reallocate(ptr: *mut c_void, old_size: usize, new_size: usize) -> *mut c_void {
+    if new_size > old_size {
+        reserve(new_size - old_size, false);
+    } else {
+        release(old_size - new_size);
+    }
+    let prev = unsafe { PREV_REALLOCATE }.expect("memory functions not installed");
+    unsafe { prev(ptr, old_size, new_size) }
+}
+
+unsafe fn free(ptr: *mut c_void, size: usize) {
+    release(size);
+    let prev = unsafe { PREV_FREE }.expect("memory functions not installed");
+    unsafe { prev(ptr, size) }
+}
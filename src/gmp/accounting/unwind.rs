@@ -0,0 +1,43 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+// This module is only compiled if the C-unwind ABI is supported, as
+// older compilers cannot even parse it.
+
+use crate::gmp;
+use libc::c_void;
+
+unsafe extern "C-unwind" fn c_allocate(alloc_size: usize) -> *mut c_void {
+    super::allocate(alloc_size)
+}
+
+unsafe extern "C-unwind" fn c_reallocate(
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+) -> *mut c_void {
+    unsafe { super::reallocate(ptr, old_size, new_size) }
+}
+
+unsafe extern "C-unwind" fn c_free(ptr: *mut c_void, size: usize) {
+    unsafe { super::free(ptr, size) }
+}
+
+pub(super) unsafe fn set_functions() {
+    unsafe {
+        gmp::set_memory_functions_unwind(Some(c_allocate), Some(c_reallocate), Some(c_free));
+    }
+}
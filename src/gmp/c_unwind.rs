@@ -0,0 +1,353 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+// This module is only compiled if the C-unwind ABI is supported, as
+// older compilers cannot even parse it. Its items are re-exported by
+// the gmp module.
+
+use libc::c_void;
+
+/// An allocation function that can unwind.
+///
+/// This is like [`allocate_function`](type.allocate_function.html),
+/// but the function can panic, for example when an allocation fails.
+/// It is only available if the compiler supports the `C-unwind` ABI,
+/// which is the case for Rust 1.71 and later.
+///
+/// See [`set_memory_functions_unwind`](fn.set_memory_functions_unwind.html).
+pub type allocate_function_unwind = unsafe extern "C-unwind" fn(alloc_size: usize) -> *mut c_void;
+/// A reallocation function that can unwind.
+///
+/// This is like
+/// [`reallocate_function`](type.reallocate_function.html), but the
+/// function can panic, for example when an allocation fails. It is
+/// only available if the compiler supports the `C-unwind` ABI, which
+/// is the case for Rust 1.71 and later.
+///
+/// See [`set_memory_functions_unwind`](fn.set_memory_functions_unwind.html).
+pub type reallocate_function_unwind =
+    unsafe extern "C-unwind" fn(ptr: *mut c_void, old_size: usize, new_size: usize) -> *mut c_void;
+/// A free function that can unwind.
+///
+/// This is like [`free_function`](type.free_function.html), but the
+/// function can panic. It is only available if the compiler supports
+/// the `C-unwind` ABI, which is the case for Rust 1.71 and later.
+///
+/// See [`set_memory_functions_unwind`](fn.set_memory_functions_unwind.html).
+pub type free_function_unwind = unsafe extern "C-unwind" fn(ptr: *mut c_void, size: usize);
+
+// These declare the same C functions as set_memory_functions and
+// get_memory_functions, but with function pointers that can unwind.
+// The functions that can call them are declared using extern_c! and
+// defined using extern_c_fn!, which use the C-unwind ABI when this
+// module is compiled, so that the panic can unwind through them.
+#[allow(clashing_extern_declarations)]
+extern "C-unwind" {
+    /// Sets memory functions that can unwind.
+    ///
+    /// This is like
+    /// [`set_memory_functions`](fn.set_memory_functions.html), but
+    /// the memory functions can panic, and the panic unwinds through
+    /// the [GMP], MPFR or MPC function that called them, so that it
+    /// can be caught using [`catch_unwind`]. It is only available if
+    /// the compiler supports the `C-unwind` ABI, which is the case for
+    /// Rust 1.71 and later.
+    ///
+    /// When the `C-unwind` ABI is supported, the functions of the
+    /// [`gmp`](index.html), [`mpfr`](../mpfr/index.html) and
+    /// [`mpc`](../mpc/index.html) modules that can call the memory
+    /// functions are declared with it, so that unwinding through them
+    /// is defined.
+    ///
+    /// A `None` argument sets the default function.
+    ///
+    /// # Unwinding through the C libraries
+    ///
+    /// The GMP manual states that there is currently no defined way
+    /// for allocation functions to recover from an error, and that a
+    /// `longjmp` will have undefined results; unwinding is no
+    /// different. In practice, the C libraries do not clean up
+    /// anything when unwinding, so that:
+    ///
+    ///   * Any temporary memory allocated by the interrupted function
+    ///     is leaked.
+    ///   * The variable the interrupted function was writing to can be
+    ///     left invalid. For example [`mpz_mul`](fn.mpz_mul.html)
+    ///     can free the old block of the result and update its
+    ///     allocated size before allocating the new block, so that
+    ///     clearing the variable afterwards would free memory that is
+    ///     not allocated. The variable must not be used or cleared
+    ///     again, and the memory it owns is leaked.
+    ///   * Variables that are only read by the interrupted function
+    ///     are not modified.
+    ///
+    /// The C libraries must also have been built with unwind tables,
+    /// which is the default on most targets.
+    ///
+    /// So to recover from an allocation failure without corrupting
+    /// any variables, the result should be written to a new temporary
+    /// variable that is not also an operand, and only stored in the
+    /// destination if the function succeeds. If the function panics,
+    /// the temporary variable is abandoned without clearing it. A
+    /// temporary initialized using [`mpz_init`](fn.mpz_init.html) does
+    /// not own any memory, so only the memory allocated during the
+    /// interrupted function is leaked.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use core::mem::MaybeUninit;
+    /// use gmp_mpfr_sys::gmp;
+    /// use libc::c_void;
+    /// use std::panic::{self, AssertUnwindSafe};
+    ///
+    /// const MAX_SIZE: usize = 1 << 20;
+    /// static mut PREV_ALLOCATE: gmp::allocate_function = None;
+    /// static mut PREV_REALLOCATE: gmp::reallocate_function = None;
+    ///
+    /// unsafe extern "C-unwind" fn allocate(alloc_size: usize) -> *mut c_void {
+    ///     if alloc_size > MAX_SIZE {
+    ///         panic!("allocation too large");
+    ///     }
+    ///     unsafe { PREV_ALLOCATE.unwrap()(alloc_size) }
+    /// }
+    /// unsafe extern "C-unwind" fn reallocate(
+    ///     ptr: *mut c_void,
+    ///     old_size: usize,
+    ///     new_size: usize,
+    /// ) -> *mut c_void {
+    ///     if new_size > MAX_SIZE {
+    ///         panic!("allocation too large");
+    ///     }
+    ///     unsafe { PREV_REALLOCATE.unwrap()(ptr, old_size, new_size) }
+    /// }
+    ///
+    /// // Computes dst = a × b, leaving dst unchanged on failure.
+    /// unsafe fn try_mul(
+    ///     dst: *mut gmp::mpz_t,
+    ///     a: *const gmp::mpz_t,
+    ///     b: *const gmp::mpz_t,
+    /// ) -> bool {
+    ///     let mut tmp = MaybeUninit::uninit();
+    ///     unsafe { gmp::mpz_init(tmp.as_mut_ptr()) };
+    ///     let mut tmp = unsafe { tmp.assume_init() };
+    ///     let tmp_ptr: *mut gmp::mpz_t = &mut tmp;
+    ///     let res = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+    ///         gmp::mpz_mul(tmp_ptr, a, b);
+    ///     }));
+    ///     if res.is_err() {
+    ///         // tmp can be invalid, so it is abandoned
+    ///         return false;
+    ///     }
+    ///     unsafe {
+    ///         gmp::mpz_swap(dst, &mut tmp);
+    ///         gmp::mpz_clear(&mut tmp);
+    ///     }
+    ///     true
+    /// }
+    ///
+    /// unsafe {
+    ///     let mut prev_allocate = None;
+    ///     let mut prev_reallocate = None;
+    ///     let mut prev_free = None;
+    ///     gmp::get_memory_functions(&mut prev_allocate, &mut prev_reallocate, &mut prev_free);
+    ///     PREV_ALLOCATE = prev_allocate;
+    ///     PREV_REALLOCATE = prev_reallocate;
+    ///     gmp::set_memory_functions_unwind(Some(allocate), Some(reallocate), None);
+    ///
+    ///     let mut a = MaybeUninit::uninit();
+    ///     gmp::mpz_init(a.as_mut_ptr());
+    ///     let mut a = a.assume_init();
+    ///     let mut dst = MaybeUninit::uninit();
+    ///     gmp::mpz_init_set_ui(dst.as_mut_ptr(), 7);
+    ///     let mut dst = dst.assume_init();
+    ///
+    ///     // a is 2^5_000_000, which needs 625_001 bytes
+    ///     gmp::mpz_setbit(&mut a, 5_000_000);
+    ///     // a × a would need more than MAX_SIZE bytes
+    ///     let hook = panic::take_hook();
+    ///     panic::set_hook(Box::new(|_| {}));
+    ///     assert!(!try_mul(&mut dst, &a, &a));
+    ///     panic::set_hook(hook);
+    ///     assert_eq!(gmp::mpz_get_ui(&dst), 7);
+    ///     assert_eq!(gmp::mpz_sizeinbase(&a, 2), 5_000_001);
+    ///     assert_eq!(gmp::mpz_scan1(&a, 0), 5_000_000);
+    ///     // smaller products still work
+    ///     gmp::mpz_set_ui(&mut a, 1000);
+    ///     assert!(try_mul(&mut dst, &a, &a));
+    ///     assert_eq!(gmp::mpz_get_ui(&dst), 1_000_000);
+    ///
+    ///     gmp::mpz_clear(&mut a);
+    ///     gmp::mpz_clear(&mut dst);
+    ///     gmp::set_memory_functions(prev_allocate, prev_reallocate, prev_free);
+    /// }
+    /// ```
+    ///
+    /// [GMP]: https://gmplib.org/
+    /// [`catch_unwind`]: https://doc.rust-lang.org/std/panic/fn.catch_unwind.html
+    #[link_name = "__gmp_set_memory_functions"]
+    pub fn set_memory_functions_unwind(
+        alloc_func_ptr: Option<allocate_function_unwind>,
+        realloc_func_ptr: Option<reallocate_function_unwind>,
+        free_func_ptr: Option<free_function_unwind>,
+    );
+    /// Gets the memory functions as functions that can unwind.
+    ///
+    /// This is like
+    /// [`get_memory_functions`](fn.get_memory_functions.html), but the
+    /// functions are returned as functions that can unwind, which is
+    /// correct whether or not they were set using
+    /// [`set_memory_functions_unwind`](fn.set_memory_functions_unwind.html).
+    /// It is only available if the compiler supports the `C-unwind`
+    /// ABI, which is the case for Rust 1.71 and later.
+    #[link_name = "__gmp_get_memory_functions"]
+    pub fn get_memory_functions_unwind(
+        alloc_func_ptr: *mut Option<allocate_function_unwind>,
+        realloc_func_ptr: *mut Option<reallocate_function_unwind>,
+        free_func_ptr: *mut Option<free_function_unwind>,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use crate::gmp::{self, allocate_function_unwind, reallocate_function_unwind};
+    use core::{cell::Cell, mem::MaybeUninit};
+    use libc::c_void;
+    use std::{
+        boxed::Box,
+        panic::{self, AssertUnwindSafe},
+        sync::Once,
+    };
+
+    // Only allocations in the thread running the test are limited, as
+    // the other tests run in parallel.
+    std::thread_local! {
+        static MAX_SIZE: Cell<usize> = Cell::new(!0);
+    }
+    static INSTALL: Once = Once::new();
+    static mut PREV_ALLOCATE: Option<allocate_function_unwind> = None;
+    static mut PREV_REALLOCATE: Option<reallocate_function_unwind> = None;
+
+    fn check_size(size: usize) {
+        if size > MAX_SIZE.with(Cell::get) {
+            panic!("allocation too large");
+        }
+    }
+    unsafe extern "C-unwind" fn allocate(alloc_size: usize) -> *mut c_void {
+        check_size(alloc_size);
+        unsafe { PREV_ALLOCATE.unwrap()(alloc_size) }
+    }
+    unsafe extern "C-unwind" fn reallocate(
+        ptr: *mut c_void,
+        old_size: usize,
+        new_size: usize,
+    ) -> *mut c_void {
+        check_size(new_size);
+        unsafe { PREV_REALLOCATE.unwrap()(ptr, old_size, new_size) }
+    }
+
+    fn install() {
+        INSTALL.call_once(|| unsafe {
+            let mut prev_allocate = None;
+            let mut prev_reallocate = None;
+            let mut prev_free = None;
+            gmp::get_memory_functions_unwind(
+                &mut prev_allocate,
+                &mut prev_reallocate,
+                &mut prev_free,
+            );
+            PREV_ALLOCATE = prev_allocate;
+            PREV_REALLOCATE = prev_reallocate;
+            gmp::set_memory_functions_unwind(Some(allocate), Some(reallocate), prev_free);
+        });
+    }
+
+    // Returns true if f panicked, which is only caught correctly if the
+    // panic unwinds through the C libraries.
+    fn panics_with_limit<F: FnOnce()>(max_size: usize, f: F) -> bool {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        MAX_SIZE.with(|m| m.set(max_size));
+        let res = panic::catch_unwind(AssertUnwindSafe(f));
+        MAX_SIZE.with(|m| m.set(!0));
+        panic::set_hook(hook);
+        res.is_err()
+    }
+
+    #[test]
+    fn check_unwind_recovery() {
+        install();
+        unsafe {
+            let mut a = MaybeUninit::uninit();
+            gmp::mpz_init(a.as_mut_ptr());
+            let mut a = a.assume_init();
+            // a is 2^1_000_000, which needs 125_001 bytes
+            gmp::mpz_setbit(&mut a, 1_000_000);
+
+            // a × a needs 250_001 bytes
+            let mut tmp = MaybeUninit::uninit();
+            gmp::mpz_init(tmp.as_mut_ptr());
+            let tmp_ptr: *mut gmp::mpz_t = tmp.as_mut_ptr();
+            assert!(panics_with_limit(200_000, || gmp::mpz_mul(tmp_ptr, &a, &a)));
+
+            // mpz_neg is implemented in Rust and calls mpz_set
+            let mut tmp = MaybeUninit::uninit();
+            gmp::mpz_init(tmp.as_mut_ptr());
+            let tmp_ptr: *mut gmp::mpz_t = tmp.as_mut_ptr();
+            assert!(panics_with_limit(100_000, || gmp::mpz_neg(tmp_ptr, &a)));
+
+            // the operand is not modified and allocations still work
+            assert_eq!(gmp::mpz_sizeinbase(&a, 2), 1_000_001);
+            assert_eq!(gmp::mpz_scan1(&a, 0), 1_000_000);
+            let mut b = MaybeUninit::uninit();
+            gmp::mpz_init(b.as_mut_ptr());
+            let mut b = b.assume_init();
+            gmp::mpz_mul(&mut b, &a, &a);
+            assert_eq!(gmp::mpz_scan1(&b, 0), 2_000_000);
+            gmp::mpz_clear(&mut a);
+            gmp::mpz_clear(&mut b);
+        }
+    }
+
+    #[cfg(feature = "mpfr")]
+    #[test]
+    fn check_unwind_recovery_mpfr() {
+        use crate::mpfr::{self, rnd_t};
+        install();
+        unsafe {
+            // x and the temporary need 125_000 bytes each
+            let mut x = MaybeUninit::uninit();
+            mpfr::init2(x.as_mut_ptr(), 1_000_000);
+            let mut x = x.assume_init();
+            mpfr::const_pi(&mut x, rnd_t::RNDN);
+            let mut tmp = MaybeUninit::uninit();
+            mpfr::init2(tmp.as_mut_ptr(), 1_000_000);
+            let tmp_ptr: *mut mpfr::mpfr_t = tmp.as_mut_ptr();
+
+            // the full product needs a temporary of 250_000 bytes
+            assert!(panics_with_limit(200_000, || {
+                mpfr::mul(tmp_ptr, &x, &x, rnd_t::RNDN);
+            }));
+
+            assert!(mpfr::cmp_d(&x, 3.0) > 0 && mpfr::cmp_d(&x, 4.0) < 0);
+            mpfr::mul(&mut x, &x, &x, rnd_t::RNDN);
+            assert!(mpfr::cmp_d(&x, 9.0) > 0 && mpfr::cmp_d(&x, 16.0) < 0);
+            mpfr::clear(&mut x);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+/*!
+Formatting of [GMP] integers using [`core::fmt`].
+
+[`Mpz`] wraps a reference to an [`mpz_t`] and implements [`Display`],
+[`Binary`], [`Octal`], [`LowerHex`] and [`UpperHex`], so that the
+integer can be formatted without converting it to a string first.
+
+The width, fill, alignment, sign (`+`), alternate (`#`) and zero
+padding (`0`) flags are supported as for primitive integers. Negative
+numbers are written as a minus sign followed by the absolute value in
+all bases. As for primitive integers, the precision is ignored.
+
+Numbers in bases 2, 8 and 16 are written directly from the limbs.
+Decimal numbers are converted using [`mpn_get_str`]; a buffer on the
+stack is used for small numbers, and memory is only allocated for
+large numbers, using the [GMP] memory functions.
+
+# Examples
+
+```rust
+use core::mem::MaybeUninit;
+use gmp_mpfr_sys::gmp::{self, display::Mpz};
+unsafe {
+    let mut z = MaybeUninit::uninit();
+    gmp::mpz_init(z.as_mut_ptr());
+    let mut z = z.assume_init();
+    gmp::mpz_set_si(&mut z, -255);
+    assert_eq!(format!("{}", Mpz(&z)), "-255");
+    assert_eq!(format!("{:>+6}", Mpz(&z)), "  -255");
+    assert_eq!(format!("{:#x}", Mpz(&z)), "-0xff");
+    assert_eq!(format!("{:#010X}", Mpz(&z)), "-0x00000FF");
+    gmp::mpz_clear(&mut z);
+}
+```
+
+[GMP]: https://gmplib.org/
+[`Binary`]: https://doc.rust-lang.org/core/fmt/trait.Binary.html
+[`Display`]: https://doc.rust-lang.org/core/fmt/trait.Display.html
+[`LowerHex`]: https://doc.rust-lang.org/core/fmt/trait.LowerHex.html
+[`Mpz`]: struct.Mpz.html
+[`Octal`]: https://doc.rust-lang.org/core/fmt/trait.Octal.html
+[`UpperHex`]: https://doc.rust-lang.org/core/fmt/trait.UpperHex.html
+[`core::fmt`]: https://doc.rust-lang.org/core/fmt/index.html
+[`mpn_get_str`]: ../fn.mpn_get_str.html
+[`mpz_t`]: ../struct.mpz_t.html
+*/
+
+use crate::gmp::{self, limb_t, mpz_t};
+use core::{
+    fmt::{
+        Alignment, Binary, Display, Formatter, LowerHex, Octal, Result as FmtResult, UpperHex,
+        Write,
+    },
+    mem,
+    ptr::{self, NonNull},
+    slice, str,
+};
+
+/// Formats an [`mpz_t`](../struct.mpz_t.html).
+///
+/// See the [module documentation](index.html) for an example.
+#[derive(Clone, Copy, Debug)]
+pub struct Mpz<'a>(pub &'a mpz_t);
+
+impl Display for Mpz<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_mpz(self.0, f, 10, false, "")
+    }
+}
+
+impl Binary for Mpz<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_mpz(self.0, f, 2, false, "0b")
+    }
+}
+
+impl Octal for Mpz<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_mpz(self.0, f, 8, false, "0o")
+    }
+}
+
+impl LowerHex for Mpz<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_mpz(self.0, f, 16, false, "0x")
+    }
+}
+
+impl UpperHex for Mpz<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_mpz(self.0, f, 16, true, "0x")
+    }
+}
+
+// The number of limbs that are converted to decimal on the stack.
+const STACK_LIMBS: usize = 8;
+// Enough for STACK_LIMBS 64-bit limbs in decimal plus one.
+const STACK_DIGITS: usize = 160;
+
+const ZEROS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+fn fmt_mpz(z: &mpz_t, f: &mut Formatter<'_>, base: u32, upper: bool, prefix: &str) -> FmtResult {
+    let neg = z.size < 0;
+    let n = if neg {
+        -(z.size as isize)
+    } else {
+        z.size as isize
+    } as usize;
+    if n == 0 {
+        return write_padded(f, Some(false), prefix, 1, true, |f| f.write_str("0"));
+    }
+    let limbs = unsafe { slice::from_raw_parts(z.d.as_ptr() as *const limb_t, n) };
+    let numb_bits = gmp::NUMB_BITS as usize;
+    if base == 10 {
+        return fmt_decimal(limbs, neg, f);
+    }
+    let digit_bits = base.trailing_zeros() as usize;
+    let bits = n * numb_bits - (limbs[n - 1].leading_zeros() as usize - gmp::NAIL_BITS as usize);
+    let num_digits = (bits - 1) / digit_bits + 1;
+    write_padded(f, Some(neg), prefix, num_digits, true, |f| {
+        let mut buf = [0u8; 64];
+        let mut len = 0;
+        for i in (0..num_digits).rev() {
+            let pos = i * digit_bits;
+            let (index, offset) = (pos / numb_bits, pos % numb_bits);
+            let mut val = limbs[index] >> offset;
+            if offset + digit_bits > numb_bits && index + 1 < n {
+                val |= limbs[index + 1] << (numb_bits - offset);
+            }
+            buf[len] = digit_char((val as u32) & (base - 1), upper);
+            len += 1;
+            if len == buf.len() || i == 0 {
+                f.write_str(unsafe { str::from_utf8_unchecked(&buf[..len]) })?;
+                len = 0;
+            }
+        }
+        Ok(())
+    })
+}
+
+fn fmt_decimal(limbs: &[limb_t], neg: bool, f: &mut Formatter<'_>) -> FmtResult {
+    // mpn_get_str clobbers its input, so a copy is converted.
+    let n = limbs.len();
+    let mut stack_limbs = [0 as limb_t; STACK_LIMBS];
+    let mut heap_limbs;
+    let copy = if n <= STACK_LIMBS {
+        &mut stack_limbs[..n]
+    } else {
+        heap_limbs = GmpBuf::new(mem::size_of_val(limbs));
+        unsafe { slice::from_raw_parts_mut(heap_limbs.as_mut_ptr() as *mut limb_t, n) }
+    };
+    copy.copy_from_slice(limbs);
+    // There can be leading zeros, so there must be space for the
+    // largest number with n limbs; 1234 / 4096 > log10(2).
+    let cap = n * gmp::NUMB_BITS as usize / 4096 * 1234
+        + (n * gmp::NUMB_BITS as usize % 4096) * 1234 / 4096
+        + 2;
+    let mut stack_digits = [0u8; STACK_DIGITS];
+    let mut heap_digits;
+    let digits = if cap <= STACK_DIGITS {
+        &mut stack_digits[..cap]
+    } else {
+        heap_digits = GmpBuf::new(cap);
+        unsafe { slice::from_raw_parts_mut(heap_digits.as_mut_ptr(), cap) }
+    };
+    let len = unsafe { gmp::mpn_get_str(digits.as_mut_ptr(), 10, copy.as_mut_ptr(), n as _) };
+    let digits = &mut digits[..len];
+    let skip = digits.iter().position(|&d| d != 0).unwrap_or(len - 1);
+    let digits = &mut digits[skip..];
+    for d in digits.iter_mut() {
+        *d += b'0';
+    }
+    let s = unsafe { str::from_utf8_unchecked(digits) };
+    write_padded(f, Some(neg), "", s.len(), true, |f| f.write_str(s))
+}
+
+fn digit_char(digit: u32, upper: bool) -> u8 {
+    match digit {
+        0..=9 => b'0' + digit as u8,
+        _ if upper => b'A' + (digit - 10) as u8,
+        _ => b'a' + (digit - 10) as u8,
+    }
+}
+
+// Writes a number with len ASCII characters after its sign and prefix,
+// padding it according to the width, fill, alignment and flags of f.
+// The sign is None for NaN, which has no sign. Only numeric values can
+// be padded with zeros.
+pub(crate) fn write_padded<F>(
+    f: &mut Formatter<'_>,
+    neg: Option<bool>,
+    prefix: &str,
+    len: usize,
+    numeric: bool,
+    write_digits: F,
+) -> FmtResult
+where
+    F: FnOnce(&mut Formatter<'_>) -> FmtResult,
+{
+    let sign = match neg {
+        Some(true) => "-",
+        Some(false) if f.sign_plus() => "+",
+        _ => "",
+    };
+    let prefix = if f.alternate() { prefix } else { "" };
+    let total = sign.len() + prefix.len() + len;
+    let width = f.width().unwrap_or(0);
+    if total >= width {
+        f.write_str(sign)?;
+        f.write_str(prefix)?;
+        return write_digits(f);
+    }
+    let pad = width - total;
+    if numeric && f.sign_aware_zero_pad() {
+        f.write_str(sign)?;
+        f.write_str(prefix)?;
+        write_zeros(f, pad)?;
+        return write_digits(f);
+    }
+    let (before, after) = match f.align() {
+        Some(Alignment::Left) => (0, pad),
+        Some(Alignment::Center) => (pad / 2, pad - pad / 2),
+        Some(Alignment::Right) | None => (pad, 0),
+    };
+    let fill = f.fill();
+    for _ in 0..before {
+        f.write_char(fill)?;
+    }
+    f.write_str(sign)?;
+    f.write_str(prefix)?;
+    write_digits(f)?;
+    for _ in 0..after {
+        f.write_char(fill)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_zeros(f: &mut Formatter<'_>, mut count: usize) -> FmtResult {
+    while count > 0 {
+        let chunk = if count < ZEROS.len() {
+            count
+        } else {
+            ZEROS.len()
+        };
+        f.write_str(&ZEROS[..chunk])?;
+        count -= chunk;
+    }
+    Ok(())
+}
+
+// A buffer allocated and freed using the GMP memory functions, for
+// numbers that are too large for the stack.
+pub(crate) struct GmpBuf {
+    ptr: NonNull<u8>,
+    size: usize,
+}
+
+impl GmpBuf {
+    pub(crate) fn new(size: usize) -> GmpBuf {
+        let mut alloc = None;
+        unsafe {
+            gmp::get_memory_functions(&mut alloc, ptr::null_mut(), ptr::null_mut());
+        }
+        let alloc = alloc.expect("no allocation function");
+        let ptr = NonNull::new(alloc(size) as *mut u8).expect("allocation failed");
+        GmpBuf { ptr, size }
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+}
+
+impl Drop for GmpBuf {
+    fn drop(&mut self) {
+        let mut free = None;
+        unsafe {
+            gmp::get_memory_functions(ptr::null_mut(), ptr::null_mut(), &mut free);
+            if let Some(free) = free {
+                free(self.ptr.as_ptr() as *mut _, self.size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gmp::{self, display::Mpz};
+    use core::{
+        fmt::{Result as FmtResult, Write},
+        mem::MaybeUninit,
+        str,
+    };
+
+    struct Buf {
+        bytes: [u8; 512],
+        len: usize,
+    }
+
+    impl Write for Buf {
+        fn write_str(&mut self, s: &str) -> FmtResult {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    fn check(expected: &str, args: core::fmt::Arguments<'_>) {
+        let mut buf = Buf {
+            bytes: [0; 512],
+            len: 0,
+        };
+        buf.write_fmt(args).unwrap();
+        assert_eq!(str::from_utf8(&buf.bytes[..buf.len]).unwrap(), expected);
+    }
+
+    #[test]
+    fn check_mpz() {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init(z.as_mut_ptr());
+            let mut z = z.assume_init();
+            check("0", format_args!("{}", Mpz(&z)));
+            check("+0x0", format_args!("{:+#x}", Mpz(&z)));
+            gmp::mpz_set_si(&mut z, -10);
+            check("-1010", format_args!("{:b}", Mpz(&z)));
+            check("-0o12", format_args!("{:#o}", Mpz(&z)));
+            check("*-10**", format_args!("{:*^6}", Mpz(&z)));
+            check("-0010", format_args!("{:05}", Mpz(&z)));
+            // 2^200 is more than STACK_LIMBS on 32-bit limbs
+            gmp::mpz_ui_pow_ui(&mut z, 2, 200);
+            check(
+                "1606938044258990275541962092341162602522202993782792835301376",
+                format_args!("{}", Mpz(&z)),
+            );
+            check(
+                "100000000000000000000000000000000000000000000000000",
+                format_args!("{:x}", Mpz(&z)),
+            );
+            // 3^1000 needs a heap buffer
+            gmp::mpz_ui_pow_ui(&mut z, 3, 1000);
+            let mut buf = Buf {
+                bytes: [0; 512],
+                len: 0,
+            };
+            write!(buf, "{}", Mpz(&z)).unwrap();
+            assert_eq!(buf.len, 478);
+            assert_eq!(&buf.bytes[..10], b"1322070819");
+            assert_eq!(&buf.bytes[468..478], b"2855220001");
+            gmp::mpz_clear(&mut z);
+        }
+    }
+}
@@ -133,7 +133,7 @@ minor version bump.
 
 Experimental features may also not work on all platforms.
 
-There are three experimental feature:
+There are nine experimental features:
 
  1. `use-system-libs`, disabled by default. Using this feature, the
     system libraries for [GMP], and [MPFR] and [MPC] if enabled, will
@@ -141,7 +141,11 @@ There are three experimental feature:
     of the system libraries must be equal to those provided by the
     crate, and the minor versions of the system libraries must be
     greater or equal to those provided by the crate. There are no
-    restriction on the patch version.
+    restriction on the patch version. If `pkg-config` is available,
+    the *gmp.pc*, *mpfr.pc* and *mpc.pc* files are used to find the
+    include and library directories, so that system libraries
+    installed in a non-default prefix can be used; otherwise the
+    libraries are expected in the default compiler search paths.
  2. `force-cross`, disabled by default. Without this feature, the
     build will fail if cross compilation is detected, because cross
     compilation is not tested or supported and can lead to silent
@@ -155,6 +159,52 @@ There are three experimental feature:
     GMP sources are miscompiled is unfortunately quite high. And if
     they indeed are miscompiled, the tests are very likely to trigger
     the compiler-introduced bug.
+ 4. `c-shared`, disabled by default. Using this feature, the C
+    libraries are built as shared libraries instead of static
+    libraries, and are linked dynamically. The shared libraries are
+    placed in the `DEP_GMP_LIB_DIR` directory; `cargo run` and `cargo
+    test` find them there, but other executables need the directory
+    in their library search path, or a copy of the libraries. Shared
+    libraries are cached separately from static libraries.
+ 5. `c-sanitize-address`, `c-sanitize-memory` and
+    `c-sanitize-undefined`, disabled by default. Using these features,
+    the C libraries are built for AddressSanitizer, MemorySanitizer or
+    UndefinedBehaviorSanitizer, so that they can be used with Rust
+    code built with for example `-Zsanitizer=address`. [GMP] is
+    configured with `--disable-assembly` instead of `--enable-fat`,
+    and the C flags are set to those from the `CFLAGS` environment
+    variable, or `-O1` if it is not set, followed by `-g
+    -fno-omit-frame-pointer` and the matching `-fsanitize` flags.
+    MemorySanitizer requires Clang, for example with `CC=clang`, and
+    cannot be combined with AddressSanitizer. These features cannot be
+    used with `GMP_MPFR_SYS_CPU` or for wasm32 targets, and the
+    libraries are cached separately.
+ 6. `c-assert`, disabled by default. Using this feature, [GMP] is
+    configured with `--enable-assert` and [MPFR] with
+    `--enable-assert=full`, so that the C libraries check their
+    internal invariants, for example that a [`gmp::mpz_t`] is
+    normalized or that an exponent is in range. [MPC] always checks
+    its assertions. A failed assertion prints a message such as
+    “GNU MP assertion failed” to the standard error and aborts the
+    process, so that `cargo test` reports the test binary as killed by
+    `SIGABRT` after the message. The checks make the libraries slower,
+    and the libraries are cached separately.
+ 7. `rust-alloc`, disabled by default. Using this feature,
+    [`gmp::set_rust_memory_functions`] can be used to allocate the
+    memory of the C libraries using Rust’s global allocator, so that
+    for example a tracking allocator also sees the memory used by
+    [GMP], [MPFR] and [MPC]. The memory functions are global, so they
+    should be set once at startup before any object is initialized.
+ 8. `accounting`, disabled by default. Using this feature,
+    [`gmp::accounting`] can count the memory allocated by the C
+    libraries for the process and for each thread, measure the memory
+    used by a closure, and limit the memory of the process, either
+    aborting the process or panicking when the limit would be exceeded.
+    Panicking needs Rust 1.71 or later. This feature requires the
+    standard library.
+ 9. `owned`, disabled by default. Using this feature, the [`owned`]
+    module provides types such as `owned::Mpz` that own a C value,
+    initializing it on construction and clearing it when dropped.
 
 ## Metadata
 
@@ -164,7 +214,8 @@ The gmp-mpfr-sys crate passes some metadata to its dependents:
     32 or 64.
  2. `DEP_GMP_OUT_DIR` contains the path of a directory that contains
     two subdirectories: the first subdirectory is named *lib* and
-    contains the generated library (*.a*) files, and the second
+    contains the generated library (*.a*, or shared library with the
+    `c-shared` feature) files, and the second
     subdirectory is named *include* and contains the corresponding
     header (*.h*) files.
  3. `DEP_GMP_LIB_DIR` contains the path of the *lib* subdirectory of
@@ -231,15 +282,83 @@ Then, to build a crate with a dependency on this crate:
 
 ## Cross compilation
 
-While some cross compilation is possible, it is not tested
-automatically, and may not work. Merge requests that improve cross
-compilation are accepted.
+Cross compilation is tested automatically only from x86_64 to i686
+and from x86_64 GNU/Linux to aarch64 GNU/Linux, and may not work for
+other targets. Merge requests that improve cross compilation are
+accepted.
 
 The experimental feature `force-cross` must be enabled for cross
 compilation. There is one case which is allowed even without the
 feature: when the only difference between host and target is that the
 host is x86_64 and the target is i686.
 
+When cross compiling, the C libraries are configured with `--build`
+set to the host and `--host` set to the target, and the C tests are
+not run. The C compiler, archiver and flags are taken from the first
+environment variable that is set out of `CC_<target>`,
+`CC_<target_with_underscores>`, `TARGET_CC` and `CC`, and similarly
+for `AR` and `CFLAGS`, like in the [cc crate]. If no C compiler is
+set, a compiler prefixed with the target, for example
+`aarch64-linux-gnu-gcc`, is used.
+
+The targets `wasm32-unknown-emscripten` and `wasm32-wasip1`, which
+was named `wasm32-wasi` before Rust 1.84, are supported. For these
+targets, GMP is configured with `--disable-assembly` instead of
+`--enable-fat`. If no C compiler is set, `emcc` is used for
+Emscripten, and a compiler prefixed with the target, for example
+`wasm32-wasip1-clang` from [wasi-sdk], is used for WASI. Functions that take a C `FILE` parameter, such as
+[`gmp::mpz_out_str`], are not available for targets where the
+[libc crate] has no `FILE` type.
+
+## Building GMP for a specific CPU
+
+By default, [GMP] is configured with `--enable-fat`, so that the built
+library is portable and selects the best code for the CPU at run
+time. If the built program will only run on one kind of CPU, you can
+set the environment variable `GMP_MPFR_SYS_CPU` to a CPU name known to
+[GMP], for example `haswell` or `zen2`, and [GMP] will be configured
+for that CPU instead, using its assembly code and tuned parameters
+without the fat dispatch overhead. Setting `GMP_MPFR_SYS_CPU` to
+`native` uses the CPU of the build machine as detected by the
+*config.guess* script of [GMP]; this is not supported when cross
+compiling. The built library may not run on other CPUs.
+
+`GMP_MPFR_SYS_CPU` is not supported for wasm32 targets. Libraries
+built for a specific CPU are cached separately from portable
+libraries. If `GMP_MPFR_SYS_CPU` is `native` but the CPU cannot be
+detected, the built libraries are not cached.
+
+## Extra configure arguments
+
+The configure options of the C libraries can be extended without
+changing the crate by setting the environment variables:
+
+  * `GMP_MPFR_SYS_GMP_CONFIGURE_ARGS`,
+    `GMP_MPFR_SYS_MPFR_CONFIGURE_ARGS` and
+    `GMP_MPFR_SYS_MPC_CONFIGURE_ARGS` to extra arguments for the
+    configure scripts of [GMP], [MPFR] and [MPC], for example
+    `--enable-assert` or `--enable-decimal-float`,
+  * `GMP_MPFR_SYS_GMP_CFLAGS`, `GMP_MPFR_SYS_MPFR_CFLAGS` and
+    `GMP_MPFR_SYS_MPC_CFLAGS` to the C flags for each library, for
+    example `-O2 -g`.
+
+The configure arguments are passed to the shell as they are, so they
+can contain quotes. The C flags replace the flags that the library
+would otherwise choose; by default [MPFR] and [MPC] use the compiler
+and flags that [GMP] was built with. These variables are ignored when
+the libraries are not built from source, and libraries built with
+different values are cached separately.
+
+## Build logs
+
+When the C libraries are built from source, the output of each
+configure, make and make check step is written to its own log file,
+for example *gmp-check.log*, in the *logs* subdirectory of the build
+script’s `OUT_DIR` instead of the build script output. If a step
+fails, the build panics with the last lines of the log, the names of
+the C tests that failed if any, and the path of the full log, which is
+kept. The logs are removed after a successful build.
+
 ## Caching the built C libraries
 
 Building the C libraries can take some time. In order to save
@@ -256,6 +375,63 @@ To use a different directory, you can set the environment variable
 `GMP_MPFR_SYS_CACHE` variable to an empty string or to a single
 underscore (`"_"`)  will disable caching.
 
+The cache can be shared by builds running at the same time. New cache
+entries are written to a temporary directory which is then renamed
+into place, and each entry has a *manifest.txt* file listing the
+sizes and hashes of its files. An entry that is incomplete or
+corrupted is not used, and the libraries are built again instead.
+
+Libraries built with different inputs are cached separately. The
+cache directory name contains a hash of the C compiler and its
+version, the `CC`, `AR` and `CFLAGS` environment variables, the
+configure options, the enabled features that affect the C libraries
+and the versions of the included C sources. These inputs are listed
+in a *key.txt* file next to the cached libraries.
+
+If a cache entry has [GMP], or [GMP] and [MPFR], but not all the
+libraries that are needed, for example after enabling the `mpc`
+feature, only the missing libraries are built against the cached ones
+and then added to the same cache entry. In that case [MPFR] is built
+without access to the internals of the [GMP] build.
+
+The cache is not limited in size by default. To limit it, you can set
+the environment variables:
+
+  * `GMP_MPFR_SYS_CACHE_MAX_SIZE` to the maximum size of the cache in
+    bytes, optionally with a suffix `K`, `M` or `G`, for example
+    `500M`,
+  * `GMP_MPFR_SYS_CACHE_MAX_AGE` to the maximum time since a cache
+    entry was last used, in days, or with a suffix `s`, `m`, `h` or
+    `d`, for example `30d`.
+
+Whenever a build saves or uses a cache entry, entries for all versions
+and targets that are too old are removed, and then the least recently
+used entries are removed until the cache is small enough. The entry
+used by the build itself is always kept. This includes entries saved
+by older versions of the crate, which are considered last used when
+they were saved.
+
+## Using prebuilt C libraries
+
+Instead of building the C libraries from the included sources, the
+build can use libraries that were built beforehand. To do this, set
+the following environment variables:
+
+  * `GMP_MPFR_SYS_GMP_LIB_DIR` and `GMP_MPFR_SYS_GMP_INCLUDE_DIR` to
+    the directories containing the [GMP] library and *gmp.h*,
+  * `GMP_MPFR_SYS_MPFR_LIB_DIR` and `GMP_MPFR_SYS_MPFR_INCLUDE_DIR`
+    to the directories containing the [MPFR] library and *mpfr.h*,
+  * `GMP_MPFR_SYS_MPC_LIB_DIR` and `GMP_MPFR_SYS_MPC_INCLUDE_DIR` to
+    the directories containing the [MPC] library and *mpc.h*.
+
+If an include directory is not set, the *include* directory next to
+the library directory is used. If the [MPFR] or [MPC] directories are
+not set, they default to the directories of the library before them.
+The versions of the libraries are checked in the same way as for the
+libraries built from source. If a static library (for example
+*libgmp.a*) is found it is linked statically, otherwise a shared
+library is linked dynamically. The prebuilt libraries are not cached.
+
 [*Cargo.toml*]: https://doc.rust-lang.org/cargo/guide/dependencies.html
 [GMP]: https://gmplib.org/
 [GNU GPL]: https://www.gnu.org/licenses/gpl-3.0.html
@@ -269,11 +445,18 @@ underscore (`"_"`)  will disable caching.
 [`MPFR_RNDN`]: C/MPFR/constant.MPFR_Basics.html#Rounding-Modes
 [`Rational`]: https://docs.rs/rug/&#42;/rug/struct.Rational.html
 [`enum MPFR_RND_T`]: C/MPFR/constant.MPFR_Basics.html#index-mpfr_005frnd_005ft
+[`gmp::accounting`]: gmp/accounting/index.html
+[`gmp::mpz_out_str`]: gmp/fn.mpz_out_str.html
+[`gmp::set_rust_memory_functions`]: gmp/fn.set_rust_memory_functions.html
 [`mp_set_memory_functions`]: C/GMP/constant.Custom_Allocation.html#index-mp_005fset_005fmemory_005ffunctions
 [`mpz_init`]: C/GMP/constant.Integer_Functions.html#index-mpz_005finit
+[`owned`]: owned/index.html
+[cc crate]: https://crates.io/crates/cc
+[libc crate]: https://crates.io/crates/libc
 [msys]: https://www.msys2.org/
 [rug crate]: https://crates.io/crates/rug
 [sys crate]: https://crates.io/crates/gmp-mpfr-sys
+[wasi-sdk]: https://github.com/WebAssembly/wasi-sdk
 */
 #![no_std]
 #![warn(missing_docs)]
@@ -378,11 +561,44 @@ NzQgMCAwLjY1NDc1IDAuNDc0MTMgMS4xNzQgMS4xMjg5IDEuMTc0eiIvPgogICA8L2c+CiAgPC9nPgog
     clippy::useless_conversion
 )]
 
+#[cfg(feature = "rust-alloc")]
+extern crate alloc;
+#[cfg(feature = "accounting")]
+extern crate std;
+
+#[cfg(c_unwind)]
+#[macro_use]
+mod c_unwind;
+
+#[cfg(not(c_unwind))]
+macro_rules! extern_c {
+    ($($decl:tt)*) => {
+        extern "C" {
+            $($decl)*
+        }
+    };
+}
+
+#[cfg(not(c_unwind))]
+macro_rules! extern_c_fn {
+    ($(
+        $(#[$attr:meta])*
+        pub unsafe fn $name:ident($($arg:tt)*) $(-> $ret:ty)? $body:block
+    )*) => {
+        $(
+            $(#[$attr])*
+            pub unsafe extern "C" fn $name($($arg)*) $(-> $ret)? $body
+        )*
+    };
+}
+
 pub mod gmp;
 #[cfg(feature = "mpc")]
 pub mod mpc;
 #[cfg(feature = "mpfr")]
 pub mod mpfr;
+#[cfg(feature = "owned")]
+pub mod owned;
 
 #[cfg(extended_key_value_attributes)]
 pub mod C;
@@ -48,7 +48,10 @@ use crate::{
     gmp::{mpf_t, mpq_t, mpz_t, randstate_t},
     mpfr::{mpfr_t, prec_t, rnd_t as mpfr_rnd_t},
 };
-use libc::{c_char, c_int, c_long, c_ulong, intmax_t, uintmax_t, FILE};
+use core::mem;
+#[cfg(not(no_file))]
+use libc::FILE;
+use libc::{c_char, c_int, c_long, c_ulong, intmax_t, uintmax_t};
 
 include!(concat!(env!("OUT_DIR"), "/mpc_h.rs"));
 
@@ -142,6 +145,11 @@ pub struct mpc_t {
     pub im: mpfr_t,
 }
 
+// Compile-time checks that the layout matches the size and offset
+// found in mpc.h by the build script.
+const _: [(); MPC_SIZEOF_MPC_T] = [(); mem::size_of::<mpc_t>()];
+const _: [(); MPC_OFFSETOF_IM] = [(); mem::size_of::<mpfr_t>()];
+
 // Types for function declarations in this file.
 
 type mpz_srcptr = *const mpz_t;
@@ -153,7 +161,7 @@ type mpfr_ptr = *mut mpfr_t;
 type mpc_ptr = *mut mpc_t;
 type mpc_srcptr = *const mpc_t;
 
-extern "C" {
+extern_c! {
     // Initialization Functions
 
     /// See: [`mpc_init2`](../C/MPC/constant.Complex_Functions.html#index-mpc_005finit2)
@@ -261,6 +269,7 @@ extern "C" {
     /// See: [`mpc_free_str`](../C/MPC/constant.Complex_Functions.html#index-mpc_005ffree_005fstr)
     #[link_name = "mpc_free_str"]
     pub fn free_str(rop: *mut c_char);
+    #[cfg(not(no_file))]
     /// See: [`mpc_inp_str`](../C/MPC/constant.Complex_Functions.html#index-mpc_005finp_005fstr)
     #[link_name = "mpc_inp_str"]
     pub fn inp_str(
@@ -270,6 +279,7 @@ extern "C" {
         base: c_int,
         rnd: rnd_t,
     ) -> c_int;
+    #[cfg(not(no_file))]
     /// See: [`mpc_out_str`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fout_005fstr)
     #[link_name = "mpc_out_str"]
     pub fn out_str(
@@ -289,12 +299,14 @@ extern "C" {
     #[link_name = "mpc_cmp_si_si"]
     pub fn cmp_si_si(op1: mpc_srcptr, op2r: c_long, op2i: c_long) -> c_int;
 }
-/// See: [`mpc_cmp_si`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fcmp_005fsi)
-#[inline]
-pub unsafe extern "C" fn cmp_si(op1: mpc_srcptr, op2: c_long) -> c_int {
-    unsafe { cmp_si_si(op1, op2, 0) }
+extern_c_fn! {
+    /// See: [`mpc_cmp_si`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fcmp_005fsi)
+    #[inline]
+    pub unsafe fn cmp_si(op1: mpc_srcptr, op2: c_long) -> c_int {
+        unsafe { cmp_si_si(op1, op2, 0) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpc_cmp_abs`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fcmp_005fabs)
     #[link_name = "mpc_cmp_abs"]
     pub fn cmp_abs(op1: mpc_srcptr, op2: mpc_srcptr) -> c_int;
@@ -327,7 +339,7 @@ pub unsafe extern "C" fn imagref(op: mpc_ptr) -> mpfr_ptr {
 pub unsafe extern "C" fn imagref_const(op: mpc_srcptr) -> mpfr_srcptr {
     unsafe { (op as mpfr_srcptr).offset(1) }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpc_arg`](../C/MPC/constant.Complex_Functions.html#index-mpc_005farg)
     #[link_name = "mpc_arg"]
     pub fn arg(rop: mpfr_ptr, op: mpc_srcptr, rnd: mpfr_rnd_t) -> c_int;
@@ -359,12 +371,14 @@ extern "C" {
     #[link_name = "mpc_sub_ui"]
     pub fn sub_ui(rop: mpc_ptr, op1: mpc_srcptr, op2: c_ulong, rnd: rnd_t) -> c_int;
 }
-/// See: [`mpc_ui_sub`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fui_005fsub)
-#[inline]
-pub unsafe extern "C" fn ui_sub(rop: mpc_ptr, op1: c_ulong, op2: mpc_srcptr, rnd: rnd_t) -> c_int {
-    unsafe { ui_ui_sub(rop, op1, 0, op2, rnd) }
+extern_c_fn! {
+    /// See: [`mpc_ui_sub`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fui_005fsub)
+    #[inline]
+    pub unsafe fn ui_sub(rop: mpc_ptr, op1: c_ulong, op2: mpc_srcptr, rnd: rnd_t) -> c_int {
+        unsafe { ui_ui_sub(rop, op1, 0, op2, rnd) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpc_ui_ui_sub`](../C/MPC/constant.Complex_Functions.html#index-mpc_005fui_005fui_005fsub)
     #[link_name = "mpc_ui_ui_sub"]
     pub fn ui_ui_sub(
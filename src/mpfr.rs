@@ -105,14 +105,27 @@ fn main() {
 #![allow(clippy::needless_doctest_main)]
 
 use crate::gmp::{limb_t, mpf_t, mpq_t, mpz_t, randstate_t, NUMB_BITS};
-use core::{mem, ptr::NonNull};
+use core::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Sub, SubAssign},
+    ptr::NonNull,
+};
 #[doc(hidden)]
-// libc::c_int is public for the mpfr_round_nearest_away macro
+// libc::c_int is public as older versions of the
+// mpfr_round_nearest_away macro used it
 pub use libc::c_int;
-use libc::{c_char, c_long, c_uint, c_ulong, c_void, intmax_t, uintmax_t, FILE};
+#[cfg(not(no_file))]
+use libc::FILE;
+use libc::{c_char, c_long, c_uint, c_ulong, c_void, intmax_t, uintmax_t};
 
 include!(concat!(env!("OUT_DIR"), "/mpfr_h.rs"));
 
+pub mod display;
+pub mod ieee;
+pub mod interval;
+
 /// See: [`mpfr_prec_t`](../C/MPFR/constant.MPFR_Basics.html#index-mpfr_005fprec_005ft)
 pub type prec_t = c_long;
 
@@ -188,9 +201,33 @@ pub type flags_t = c_uint;
 pub type exp_t = c_long;
 
 /// See: [Nomenclature and Types](../C/MPFR/constant.MPFR_Basics.html#Nomenclature-and-Types)
-pub const PREC_MIN: prec_t = 1;
+pub const PREC_MIN: prec_t = MPFR_PREC_MIN;
 /// See: [Nomenclature and Types](../C/MPFR/constant.MPFR_Basics.html#Nomenclature-and-Types)
-pub const PREC_MAX: prec_t = prec_t::max_value() - 256;
+pub const PREC_MAX: prec_t = MPFR_PREC_MAX;
+
+/// The initial value of the minimum exponent.
+///
+/// See: [`mpfr_set_emin`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005femin)
+pub const EMIN_DEFAULT: exp_t = MPFR_EMIN_DEFAULT;
+/// The initial value of the maximum exponent.
+///
+/// See: [`mpfr_set_emax`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005femax)
+pub const EMAX_DEFAULT: exp_t = MPFR_EMAX_DEFAULT;
+
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_UNDERFLOW: flags_t = MPFR_FLAGS_UNDERFLOW;
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_OVERFLOW: flags_t = MPFR_FLAGS_OVERFLOW;
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_NAN: flags_t = MPFR_FLAGS_NAN;
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_INEXACT: flags_t = MPFR_FLAGS_INEXACT;
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_ERANGE: flags_t = MPFR_FLAGS_ERANGE;
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_DIVBY0: flags_t = MPFR_FLAGS_DIVBY0;
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+pub const FLAGS_ALL: flags_t = MPFR_FLAGS_ALL;
 
 /// See: [`mpfr_t`](../C/MPFR/constant.MPFR_Basics.html#index-mpfr_005ft)
 /// and [Internals](../C/MPFR/constant.MPFR_Interface.html#Internals)
@@ -217,6 +254,13 @@ pub struct mpfr_t {
     pub d: NonNull<limb_t>,
 }
 
+// Compile-time checks that the types match the sizes found in mpfr.h
+// by the build script.
+const _: [(); MPFR_SIZEOF_PREC_T] = [(); mem::size_of::<prec_t>()];
+const _: [(); MPFR_SIZEOF_EXP_T] = [(); mem::size_of::<exp_t>()];
+const _: [(); MPFR_SIZEOF_FLAGS_T] = [(); mem::size_of::<flags_t>()];
+const _: [(); MPFR_SIZEOF_MPFR_T] = [(); mem::size_of::<mpfr_t>()];
+
 /// See: [`mpfr_custom_init_set`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcustom_005finit_005fset)
 pub const NAN_KIND: c_int = 0;
 /// See: [`mpfr_custom_init_set`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcustom_005finit_005fset)
@@ -252,7 +296,7 @@ const EXP_INF: exp_t = 2 - EXP_MAX;
 
 // Initialization Functions
 
-extern "C" {
+extern_c! {
     /// See: [`mpfr_init2`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit2)
     #[link_name = "mpfr_init2"]
     pub fn init2(x: mpfr_ptr, prec: prec_t);
@@ -292,7 +336,7 @@ macro_rules! MPFR_DECL_INIT {
         };
     };
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_set_default_prec`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005fdefault_005fprec)
     #[link_name = "mpfr_set_default_prec"]
     pub fn set_default_prec(prec: prec_t);
@@ -311,16 +355,18 @@ pub unsafe extern "C" fn get_prec(x: mpfr_srcptr) -> prec_t {
 
 // Assignment Functions
 
-extern "C" {
+extern_c! {
     #[link_name = "mpfr_set4"]
     fn set4(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t, i: c_int) -> c_int;
 }
-/// See: [`mpfr_set`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset)
-#[inline]
-pub unsafe extern "C" fn set(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int {
-    unsafe { set4(rop, op, rnd, (*op).sign) }
+extern_c_fn! {
+    /// See: [`mpfr_set`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset)
+    #[inline]
+    pub unsafe fn set(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int {
+        unsafe { set4(rop, op, rnd, (*op).sign) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_set_ui`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005fui)
     #[link_name = "mpfr_set_ui"]
     pub fn set_ui(rop: mpfr_ptr, op: c_ulong, rnd: rnd_t) -> c_int;
@@ -391,63 +437,65 @@ extern "C" {
 
 // Combined Initialization and Assignment Functions
 
-/// See: [`mpfr_init_set`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset)
-#[inline]
-pub unsafe extern "C" fn init_set(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set(rop, op, rnd)
+extern_c_fn! {
+    /// See: [`mpfr_init_set`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset)
+    #[inline]
+    pub unsafe fn init_set(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set(rop, op, rnd)
+        }
     }
-}
-/// See: [`mpfr_init_set_ui`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fui)
-#[inline]
-pub unsafe extern "C" fn init_set_ui(rop: mpfr_ptr, op: c_ulong, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set_ui(rop, op, rnd)
+    /// See: [`mpfr_init_set_ui`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fui)
+    #[inline]
+    pub unsafe fn init_set_ui(rop: mpfr_ptr, op: c_ulong, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set_ui(rop, op, rnd)
+        }
     }
-}
-/// See: [`mpfr_init_set_si`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fsi)
-#[inline]
-pub unsafe extern "C" fn init_set_si(rop: mpfr_ptr, op: c_long, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set_si(rop, op, rnd)
+    /// See: [`mpfr_init_set_si`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fsi)
+    #[inline]
+    pub unsafe fn init_set_si(rop: mpfr_ptr, op: c_long, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set_si(rop, op, rnd)
+        }
     }
-}
-/// See: [`mpfr_init_set_d`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fd)
-#[inline]
-pub unsafe extern "C" fn init_set_d(rop: mpfr_ptr, op: f64, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set_d(rop, op, rnd)
+    /// See: [`mpfr_init_set_d`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fd)
+    #[inline]
+    pub unsafe fn init_set_d(rop: mpfr_ptr, op: f64, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set_d(rop, op, rnd)
+        }
     }
-}
-/// See: [`mpfr_init_set_z`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fz)
-#[inline]
-pub unsafe extern "C" fn init_set_z(rop: mpfr_ptr, op: mpz_srcptr, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set_z(rop, op, rnd)
+    /// See: [`mpfr_init_set_z`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fz)
+    #[inline]
+    pub unsafe fn init_set_z(rop: mpfr_ptr, op: mpz_srcptr, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set_z(rop, op, rnd)
+        }
     }
-}
-/// See: [`mpfr_init_set_q`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fq)
-#[inline]
-pub unsafe extern "C" fn init_set_q(rop: mpfr_ptr, op: mpq_srcptr, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set_q(rop, op, rnd)
+    /// See: [`mpfr_init_set_q`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fq)
+    #[inline]
+    pub unsafe fn init_set_q(rop: mpfr_ptr, op: mpq_srcptr, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set_q(rop, op, rnd)
+        }
     }
-}
-/// See: [`mpfr_init_set_f`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005ff)
-#[inline]
-pub unsafe extern "C" fn init_set_f(rop: mpfr_ptr, op: mpf_srcptr, rnd: rnd_t) -> c_int {
-    unsafe {
-        init(rop);
-        set_f(rop, op, rnd)
+    /// See: [`mpfr_init_set_f`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005ff)
+    #[inline]
+    pub unsafe fn init_set_f(rop: mpfr_ptr, op: mpf_srcptr, rnd: rnd_t) -> c_int {
+        unsafe {
+            init(rop);
+            set_f(rop, op, rnd)
+        }
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_init_set_str`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finit_005fset_005fstr)
     #[link_name = "mpfr_init_set_str"]
     pub fn init_set_str(x: mpfr_ptr, s: *const c_char, base: c_int, rnd: rnd_t) -> c_int;
@@ -655,12 +703,14 @@ extern "C" {
     #[link_name = "mpfr_neg"]
     pub fn neg(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int;
 }
-/// See: [`mpfr_abs`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fabs)
-#[inline]
-pub unsafe extern "C" fn abs(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int {
-    unsafe { set4(rop, op, rnd, 1) }
+extern_c_fn! {
+    /// See: [`mpfr_abs`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fabs)
+    #[inline]
+    pub unsafe fn abs(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int {
+        unsafe { set4(rop, op, rnd, 1) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_dim`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fdim)
     #[link_name = "mpfr_dim"]
     pub fn dim(rop: mpfr_ptr, op1: mpfr_srcptr, op2: mpfr_srcptr, rnd: rnd_t) -> c_int;
@@ -736,26 +786,28 @@ extern "C" {
 
 // Comparison Functions
 
-extern "C" {
+extern_c! {
     #[link_name = "mpfr_cmp3"]
     fn cmp3(op1: mpfr_srcptr, op2: mpfr_srcptr, i: c_int) -> c_int;
 }
-/// See: [`mpfr_cmp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp)
-#[inline]
-pub unsafe extern "C" fn cmp(op1: mpfr_srcptr, op2: mpfr_srcptr) -> c_int {
-    unsafe { cmp3(op1, op2, 1) }
-}
-/// See: [`mpfr_cmp_ui`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp_005fui)
-#[inline]
-pub unsafe extern "C" fn cmp_ui(op1: mpfr_srcptr, op2: c_ulong) -> c_int {
-    unsafe { cmp_ui_2exp(op1, op2, 0) }
-}
-/// See: [`mpfr_cmp_si`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp_005fsi)
-#[inline]
-pub unsafe extern "C" fn cmp_si(op1: mpfr_srcptr, op2: c_long) -> c_int {
-    unsafe { cmp_si_2exp(op1, op2, 0) }
+extern_c_fn! {
+    /// See: [`mpfr_cmp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp)
+    #[inline]
+    pub unsafe fn cmp(op1: mpfr_srcptr, op2: mpfr_srcptr) -> c_int {
+        unsafe { cmp3(op1, op2, 1) }
+    }
+    /// See: [`mpfr_cmp_ui`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp_005fui)
+    #[inline]
+    pub unsafe fn cmp_ui(op1: mpfr_srcptr, op2: c_ulong) -> c_int {
+        unsafe { cmp_ui_2exp(op1, op2, 0) }
+    }
+    /// See: [`mpfr_cmp_si`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp_005fsi)
+    #[inline]
+    pub unsafe fn cmp_si(op1: mpfr_srcptr, op2: c_long) -> c_int {
+        unsafe { cmp_si_2exp(op1, op2, 0) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_cmp_d`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcmp_005fd)
     #[link_name = "mpfr_cmp_d"]
     pub fn cmp_d(op1: mpfr_srcptr, op2: f64) -> c_int;
@@ -799,7 +851,7 @@ pub unsafe extern "C" fn inf_p(op: mpfr_srcptr) -> c_int {
         0
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_number_p`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fnumber_005fp)
     #[link_name = "mpfr_number_p"]
     pub fn number_p(op: mpfr_srcptr) -> c_int;
@@ -822,21 +874,23 @@ pub unsafe extern "C" fn regular_p(op: mpfr_srcptr) -> c_int {
         0
     }
 }
-/// See: [`mpfr_sgn`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fsgn)
-#[inline]
-pub unsafe extern "C" fn sgn(op: mpfr_srcptr) -> c_int {
-    if unsafe { (*op).exp } < EXP_INF {
-        unsafe {
-            if nan_p(op) != 0 {
-                set_erangeflag();
+extern_c_fn! {
+    /// See: [`mpfr_sgn`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fsgn)
+    #[inline]
+    pub unsafe fn sgn(op: mpfr_srcptr) -> c_int {
+        if unsafe { (*op).exp } < EXP_INF {
+            unsafe {
+                if nan_p(op) != 0 {
+                    set_erangeflag();
+                }
             }
+            0
+        } else {
+            unsafe { (*op).sign }
         }
-        0
-    } else {
-        unsafe { (*op).sign }
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_greater_p`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fgreater_005fp)
     #[link_name = "mpfr_greater_p"]
     pub fn greater_p(op1: mpfr_srcptr, op2: mpfr_srcptr) -> c_int;
@@ -1047,15 +1101,19 @@ extern "C" {
 
     // Input and Output Functions
 
+    #[cfg(not(no_file))]
     /// See: [`mpfr_out_str`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fout_005fstr)
     #[link_name = "__gmpfr_out_str"]
     pub fn out_str(stream: *mut FILE, base: c_int, n: usize, op: mpfr_srcptr, rnd: rnd_t) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpfr_inp_str`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005finp_005fstr)
     #[link_name = "__gmpfr_inp_str"]
     pub fn inp_str(rop: mpfr_ptr, stream: *mut FILE, base: c_int, rnd: rnd_t) -> usize;
+    #[cfg(not(no_file))]
     /// See: [`mpfr_fpif_export`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ffpip_005fexport)
     #[link_name = "__gmpfr_fpif_export"]
     pub fn fpif_export(stream: *mut FILE, op: mpfr_ptr) -> c_int;
+    #[cfg(not(no_file))]
     /// See: [`mpfr_fpif_import`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ffpip_005fimport)
     #[link_name = "__gmpfr_fpif_import"]
     pub fn fpif_import(op: mpfr_ptr, stream: *mut FILE) -> c_int;
@@ -1065,6 +1123,7 @@ extern "C" {
 
     // Formatted Output Functions
 
+    #[cfg(not(no_file))]
     /// See: [`mpfr_fprintf`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ffprintf)
     #[link_name = "__gmpfr_fprintf"]
     pub fn fprintf(stream: *mut FILE, template: *const c_char, ...) -> c_int;
@@ -1087,35 +1146,39 @@ extern "C" {
     #[link_name = "mpfr_rint"]
     pub fn rint(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int;
 }
-/// See: [`mpfr_ceil`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fceil)
-#[inline]
-pub unsafe extern "C" fn ceil(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
-    unsafe { rint(rop, op, rnd_t::RNDU) }
-}
-/// See: [`mpfr_floor`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ffloor)
-#[inline]
-pub unsafe extern "C" fn floor(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
-    unsafe { rint(rop, op, rnd_t::RNDD) }
-}
-/// See: [`mpfr_round`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fround)
-#[inline]
-pub unsafe extern "C" fn round(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
-    #[allow(deprecated)]
-    unsafe {
-        rint(rop, op, rnd_t::RNDNA)
+extern_c_fn! {
+    /// See: [`mpfr_ceil`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fceil)
+    #[inline]
+    pub unsafe fn ceil(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
+        unsafe { rint(rop, op, rnd_t::RNDU) }
+    }
+    /// See: [`mpfr_floor`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ffloor)
+    #[inline]
+    pub unsafe fn floor(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
+        unsafe { rint(rop, op, rnd_t::RNDD) }
+    }
+    /// See: [`mpfr_round`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fround)
+    #[inline]
+    pub unsafe fn round(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
+        #[allow(deprecated)]
+        unsafe {
+            rint(rop, op, rnd_t::RNDNA)
+        }
     }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_roundeven`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005froundeven)
     #[link_name = "mpfr_roundeven"]
     pub fn roundeven(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int;
 }
-/// See: [`mpfr_trunc`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ftrunc)
-#[inline]
-pub unsafe extern "C" fn trunc(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
-    unsafe { rint(rop, op, rnd_t::RNDZ) }
+extern_c_fn! {
+    /// See: [`mpfr_trunc`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005ftrunc)
+    #[inline]
+    pub unsafe fn trunc(rop: mpfr_ptr, op: mpfr_srcptr) -> c_int {
+        unsafe { rint(rop, op, rnd_t::RNDZ) }
+    }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_rint_ceil`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005frint_005fceil)
     #[link_name = "mpfr_rint_ceil"]
     pub fn rint_ceil(rop: mpfr_ptr, op: mpfr_srcptr, rnd: rnd_t) -> c_int;
@@ -1186,22 +1249,26 @@ extern "C" {
 #[macro_export]
 macro_rules! mpfr_round_nearest_away {
     ($foo:expr, $rop:expr $(, $op:expr)*) => {{
-        use $crate::mpfr::c_int;
         type mpfr_ptr = *mut $crate::mpfr::mpfr_t;
         let rop: mpfr_ptr = $rop;
-        extern "C" {
-            fn mpfr_round_nearest_away_begin(rop: mpfr_ptr);
-            fn mpfr_round_nearest_away_end(rop: mpfr_ptr, inex: c_int) -> c_int;
-        }
-        mpfr_round_nearest_away_begin(rop);
-        mpfr_round_nearest_away_end(
+        $crate::mpfr::round_nearest_away_begin(rop);
+        $crate::mpfr::round_nearest_away_end(
             rop,
             $foo(rop $(, $op)*, $crate::mpfr::rnd_t::RNDN),
         )
     }};
 }
+// used by the mpfr_round_nearest_away macro
+extern_c! {
+    #[doc(hidden)]
+    #[link_name = "mpfr_round_nearest_away_begin"]
+    pub fn round_nearest_away_begin(rop: mpfr_ptr);
+    #[doc(hidden)]
+    #[link_name = "mpfr_round_nearest_away_end"]
+    pub fn round_nearest_away_end(rop: mpfr_ptr, inex: c_int) -> c_int;
+}
 
-extern "C" {
+extern_c! {
     // Miscellaneous Functions
 
     /// See: [`mpfr_nexttoward`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fnexttoward)
@@ -1241,7 +1308,7 @@ extern "C" {
 pub unsafe extern "C" fn get_exp(x: mpfr_srcptr) -> exp_t {
     unsafe { (*x).exp }
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_set_exp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005fexp)
     #[link_name = "mpfr_set_exp"]
     pub fn set_exp(x: mpfr_ptr, e: exp_t) -> c_int;
@@ -1255,22 +1322,24 @@ pub unsafe extern "C" fn signbit(op: mpfr_srcptr) -> c_int {
         0
     }
 }
-/// See: [`mpfr_setsign`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fsetsign)
-#[inline]
-pub unsafe extern "C" fn setsign(rop: mpfr_ptr, op: mpfr_srcptr, s: c_int, rnd: rnd_t) -> c_int {
-    unsafe { set4(rop, op, rnd, if s != 0 { -1 } else { 1 }) }
+extern_c_fn! {
+    /// See: [`mpfr_setsign`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fsetsign)
+    #[inline]
+    pub unsafe fn setsign(rop: mpfr_ptr, op: mpfr_srcptr, s: c_int, rnd: rnd_t) -> c_int {
+        unsafe { set4(rop, op, rnd, if s != 0 { -1 } else { 1 }) }
+    }
+    /// See: [`mpfr_copysign`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcopysign)
+    #[inline]
+    pub unsafe fn copysign(
+        rop: mpfr_ptr,
+        op1: mpfr_srcptr,
+        op2: mpfr_srcptr,
+        rnd: rnd_t,
+    ) -> c_int {
+        unsafe { set4(rop, op1, rnd, (*op2).sign) }
+    }
 }
-/// See: [`mpfr_copysign`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcopysign)
-#[inline]
-pub unsafe extern "C" fn copysign(
-    rop: mpfr_ptr,
-    op1: mpfr_srcptr,
-    op2: mpfr_srcptr,
-    rnd: rnd_t,
-) -> c_int {
-    unsafe { set4(rop, op1, rnd, (*op2).sign) }
-}
-extern "C" {
+extern_c! {
     /// See: [`mpfr_get_version`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fget_005fversion)
     #[link_name = "mpfr_get_version"]
     pub fn get_version() -> *const c_char;
@@ -1290,7 +1359,7 @@ pub const VERSION_STRING: *const c_char = MPFR_VERSION_STRING;
 pub extern "C" fn VERSION_NUM(major: c_int, minor: c_int, patchlevel: c_int) -> c_int {
     (major << 16) | (minor << 8) | patchlevel
 }
-extern "C" {
+extern_c! {
     /// See: [`mpfr_get_patches`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fget_005fpatches)
     #[link_name = "mpfr_get_patches"]
     pub fn get_patches() -> *const c_char;
@@ -1445,25 +1514,27 @@ extern "C" {
     #[link_name = "mpfr_reldiff"]
     pub fn reldiff(rop: mpfr_ptr, op1: mpfr_srcptr, op2: mpfr_srcptr, rnd: rnd_t);
 }
-/// See: [`mpfr_mul_2exp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fmul_005f2exp)
-#[inline]
-pub unsafe extern "C" fn mul_2exp(
-    rop: mpfr_ptr,
-    op1: mpfr_srcptr,
-    op2: c_ulong,
-    rnd: rnd_t,
-) -> c_int {
-    unsafe { mul_2ui(rop, op1, op2, rnd) }
-}
-/// See: [`mpfr_div_2exp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fdiv_005f2exp)
-#[inline]
-pub unsafe extern "C" fn div_2exp(
-    rop: mpfr_ptr,
-    op1: mpfr_srcptr,
-    op2: c_ulong,
-    rnd: rnd_t,
-) -> c_int {
-    unsafe { div_2ui(rop, op1, op2, rnd) }
+extern_c_fn! {
+    /// See: [`mpfr_mul_2exp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fmul_005f2exp)
+    #[inline]
+    pub unsafe fn mul_2exp(
+        rop: mpfr_ptr,
+        op1: mpfr_srcptr,
+        op2: c_ulong,
+        rnd: rnd_t,
+    ) -> c_int {
+        unsafe { mul_2ui(rop, op1, op2, rnd) }
+    }
+    /// See: [`mpfr_div_2exp`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fdiv_005f2exp)
+    #[inline]
+    pub unsafe fn div_2exp(
+        rop: mpfr_ptr,
+        op1: mpfr_srcptr,
+        op2: c_ulong,
+        rnd: rnd_t,
+    ) -> c_int {
+        unsafe { div_2ui(rop, op1, op2, rnd) }
+    }
 }
 
 // Custom Interface
@@ -1533,6 +1604,541 @@ pub unsafe extern "C" fn custom_move(x: mpfr_ptr, new_position: *mut c_void) {
     unsafe { (*x).d = NonNull::new_unchecked(new_position as *mut limb_t) }
 }
 
+// Exception Flags
+
+/// A set of MPFR exception flags.
+///
+/// This is a [`flags_t`](type.flags_t.html) mask with set operations.
+/// The `Debug` implementation lists the flags in the set.
+///
+/// See: [Exceptions](../C/MPFR/constant.MPFR_Basics.html#Exceptions)
+///
+/// # Examples
+///
+/// ```rust
+/// use core::mem::MaybeUninit;
+/// use gmp_mpfr_sys::mpfr::{self, Flags};
+/// unsafe {
+///     let mut f = MaybeUninit::uninit();
+///     mpfr::init2(f.as_mut_ptr(), 4);
+///     let mut f = f.assume_init();
+///     // 21 needs five significant bits, so setting is inexact
+///     let (_, raised) = mpfr::capture_flags(|| mpfr::set_ui(&mut f, 21, mpfr::rnd_t::RNDN));
+///     assert_eq!(raised, Flags::INEXACT);
+///     assert!((raised | Flags::NAN).contains(Flags::INEXACT));
+///     assert_eq!(format!("{:?}", raised | Flags::NAN), "Flags(NAN | INEXACT)");
+///     mpfr::clear(&mut f);
+/// }
+/// ```
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Flags(pub flags_t);
+
+impl Flags {
+    /// The empty set.
+    pub const EMPTY: Flags = Flags(0);
+    /// The underflow flag, [`FLAGS_UNDERFLOW`](constant.FLAGS_UNDERFLOW.html).
+    pub const UNDERFLOW: Flags = Flags(FLAGS_UNDERFLOW);
+    /// The overflow flag, [`FLAGS_OVERFLOW`](constant.FLAGS_OVERFLOW.html).
+    pub const OVERFLOW: Flags = Flags(FLAGS_OVERFLOW);
+    /// The NaN flag, [`FLAGS_NAN`](constant.FLAGS_NAN.html).
+    pub const NAN: Flags = Flags(FLAGS_NAN);
+    /// The inexact flag, [`FLAGS_INEXACT`](constant.FLAGS_INEXACT.html).
+    pub const INEXACT: Flags = Flags(FLAGS_INEXACT);
+    /// The erange (range error) flag, [`FLAGS_ERANGE`](constant.FLAGS_ERANGE.html).
+    pub const ERANGE: Flags = Flags(FLAGS_ERANGE);
+    /// The divide-by-zero flag, [`FLAGS_DIVBY0`](constant.FLAGS_DIVBY0.html).
+    pub const DIVBY0: Flags = Flags(FLAGS_DIVBY0);
+    /// All the flags, [`FLAGS_ALL`](constant.FLAGS_ALL.html).
+    pub const ALL: Flags = Flags(FLAGS_ALL);
+
+    /// Returns the [`flags_t`](type.flags_t.html) mask.
+    #[inline]
+    pub const fn bits(self) -> flags_t {
+        self.0
+    }
+
+    /// Returns `true` if the set is empty.
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns `true` if all the flags in `other` are in the set.
+    #[inline]
+    pub const fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if any of the flags in `other` is in the set.
+    #[inline]
+    pub const fn intersects(self, other: Flags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns the flags that are currently raised.
+    ///
+    /// See: [`mpfr_flags_save`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fflags_005fsave)
+    #[inline]
+    pub fn raised() -> Flags {
+        Flags(unsafe { flags_save() })
+    }
+}
+
+impl BitAnd for Flags {
+    type Output = Flags;
+    #[inline]
+    fn bitand(self, rhs: Flags) -> Flags {
+        Flags(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for Flags {
+    #[inline]
+    fn bitand_assign(&mut self, rhs: Flags) {
+        self.0 &= rhs.0;
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+    #[inline]
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Flags {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Flags) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitXor for Flags {
+    type Output = Flags;
+    #[inline]
+    fn bitxor(self, rhs: Flags) -> Flags {
+        Flags(self.0 ^ rhs.0)
+    }
+}
+
+impl BitXorAssign for Flags {
+    #[inline]
+    fn bitxor_assign(&mut self, rhs: Flags) {
+        self.0 ^= rhs.0;
+    }
+}
+
+impl Sub for Flags {
+    type Output = Flags;
+    #[inline]
+    fn sub(self, rhs: Flags) -> Flags {
+        Flags(self.0 & !rhs.0)
+    }
+}
+
+impl SubAssign for Flags {
+    #[inline]
+    fn sub_assign(&mut self, rhs: Flags) {
+        self.0 &= !rhs.0;
+    }
+}
+
+impl Not for Flags {
+    type Output = Flags;
+    /// Returns the flags in
+    /// [`Flags::ALL`](struct.Flags.html#associatedconstant.ALL) that are
+    /// not in the set.
+    #[inline]
+    fn not(self) -> Flags {
+        Flags(!self.0 & FLAGS_ALL)
+    }
+}
+
+impl Debug for Flags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        const NAMES: [(Flags, &str); 6] = [
+            (Flags::UNDERFLOW, "UNDERFLOW"),
+            (Flags::OVERFLOW, "OVERFLOW"),
+            (Flags::NAN, "NAN"),
+            (Flags::INEXACT, "INEXACT"),
+            (Flags::ERANGE, "ERANGE"),
+            (Flags::DIVBY0, "DIVBY0"),
+        ];
+        f.write_str("Flags(")?;
+        let mut rest = *self;
+        let mut first = true;
+        for &(flag, name) in NAMES.iter() {
+            if rest.contains(flag) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                rest -= flag;
+                first = false;
+            }
+        }
+        if !rest.is_empty() {
+            if !first {
+                f.write_str(" | ")?;
+            }
+            write!(f, "{:#x}", rest.0)?;
+        } else if first {
+            f.write_str("empty")?;
+        }
+        f.write_str(")")
+    }
+}
+
+// Restores the saved flags on drop, so that they are restored even if
+// the closure panics.
+struct FlagsRestorer(flags_t);
+
+impl Drop for FlagsRestorer {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { flags_restore(self.0, FLAGS_ALL) }
+    }
+}
+
+/// Runs a closure and returns its result together with the flags that
+/// it raised.
+///
+/// The flags are saved and cleared before the closure is called, and
+/// restored afterwards, so the flags raised by the closure are not
+/// visible outside. The flags are restored even if the closure
+/// panics.
+///
+/// See: [`mpfr_flags_save`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fflags_005fsave)
+/// and [`mpfr_flags_restore`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fflags_005frestore)
+///
+/// # Examples
+///
+/// ```rust
+/// use core::mem::MaybeUninit;
+/// use gmp_mpfr_sys::mpfr::{self, Flags};
+/// unsafe {
+///     let mut f = MaybeUninit::uninit();
+///     mpfr::init2(f.as_mut_ptr(), 53);
+///     let mut f = f.assume_init();
+///     let fp: *mut mpfr::mpfr_t = &mut f;
+///     mpfr::set_ui(fp, 1, mpfr::rnd_t::RNDN);
+///     let (dir, raised) = mpfr::capture_flags(|| mpfr::div_ui(fp, fp, 0, mpfr::rnd_t::RNDN));
+///     assert_eq!(dir, 0);
+///     assert_eq!(raised, Flags::DIVBY0);
+///     assert!(!Flags::raised().contains(Flags::DIVBY0));
+///     mpfr::clear(&mut f);
+/// }
+/// ```
+pub fn capture_flags<R, F: FnOnce() -> R>(f: F) -> (R, Flags) {
+    capture_flags_mask(Flags::ALL, f)
+}
+
+/// Runs a closure and returns its result together with the flags in
+/// `mask` that it raised.
+///
+/// This is like [`capture_flags`](fn.capture_flags.html), except that
+/// only the flags in `mask` are cleared before the closure is called
+/// and tested afterwards. All the flags are restored.
+pub fn capture_flags_mask<R, F: FnOnce() -> R>(mask: Flags, f: F) -> (R, Flags) {
+    let _restorer = FlagsRestorer(unsafe { flags_save() });
+    unsafe { flags_clear(mask.0) };
+    let ret = f();
+    let raised = Flags(unsafe { flags_test(mask.0) });
+    (ret, raised)
+}
+
+// Thread-Local Environment
+
+/// A guard that saves the thread-local MPFR environment and restores it
+/// when dropped.
+///
+/// The environment consists of the exponent range, the default
+/// precision, the default rounding mode and the exception flags. The
+/// environment is restored even if the thread panics while the guard
+/// is alive. Since the environment is thread local, the guard cannot
+/// be sent to other threads.
+///
+/// The environment can be changed using the methods of the guard, or
+/// directly using functions such as [`set_emin`](fn.set_emin.html) while
+/// the guard is alive. Guards can be nested, in which case they should
+/// be dropped in the reverse order of their creation.
+///
+/// See also [`with_env`](fn.with_env.html).
+///
+/// # Examples
+///
+/// ```rust
+/// use gmp_mpfr_sys::mpfr::{self, EnvGuard};
+/// unsafe {
+///     let emin = mpfr::get_emin();
+///     {
+///         let mut guard = EnvGuard::new();
+///         assert!(guard.set_emin(-1021));
+///         guard.set_default_prec(24);
+///         assert_eq!(mpfr::get_emin(), -1021);
+///         assert_eq!(mpfr::get_default_prec(), 24);
+///     }
+///     assert_eq!(mpfr::get_emin(), emin);
+///     assert_eq!(mpfr::get_default_prec(), 53);
+/// }
+/// ```
+#[must_use]
+pub struct EnvGuard {
+    emin: exp_t,
+    emax: exp_t,
+    default_prec: prec_t,
+    default_rounding_mode: rnd_t,
+    flags: flags_t,
+    // the environment belongs to the current thread
+    _not_send: PhantomData<*const ()>,
+}
+
+impl EnvGuard {
+    /// Saves the current environment.
+    pub fn new() -> EnvGuard {
+        unsafe {
+            EnvGuard {
+                emin: get_emin(),
+                emax: get_emax(),
+                default_prec: get_default_prec(),
+                default_rounding_mode: get_default_rounding_mode(),
+                flags: flags_save(),
+                _not_send: PhantomData,
+            }
+        }
+    }
+
+    /// Sets the minimum exponent.
+    ///
+    /// Returns `false` and leaves the minimum exponent unchanged if
+    /// `exp` is outside the range allowed by MPFR.
+    ///
+    /// See: [`mpfr_set_emin`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005femin)
+    #[inline]
+    pub fn set_emin(&mut self, exp: exp_t) -> bool {
+        unsafe { set_emin(exp) == 0 }
+    }
+
+    /// Sets the maximum exponent.
+    ///
+    /// Returns `false` and leaves the maximum exponent unchanged if
+    /// `exp` is outside the range allowed by MPFR.
+    ///
+    /// See: [`mpfr_set_emax`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005femax)
+    #[inline]
+    pub fn set_emax(&mut self, exp: exp_t) -> bool {
+        unsafe { set_emax(exp) == 0 }
+    }
+
+    /// Sets the default precision.
+    ///
+    /// See: [`mpfr_set_default_prec`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005fdefault_005fprec)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prec` is not in the range
+    /// <code>[PREC_MIN](constant.PREC_MIN.html)..=[PREC_MAX](constant.PREC_MAX.html)</code>.
+    #[inline]
+    pub fn set_default_prec(&mut self, prec: prec_t) {
+        assert!(
+            (PREC_MIN..=PREC_MAX).contains(&prec),
+            "precision out of range"
+        );
+        unsafe { set_default_prec(prec) }
+    }
+
+    /// Sets the default rounding mode.
+    ///
+    /// See: [`mpfr_set_default_rounding_mode`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fset_005fdefault_005frounding_005fmode)
+    #[inline]
+    pub fn set_default_rounding_mode(&mut self, rnd: rnd_t) {
+        unsafe { set_default_rounding_mode(rnd) }
+    }
+
+    /// Sets the exception flags to `flags`, clearing the other flags.
+    ///
+    /// See: [`mpfr_flags_restore`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fflags_005frestore)
+    #[inline]
+    pub fn set_flags(&mut self, flags: Flags) {
+        unsafe { flags_restore(flags.0, FLAGS_ALL) }
+    }
+}
+
+impl Default for EnvGuard {
+    #[inline]
+    fn default() -> EnvGuard {
+        EnvGuard::new()
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        unsafe {
+            set_emin(self.emin);
+            set_emax(self.emax);
+            set_default_prec(self.default_prec);
+            set_default_rounding_mode(self.default_rounding_mode);
+            flags_restore(self.flags, FLAGS_ALL);
+        }
+    }
+}
+
+impl Debug for EnvGuard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("EnvGuard")
+            .field("emin", &self.emin)
+            .field("emax", &self.emax)
+            .field("default_prec", &self.default_prec)
+            .field("default_rounding_mode", &self.default_rounding_mode)
+            .field("flags", &Flags(self.flags))
+            .finish()
+    }
+}
+
+/// Runs a closure and then restores the thread-local MPFR environment.
+///
+/// The closure can change the exponent range, the default precision,
+/// the default rounding mode and the exception flags, and they are
+/// restored when it returns or panics. This is equivalent to calling
+/// the closure while an [`EnvGuard`](struct.EnvGuard.html) is alive.
+///
+/// # Examples
+///
+/// ```rust
+/// use gmp_mpfr_sys::mpfr;
+/// let emax = unsafe { mpfr::get_emax() };
+/// let inner = mpfr::with_env(|| unsafe {
+///     mpfr::set_emax(1024);
+///     mpfr::get_emax()
+/// });
+/// assert_eq!(inner, 1024);
+/// assert_eq!(unsafe { mpfr::get_emax() }, emax);
+/// ```
+pub fn with_env<R, F: FnOnce() -> R>(f: F) -> R {
+    let _guard = EnvGuard::new();
+    f()
+}
+
+// Ziv Loop
+
+// A working variable that is cleared on drop, so that it is cleared
+// even if there is a panic.
+struct TempVar(mpfr_t);
+
+impl TempVar {
+    // The value is NaN.
+    #[inline]
+    fn new(prec: prec_t) -> TempVar {
+        unsafe {
+            let mut var = MaybeUninit::uninit();
+            init2(var.as_mut_ptr(), prec);
+            TempVar(var.assume_init())
+        }
+    }
+}
+
+impl Drop for TempVar {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { clear(&mut self.0) }
+    }
+}
+
+/// Computes a correctly rounded result using Ziv’s strategy.
+///
+/// The closure `approx` is called with a working variable and its
+/// precision, which is larger than the precision of `rop`. It must
+/// store an approximation of the result in the working variable, and
+/// return `err` such that the error of the approximation is at most
+/// 2<sup>EXP − `err`</sup>, where EXP is the exponent of the
+/// approximation, as for [`can_round`](fn.can_round.html). If the
+/// approximation cannot be rounded correctly to the precision of `rop`
+/// with the rounding mode `rnd`, the working precision is increased
+/// and the closure is called again.
+///
+/// When the approximation can be rounded correctly, or when it is
+/// NaN, infinite or zero, it is rounded into `rop`, and the ternary
+/// value is returned. For rounding to nearest, the ternary value is
+/// correct too.
+///
+/// See: [`mpfr_can_round`](../C/MPFR/constant.MPFR_Interface.html#index-mpfr_005fcan_005fround)
+///
+/// # Panics
+///
+/// Panics if the working precision would have to exceed
+/// [`PREC_MAX`](constant.PREC_MAX.html). This can happen if the exact
+/// result is representable in the precision of `rop`, or lies exactly
+/// halfway between two representable numbers when rounding to nearest,
+/// and the closure does not return a large enough `err` to indicate
+/// this.
+///
+/// # Safety
+///
+/// `rop` must point to an initialized value, and the closure must
+/// only store a value in the working variable it is given, without
+/// reinitializing or clearing it.
+///
+/// # Examples
+///
+/// ```rust
+/// use core::mem::MaybeUninit;
+/// use gmp_mpfr_sys::mpfr::{self, rnd_t};
+/// unsafe {
+///     let mut rop = MaybeUninit::uninit();
+///     mpfr::init2(rop.as_mut_ptr(), 53);
+///     let mut rop = rop.assume_init();
+///     // √2 + √3, where each of the three operations has an error of
+///     // at most half an ulp, so the error is less than 2 ulp
+///     let dir = mpfr::ziv(&mut rop, rnd_t::RNDN, |x, wprec| {
+///         let mut t = MaybeUninit::uninit();
+///         mpfr::init2(t.as_mut_ptr(), wprec);
+///         let mut t = t.assume_init();
+///         mpfr::sqrt_ui(x, 2, rnd_t::RNDN);
+///         mpfr::sqrt_ui(&mut t, 3, rnd_t::RNDN);
+///         mpfr::add(x, x, &t, rnd_t::RNDN);
+///         mpfr::clear(&mut t);
+///         wprec - 2
+///     });
+///     assert_ne!(dir, 0);
+///     assert_eq!(mpfr::get_d(&rop, rnd_t::RNDN), 2f64.sqrt() + 3f64.sqrt());
+///     mpfr::clear(&mut rop);
+/// }
+/// ```
+pub unsafe fn ziv<F>(rop: mpfr_ptr, rnd: rnd_t, mut approx: F) -> c_int
+where
+    F: FnMut(mpfr_ptr, prec_t) -> exp_t,
+{
+    let prec = unsafe { get_prec(rop) };
+    // one more bit for rounding to nearest gives a correct ternary value
+    let check_prec = match rnd {
+        rnd_t::RNDN => prec + 1,
+        _ => prec,
+    };
+    let log2 = (mem::size_of::<prec_t>() * 8) as prec_t - prec.leading_zeros() as prec_t;
+    let mut wprec = prec + log2 + 10;
+    let mut increment = NUMB_BITS as prec_t;
+    let mut var = TempVar::new(wprec);
+    loop {
+        let err = approx(&mut var.0, wprec);
+        unsafe {
+            if regular_p(&var.0) == 0
+                || can_round(&var.0, err, rnd_t::RNDN, rnd_t::RNDZ, check_prec) != 0
+            {
+                return set(rop, &var.0, rnd);
+            }
+        }
+        assert!(
+            wprec <= PREC_MAX - increment,
+            "Ziv loop exceeded the maximum precision"
+        );
+        wprec += increment;
+        increment = wprec / 2;
+        unsafe { set_prec(&mut var.0, wprec) };
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::mpfr;
@@ -1574,6 +2180,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_exp_defaults() {
+        // tests run in new threads, which start with the default range
+        unsafe {
+            assert_eq!(mpfr::get_emin(), mpfr::EMIN_DEFAULT);
+            assert_eq!(mpfr::get_emax(), mpfr::EMAX_DEFAULT);
+        }
+    }
+
+    #[test]
+    fn check_flags() {
+        use mpfr::Flags;
+
+        let both = Flags::UNDERFLOW | Flags::ERANGE;
+        assert_eq!(both.bits(), mpfr::FLAGS_UNDERFLOW | mpfr::FLAGS_ERANGE);
+        assert!(both.contains(Flags::ERANGE) && !both.contains(Flags::ALL));
+        assert_eq!(both - Flags::ERANGE, Flags::UNDERFLOW);
+        assert_eq!(!both & Flags::ERANGE, Flags::EMPTY);
+        assert_eq!(!Flags::EMPTY, Flags::ALL);
+    }
+
+    #[test]
+    fn check_env_guard_restores_on_panic() {
+        extern crate std;
+
+        let emax = unsafe { mpfr::get_emax() };
+        let result = std::panic::catch_unwind(|| {
+            let mut guard = mpfr::EnvGuard::new();
+            assert!(guard.set_emax(100));
+            panic!("unwinding with a modified environment");
+        });
+        assert!(result.is_err());
+        assert_eq!(unsafe { mpfr::get_emax() }, emax);
+    }
+
+    #[test]
+    fn check_ziv_increases_precision() {
+        unsafe {
+            let mut rop = MaybeUninit::uninit();
+            mpfr::init2(rop.as_mut_ptr(), 53);
+            let mut rop = rop.assume_init();
+            let mut calls = 0;
+            let mut last_wprec = 0;
+            let dir = mpfr::ziv(&mut rop, mpfr::rnd_t::RNDN, |x, wprec| {
+                assert!(wprec > last_wprec);
+                calls += 1;
+                last_wprec = wprec;
+                mpfr::const_pi(x, mpfr::rnd_t::RNDN);
+                // pretend the approximation is poor at low precision
+                if wprec < 200 {
+                    10
+                } else {
+                    wprec - 1
+                }
+            });
+            assert!(calls > 1 && last_wprec >= 200);
+            assert!(dir < 0);
+            assert_eq!(mpfr::get_d(&rop, mpfr::rnd_t::RNDN), core::f64::consts::PI);
+            mpfr::clear(&mut rop);
+        }
+    }
+
     #[test]
     fn check_round_nearest_away() {
         unsafe {
@@ -0,0 +1,487 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+/*!
+Formatting of [MPFR] floating-point numbers using [`core::fmt`].
+
+[`Float`] wraps a reference to an [`mpfr_t`] together with a rounding
+mode, and implements [`Display`], [`Binary`], [`Octal`], [`LowerHex`]
+and [`UpperHex`] using positional notation, and [`LowerExp`] and
+[`UpperExp`] using decimal scientific notation.
+
+If no precision is specified, enough significant digits are written
+for the number to be read back exactly, as given by
+[`get_str_ndigits`], with trailing zeros removed. If a precision is
+specified, it is the number of digits after the point, and the number
+is rounded using the rounding mode. The width, fill, alignment, sign
+(`+`), alternate (`#`) and zero padding (`0`) flags are supported as
+for primitive floating-point numbers; the alternate flag writes the
+prefixes `0b`, `0o` and `0x`.
+
+The digits are obtained using [`get_str`] into a buffer on the stack,
+and memory is only allocated for a large number of digits, using the
+[GMP] memory functions. The MPFR flags are not modified.
+
+# Examples
+
+```rust
+use core::mem::MaybeUninit;
+use gmp_mpfr_sys::mpfr::{self, display::Float, rnd_t};
+unsafe {
+    let mut f = MaybeUninit::uninit();
+    mpfr::init2(f.as_mut_ptr(), 53);
+    let mut f = f.assume_init();
+    mpfr::set_d(&mut f, -1234.5, rnd_t::RNDN);
+    assert_eq!(format!("{}", Float(&f, rnd_t::RNDN)), "-1234.5");
+    assert_eq!(format!("{:.0}", Float(&f, rnd_t::RNDN)), "-1234");
+    assert_eq!(format!("{:.0}", Float(&f, rnd_t::RNDA)), "-1235");
+    assert_eq!(format!("{:>10.2}", Float(&f, rnd_t::RNDN)), "  -1234.50");
+    assert_eq!(format!("{:e}", Float(&f, rnd_t::RNDN)), "-1.2345e3");
+    assert_eq!(format!("{:#x}", Float(&f, rnd_t::RNDN)), "-0x4d2.8");
+    mpfr::clear(&mut f);
+}
+```
+
+[GMP]: https://gmplib.org/
+[MPFR]: https://www.mpfr.org/
+[`Binary`]: https://doc.rust-lang.org/core/fmt/trait.Binary.html
+[`Display`]: https://doc.rust-lang.org/core/fmt/trait.Display.html
+[`Float`]: struct.Float.html
+[`LowerExp`]: https://doc.rust-lang.org/core/fmt/trait.LowerExp.html
+[`LowerHex`]: https://doc.rust-lang.org/core/fmt/trait.LowerHex.html
+[`Octal`]: https://doc.rust-lang.org/core/fmt/trait.Octal.html
+[`UpperExp`]: https://doc.rust-lang.org/core/fmt/trait.UpperExp.html
+[`UpperHex`]: https://doc.rust-lang.org/core/fmt/trait.UpperHex.html
+[`core::fmt`]: https://doc.rust-lang.org/core/fmt/index.html
+[`get_str_ndigits`]: ../fn.get_str_ndigits.html
+[`get_str`]: ../fn.get_str.html
+[`mpfr_t`]: ../struct.mpfr_t.html
+*/
+
+use crate::gmp::display::{self as gmp_display, GmpBuf};
+use crate::mpfr::{self, exp_t, mpfr_t, rnd_t, FlagsRestorer};
+use core::{
+    cmp,
+    fmt::{
+        Binary, Display, Formatter, LowerExp, LowerHex, Octal, Result as FmtResult, UpperExp,
+        UpperHex,
+    },
+    slice, str,
+};
+use libc::{c_char, c_int};
+
+/// Formats an [`mpfr_t`](../struct.mpfr_t.html) using a rounding
+/// mode.
+///
+/// See the [module documentation](index.html) for an example.
+#[derive(Clone, Copy, Debug)]
+pub struct Float<'a>(pub &'a mpfr_t, pub rnd_t);
+
+impl Display for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, 10, "")
+    }
+}
+
+impl Binary for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, 2, "0b")
+    }
+}
+
+impl Octal for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, 8, "0o")
+    }
+}
+
+impl LowerHex for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, 16, "0x")
+    }
+}
+
+impl UpperHex for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, -16, "0x")
+    }
+}
+
+impl LowerExp for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, 10, "e")
+    }
+}
+
+impl UpperExp for Float<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        fmt_float(self, f, -10, "E")
+    }
+}
+
+// The number of bytes for digits on the stack.
+const STACK_DIGITS: usize = 128;
+
+// The parts of a formatted number after its sign and prefix:
+// int, int_zeros zeros, then if point is true a point, frac_zeros
+// zeros, frac, frac_trailing zeros, then if exp is Some the exponent
+// marker and exponent.
+#[derive(Default)]
+struct Parts<'a> {
+    int: &'a str,
+    int_zeros: usize,
+    point: bool,
+    frac_zeros: usize,
+    frac: &'a str,
+    frac_trailing: usize,
+    exp: Option<(&'a str, exp_t)>,
+}
+
+impl Parts<'_> {
+    fn len(&self) -> usize {
+        let mut len = self.int.len() + self.int_zeros;
+        if self.point {
+            len += 1 + self.frac_zeros + self.frac.len() + self.frac_trailing;
+        }
+        if let Some((marker, exp)) = self.exp {
+            len += marker.len() + exp_len(exp);
+        }
+        len
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.int)?;
+        gmp_display::write_zeros(f, self.int_zeros)?;
+        if self.point {
+            f.write_str(".")?;
+            gmp_display::write_zeros(f, self.frac_zeros)?;
+            f.write_str(self.frac)?;
+            gmp_display::write_zeros(f, self.frac_trailing)?;
+        }
+        if let Some((marker, exp)) = self.exp {
+            f.write_str(marker)?;
+            write!(f, "{}", exp)?;
+        }
+        Ok(())
+    }
+}
+
+fn exp_len(exp: exp_t) -> usize {
+    let mut len = if exp < 0 { 2 } else { 1 };
+    let mut rest = exp / 10;
+    while rest != 0 {
+        len += 1;
+        rest /= 10;
+    }
+    len
+}
+
+// The base is negative for upper-case digits. For LowerExp and UpperExp
+// the prefix is the exponent marker instead.
+fn fmt_float(x: &Float<'_>, f: &mut Formatter<'_>, base: c_int, prefix: &str) -> FmtResult {
+    let (op, rnd) = (x.0, x.1);
+    let is_exp = prefix == "e" || prefix == "E";
+    let _restorer = FlagsRestorer(unsafe { mpfr::flags_save() });
+    if unsafe { mpfr::nan_p(op) } != 0 {
+        return gmp_display::write_padded(f, None, "", 3, false, |f| f.write_str("NaN"));
+    }
+    let neg = unsafe { mpfr::signbit(op) } != 0;
+    if unsafe { mpfr::inf_p(op) } != 0 {
+        return gmp_display::write_padded(f, Some(neg), "", 3, false, |f| f.write_str("inf"));
+    }
+    let prec = f.precision();
+    if unsafe { mpfr::zero_p(op) } != 0 {
+        let parts = Parts {
+            int: "0",
+            point: prec.unwrap_or(0) > 0,
+            frac_trailing: prec.unwrap_or(0),
+            exp: if is_exp { Some((prefix, 0)) } else { None },
+            ..Parts::default()
+        };
+        return write_parts(f, neg, if is_exp { "" } else { prefix }, &parts);
+    }
+    if is_exp {
+        let n = match prec {
+            Some(p) => p + 1,
+            None => unsafe { mpfr::get_str_ndigits(10, mpfr::get_prec(op)) },
+        };
+        return with_digits(op, base, n, rnd, |digits, e| {
+            let len = if prec.is_some() {
+                n
+            } else {
+                trimmed_len(&digits[..n])
+            };
+            let digits = unsafe { str::from_utf8_unchecked(&digits[..len]) };
+            let parts = Parts {
+                int: &digits[..1],
+                point: len > 1,
+                frac: &digits[1..],
+                exp: Some((prefix, e - 1)),
+                ..Parts::default()
+            };
+            write_parts(f, neg, "", &parts)
+        });
+    }
+    let p = match prec {
+        Some(p) => p,
+        None => {
+            let n = unsafe { mpfr::get_str_ndigits(base.abs(), mpfr::get_prec(op)) };
+            return with_digits(op, base, n, rnd, |digits, e| {
+                let len = trimmed_len(&digits[..n]);
+                let digits = unsafe { str::from_utf8_unchecked(&digits[..len]) };
+                write_parts(f, neg, prefix, &positional(digits, e))
+            });
+        }
+    };
+    // Truncating to one digit cannot carry, so this gives the exact
+    // exponent.
+    let (e0, first) = with_digits(op, base, 1, rnd_t::RNDZ, |digits, e| (e, digits[0]));
+    let int_digits = if e0 > 0 { e0 as usize } else { 0 };
+    let skip = if e0 < 0 { (-e0) as usize } else { 0 };
+    if int_digits + p <= skip {
+        // |op| < base^-p, so the result is either 0 or base^-p.
+        let up = match rnd {
+            rnd_t::RNDZ => false,
+            rnd_t::RNDA => true,
+            rnd_t::RNDU => !neg,
+            rnd_t::RNDD => neg,
+            // a tie is rounded to even zero
+            _ => above_half_unit(op, neg, base.abs(), p, skip, first),
+        };
+        let one = if up { "1" } else { "0" };
+        let parts = if p == 0 {
+            Parts {
+                int: one,
+                ..Parts::default()
+            }
+        } else {
+            Parts {
+                int: "0",
+                point: true,
+                frac_zeros: p - 1,
+                frac: one,
+                ..Parts::default()
+            }
+        };
+        return write_parts(f, neg, prefix, &parts);
+    }
+    let n = int_digits + p - skip;
+    with_digits(op, base, n, rnd, |digits, e| {
+        // If rounding carried into a new digit, the digits are 100…0
+        // with one less digit after the point.
+        let len = if e == e0 {
+            n
+        } else {
+            digits[n] = b'0';
+            n + 1
+        };
+        let digits = unsafe { str::from_utf8_unchecked(&digits[..len]) };
+        write_parts(f, neg, prefix, &positional(digits, e))
+    })
+}
+
+// Splits the digits 0.ddd × base^e at the point.
+fn positional(digits: &str, e: exp_t) -> Parts<'_> {
+    if e <= 0 {
+        Parts {
+            int: "0",
+            point: true,
+            frac_zeros: (-e) as usize,
+            frac: digits,
+            ..Parts::default()
+        }
+    } else if e as usize >= digits.len() {
+        Parts {
+            int: digits,
+            int_zeros: e as usize - digits.len(),
+            ..Parts::default()
+        }
+    } else {
+        Parts {
+            int: &digits[..e as usize],
+            point: true,
+            frac: &digits[e as usize..],
+            ..Parts::default()
+        }
+    }
+}
+
+fn write_parts(f: &mut Formatter<'_>, neg: bool, prefix: &str, parts: &Parts<'_>) -> FmtResult {
+    gmp_display::write_padded(f, Some(neg), prefix, parts.len(), true, |f| parts.write(f))
+}
+
+fn trimmed_len(digits: &[u8]) -> usize {
+    // The first digit is never zero.
+    let mut len = digits.len();
+    while len > 1 && digits[len - 1] == b'0' {
+        len -= 1;
+    }
+    len
+}
+
+// Checks whether |op| > 0.5 × base^-p, where |op| < base^-p and skip
+// zeros follow the point. The inexact flag cannot be used to detect a
+// tie, as get_str does not set it for bases that are powers of two.
+fn above_half_unit(op: &mpfr_t, neg: bool, base: c_int, p: usize, skip: usize, first: u8) -> bool {
+    if base == 10 && p > 0 {
+        // 0.5 × 10^-p is not a binary number, so it is never equal to
+        // op, and it is 5 at position -p-1.
+        return p == skip && first >= b'5';
+    }
+    // 0.5 × base^-p is a power of two, so it is compared exactly.
+    let bits_per_digit = if base == 10 { 1 } else { base.trailing_zeros() };
+    let half_exp = -(p as exp_t) * bits_per_digit as exp_t - 1;
+    let cmp = unsafe { mpfr::cmp_si_2exp(op, if neg { -1 } else { 1 }, half_exp) };
+    if neg {
+        cmp < 0
+    } else {
+        cmp > 0
+    }
+}
+
+// Calls get_str to get n digits of op, then calls g with the digits
+// without any sign and the exponent. The slice passed to g has space
+// for at least one more digit.
+fn with_digits<R, G>(op: &mpfr_t, base: c_int, n: usize, rnd: rnd_t, g: G) -> R
+where
+    G: FnOnce(&mut [u8], exp_t) -> R,
+{
+    let cap = cmp::max(n + 2, 7);
+    let mut stack = [0u8; STACK_DIGITS];
+    let mut heap;
+    let buf = if cap <= STACK_DIGITS {
+        &mut stack[..cap]
+    } else {
+        heap = GmpBuf::new(cap);
+        unsafe { slice::from_raw_parts_mut(heap.as_mut_ptr(), cap) }
+    };
+    let mut e: exp_t = 0;
+    unsafe {
+        mpfr::get_str(buf.as_mut_ptr() as *mut c_char, &mut e, base, n, op, rnd);
+    }
+    let start = if buf[0] == b'-' { 1 } else { 0 };
+    g(&mut buf[start..], e)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mpfr::{self, display::Float, rnd_t};
+    use core::{
+        fmt::{Arguments, Result as FmtResult, Write},
+        mem::MaybeUninit,
+        str,
+    };
+
+    struct Buf {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    impl Write for Buf {
+        fn write_str(&mut self, s: &str) -> FmtResult {
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    fn check(expected: &str, args: Arguments<'_>) {
+        let mut buf = Buf {
+            bytes: [0; 256],
+            len: 0,
+        };
+        buf.write_fmt(args).unwrap();
+        assert_eq!(str::from_utf8(&buf.bytes[..buf.len]).unwrap(), expected);
+    }
+
+    #[test]
+    fn check_special() {
+        unsafe {
+            let mut f = MaybeUninit::uninit();
+            mpfr::init2(f.as_mut_ptr(), 53);
+            let mut f = f.assume_init();
+            let n = rnd_t::RNDN;
+            check("NaN", format_args!("{:+}", Float(&f, n)));
+            check("  NaN", format_args!("{:05}", Float(&f, n)));
+            mpfr::set_inf(&mut f, -1);
+            check("-inf", format_args!("{:.3e}", Float(&f, n)));
+            mpfr::set_zero(&mut f, -1);
+            check("-0", format_args!("{}", Float(&f, n)));
+            check("-0.00", format_args!("{:+.2}", Float(&f, n)));
+            mpfr::set_zero(&mut f, 1);
+            check("+0.00", format_args!("{:+.2}", Float(&f, n)));
+            check("0.0e0", format_args!("{:.1e}", Float(&f, n)));
+            assert_eq!(mpfr::flags_save(), 0);
+            mpfr::clear(&mut f);
+        }
+    }
+
+    #[test]
+    fn check_rounding() {
+        unsafe {
+            let mut f = MaybeUninit::uninit();
+            mpfr::init2(f.as_mut_ptr(), 53);
+            let mut f = f.assume_init();
+            let (n, u, d) = (rnd_t::RNDN, rnd_t::RNDU, rnd_t::RNDD);
+            mpfr::set_d(&mut f, 2.5, n);
+            check("2", format_args!("{:.0}", Float(&f, n)));
+            check("3", format_args!("{:.0}", Float(&f, u)));
+            mpfr::set_d(&mut f, 0.5, n);
+            check("0", format_args!("{:.0}", Float(&f, n)));
+            check("1", format_args!("{:.0}", Float(&f, u)));
+            mpfr::set_d(&mut f, 0.75, n);
+            check("1", format_args!("{:.0}", Float(&f, n)));
+            check("0", format_args!("{:.0}", Float(&f, d)));
+            mpfr::set_d(&mut f, 0.004, n);
+            check("0.00", format_args!("{:.2}", Float(&f, n)));
+            check("0.01", format_args!("{:.2}", Float(&f, u)));
+            check("0.0040", format_args!("{:.4}", Float(&f, n)));
+            mpfr::set_d(&mut f, -0.006, n);
+            check("-0.01", format_args!("{:.2}", Float(&f, n)));
+            check("-0.00", format_args!("{:.2}", Float(&f, u)));
+            mpfr::set_d(&mut f, 9.96, n);
+            check("10.0", format_args!("{:.1}", Float(&f, n)));
+            check("9.9", format_args!("{:.1}", Float(&f, d)));
+            check("1.0e1", format_args!("{:.1e}", Float(&f, n)));
+            mpfr::set_d(&mut f, 3.125, n);
+            check("+0003.12", format_args!("{:+08.2}", Float(&f, n)));
+            check("3.13**", format_args!("{:*<6.2}", Float(&f, rnd_t::RNDA)));
+            mpfr::set_d(&mut f, 0.75, n);
+            check("1", format_args!("{:.0b}", Float(&f, n)));
+            check("1", format_args!("{:.0o}", Float(&f, n)));
+            mpfr::set_d(&mut f, -0.53125, n);
+            check("-1", format_args!("{:.0x}", Float(&f, n)));
+            mpfr::set_d(&mut f, 0.5, n);
+            check("0", format_args!("{:.0x}", Float(&f, n)));
+            mpfr::set_d(&mut f, 0.001953125, n);
+            check("0.00", format_args!("{:.2x}", Float(&f, n)));
+            mpfr::set_d(&mut f, 0.0019531251, n);
+            check("0.01", format_args!("{:.2x}", Float(&f, n)));
+            mpfr::set_d(&mut f, 255.5, n);
+            check("0xff.8", format_args!("{:#x}", Float(&f, n)));
+            check("100", format_args!("{:.0X}", Float(&f, u)));
+            check("11111111.1", format_args!("{:b}", Float(&f, n)));
+            check("2.555E2", format_args!("{:E}", Float(&f, n)));
+            mpfr::set_d(&mut f, 1e20, n);
+            check("100000000000000000000", format_args!("{}", Float(&f, n)));
+            mpfr::set_d(&mut f, 0.0009765625, n);
+            check("0.0009765625", format_args!("{}", Float(&f, n)));
+            check("9.765625e-4", format_args!("{:e}", Float(&f, n)));
+            assert_eq!(mpfr::flags_save(), 0);
+            mpfr::clear(&mut f);
+        }
+    }
+}
@@ -0,0 +1,551 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+/*!
+Emulation of IEEE 754 binary floating-point formats.
+
+A [`Context`] restricts the exponent range to that of a [`Format`],
+and its arithmetic methods round the result to the precision of the
+format, including subnormal numbers, using [`check_range`] and
+[`subnormalize`] as described in [Exceptions]. The methods return the
+exception flags that IEEE 754 hardware would raise.
+
+The flags are returned as [`Flags`], where [`Flags::NAN`] is the IEEE
+invalid operation flag. It is raised by invalid operations such as
+0 ÷ 0 and the square root of a negative number. MPFR has no signaling
+NaNs, so a NaN operand is a quiet NaN, which gives a NaN result
+without raising the flag, even though MPFR raises its own NaN flag in
+that case. The underflow flag follows IEEE 754 default
+exception handling with tininess detected after rounding, that is it
+is raised when the result is inexact and would be smaller in magnitude
+than the smallest normal number if the exponent range were unbounded.
+This is how for example x86-64 processors detect tininess.
+
+# Examples
+
+```rust
+use core::{f32, mem::MaybeUninit};
+use gmp_mpfr_sys::mpfr::{
+    self,
+    ieee::{Context, Format},
+    Flags,
+};
+let a = f32::MIN_POSITIVE;
+let b = 3.0f32;
+let ctx = Context::new(Format::Binary32);
+unsafe {
+    let mut x = MaybeUninit::uninit();
+    ctx.init(x.as_mut_ptr());
+    let mut x = x.assume_init();
+    let mut y = MaybeUninit::uninit();
+    ctx.init(y.as_mut_ptr());
+    let mut y = y.assume_init();
+    ctx.set_d(&mut x, a.into());
+    ctx.set_d(&mut y, b.into());
+    // the quotient is subnormal
+    let (_, flags) = ctx.div(&mut x, &x, &y);
+    assert_eq!(flags, Flags::UNDERFLOW | Flags::INEXACT);
+    let q = mpfr::get_d(&x, mpfr::rnd_t::RNDN) as f32;
+    assert_eq!(q.to_bits(), (a / b).to_bits());
+    mpfr::clear(&mut x);
+    mpfr::clear(&mut y);
+}
+```
+
+[Exceptions]: ../../C/MPFR/constant.MPFR_Interface.html#Exception-Related-Functions
+[`Context`]: struct.Context.html
+[`Flags::NAN`]: ../struct.Flags.html#associatedconstant.NAN
+[`Flags`]: ../struct.Flags.html
+[`Format`]: enum.Format.html
+[`check_range`]: ../fn.check_range.html
+[`subnormalize`]: ../fn.subnormalize.html
+*/
+
+use crate::mpfr::{
+    self, check_range, exp_t, flags_clear, flags_test, get_exp, get_prec, init2, mpfr_t, nan_p,
+    prec_t, regular_p, rnd_t, subnormalize, EnvGuard, Flags, FLAGS_ALL,
+};
+use libc::c_int;
+
+type mpfr_ptr = *mut mpfr_t;
+type mpfr_srcptr = *const mpfr_t;
+
+/// An IEEE 754 binary interchange format, or the bfloat16 format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// The 16-bit binary16 format, with 11 bits of precision.
+    Binary16,
+    /// The 16-bit bfloat16 format, with 8 bits of precision and the
+    /// exponent range of [`Binary32`](#variant.Binary32).
+    Bfloat16,
+    /// The 32-bit binary32 format, with 24 bits of precision.
+    Binary32,
+    /// The 64-bit binary64 format, with 53 bits of precision.
+    Binary64,
+    /// The 128-bit binary128 format, with 113 bits of precision.
+    Binary128,
+}
+
+impl Format {
+    /// Returns the precision in bits, including the implicit bit.
+    #[inline]
+    pub fn prec(self) -> prec_t {
+        match self {
+            Format::Binary16 => 11,
+            Format::Bfloat16 => 8,
+            Format::Binary32 => 24,
+            Format::Binary64 => 53,
+            Format::Binary128 => 113,
+        }
+    }
+
+    // The maximum exponent as defined by IEEE 754, where normal
+    // numbers are in the range [1, 2) × 2^exp.
+    #[inline]
+    fn ieee_emax(self) -> exp_t {
+        match self {
+            Format::Binary16 => 15,
+            Format::Bfloat16 | Format::Binary32 => 127,
+            Format::Binary64 => 1023,
+            Format::Binary128 => 16383,
+        }
+    }
+
+    /// Returns the minimum exponent to use with [`set_emin`].
+    ///
+    /// MPFR numbers are in the range [0.5, 1) × 2<sup>exp</sup>, so
+    /// the smallest positive subnormal number of the format is
+    /// 0.5 × 2<sup>emin</sup>.
+    ///
+    /// [`set_emin`]: ../fn.set_emin.html
+    #[inline]
+    pub fn emin(self) -> exp_t {
+        1 - self.ieee_emax() - self.prec() + 2
+    }
+
+    /// Returns the maximum exponent to use with [`set_emax`].
+    ///
+    /// MPFR numbers are in the range [0.5, 1) × 2<sup>exp</sup>, so
+    /// this is one more than the maximum exponent defined by IEEE 754.
+    ///
+    /// [`set_emax`]: ../fn.set_emax.html
+    #[inline]
+    pub fn emax(self) -> exp_t {
+        self.ieee_emax() + 1
+    }
+}
+
+/// An emulation context for a [`Format`](enum.Format.html).
+///
+/// Creating a context sets the exponent range of the current thread
+/// to that of the format, and dropping it restores the previous
+/// environment like an [`EnvGuard`](../struct.EnvGuard.html). The
+/// exception flags are also restored; the flags raised by each
+/// operation are returned by its method instead.
+///
+/// The destination of an operation must have the precision of the
+/// format, which is the case if it was initialized using
+/// [`init`](#method.init). The operands should be values of the
+/// format, for example the results of earlier operations.
+#[derive(Debug)]
+pub struct Context {
+    format: Format,
+    rnd: rnd_t,
+    _guard: EnvGuard,
+}
+
+impl Context {
+    /// Creates a context that rounds to nearest with ties to even.
+    pub fn new(format: Format) -> Context {
+        Context::with_rounding(format, rnd_t::RNDN)
+    }
+
+    /// Creates a context that rounds using `rnd`.
+    ///
+    /// The IEEE 754 rounding attributes roundTiesToEven,
+    /// roundTowardZero, roundTowardPositive and roundTowardNegative
+    /// correspond to [`RNDN`], [`RNDZ`], [`RNDU`] and [`RNDD`].
+    ///
+    /// [`RNDD`]: ../enum.rnd_t.html#variant.RNDD
+    /// [`RNDN`]: ../enum.rnd_t.html#variant.RNDN
+    /// [`RNDU`]: ../enum.rnd_t.html#variant.RNDU
+    /// [`RNDZ`]: ../enum.rnd_t.html#variant.RNDZ
+    pub fn with_rounding(format: Format, rnd: rnd_t) -> Context {
+        let mut guard = EnvGuard::new();
+        // the range of each format is well inside the range allowed by MPFR
+        assert!(guard.set_emin(format.emin()));
+        assert!(guard.set_emax(format.emax()));
+        Context {
+            format,
+            rnd,
+            _guard: guard,
+        }
+    }
+
+    /// Returns the format of the context.
+    #[inline]
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the rounding mode of the context.
+    #[inline]
+    pub fn rounding(&self) -> rnd_t {
+        self.rnd
+    }
+
+    /// Initializes `x` with the precision of the format, and sets it
+    /// to NaN.
+    ///
+    /// # Safety
+    ///
+    /// This has the same requirements as [`init2`](../fn.init2.html),
+    /// and `x` must later be cleared using
+    /// [`mpfr::clear`](../fn.clear.html).
+    #[inline]
+    pub unsafe fn init(&self, x: mpfr_ptr) {
+        unsafe { init2(x, self.format.prec()) }
+    }
+
+    // Calls op with the flags cleared, then rounds the result to the
+    // format and finds the IEEE flags. The flags from before the
+    // context was created are restored when it is dropped. A NaN
+    // operand is a quiet NaN, so it does not raise the invalid
+    // operation flag, even though MPFR raises its NaN flag.
+    unsafe fn round<F: FnOnce(rnd_t) -> c_int>(
+        &self,
+        rop: mpfr_ptr,
+        nan_operand: bool,
+        op: F,
+    ) -> (c_int, Flags) {
+        assert_eq!(
+            unsafe { get_prec(rop) },
+            self.format.prec(),
+            "destination does not have the precision of the format"
+        );
+        unsafe {
+            flags_clear(FLAGS_ALL);
+            let t = op(self.rnd);
+            let t = check_range(rop, t, self.rnd);
+            let mut flags = Flags(flags_test(FLAGS_ALL));
+            // tiny after rounding to the precision with an unbounded
+            // exponent range, which is still the case before
+            // subnormalize
+            let tiny = flags.contains(Flags::UNDERFLOW)
+                || (regular_p(rop) != 0 && get_exp(rop) <= 1 - self.format.ieee_emax());
+            let t = subnormalize(rop, t, self.rnd);
+            flags -= Flags::UNDERFLOW;
+            if nan_operand {
+                flags -= Flags::NAN;
+            }
+            if t != 0 {
+                flags |= Flags::INEXACT;
+                if tiny {
+                    flags |= Flags::UNDERFLOW;
+                }
+            }
+            (t, flags)
+        }
+    }
+
+    /// Rounds `op` to the format and stores it in `rop`.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn set(&self, rop: mpfr_ptr, op: mpfr_srcptr) -> (c_int, Flags) {
+        unsafe { self.round(rop, nan_p(op) != 0, |rnd| mpfr::set(rop, op, rnd)) }
+    }
+
+    /// Rounds `op` to the format and stores it in `rop`.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// `rop` must point to an initialized value with the precision of
+    /// the format.
+    #[inline]
+    pub unsafe fn set_d(&self, rop: mpfr_ptr, op: f64) -> (c_int, Flags) {
+        unsafe { self.round(rop, op.is_nan(), |rnd| mpfr::set_d(rop, op, rnd)) }
+    }
+
+    /// Computes `op1` + `op2` in the format.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn add(&self, rop: mpfr_ptr, op1: mpfr_srcptr, op2: mpfr_srcptr) -> (c_int, Flags) {
+        unsafe {
+            self.round(rop, any_nan(&[op1, op2]), |rnd| {
+                mpfr::add(rop, op1, op2, rnd)
+            })
+        }
+    }
+
+    /// Computes `op1` − `op2` in the format.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn sub(&self, rop: mpfr_ptr, op1: mpfr_srcptr, op2: mpfr_srcptr) -> (c_int, Flags) {
+        unsafe {
+            self.round(rop, any_nan(&[op1, op2]), |rnd| {
+                mpfr::sub(rop, op1, op2, rnd)
+            })
+        }
+    }
+
+    /// Computes `op1` × `op2` in the format.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn mul(&self, rop: mpfr_ptr, op1: mpfr_srcptr, op2: mpfr_srcptr) -> (c_int, Flags) {
+        unsafe {
+            self.round(rop, any_nan(&[op1, op2]), |rnd| {
+                mpfr::mul(rop, op1, op2, rnd)
+            })
+        }
+    }
+
+    /// Computes `op1` ÷ `op2` in the format.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn div(&self, rop: mpfr_ptr, op1: mpfr_srcptr, op2: mpfr_srcptr) -> (c_int, Flags) {
+        unsafe {
+            self.round(rop, any_nan(&[op1, op2]), |rnd| {
+                mpfr::div(rop, op1, op2, rnd)
+            })
+        }
+    }
+
+    /// Computes the square root of `op` in the format.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn sqrt(&self, rop: mpfr_ptr, op: mpfr_srcptr) -> (c_int, Flags) {
+        unsafe { self.round(rop, nan_p(op) != 0, |rnd| mpfr::sqrt(rop, op, rnd)) }
+    }
+
+    /// Computes (`op1` × `op2`) + `op3` with a single rounding in the
+    /// format.
+    ///
+    /// Returns the ternary value and the IEEE exception flags.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values, and `rop` must
+    /// have the precision of the format.
+    #[inline]
+    pub unsafe fn fma(
+        &self,
+        rop: mpfr_ptr,
+        op1: mpfr_srcptr,
+        op2: mpfr_srcptr,
+        op3: mpfr_srcptr,
+    ) -> (c_int, Flags) {
+        unsafe {
+            self.round(rop, any_nan(&[op1, op2, op3]), |rnd| {
+                mpfr::fma(rop, op1, op2, op3, rnd)
+            })
+        }
+    }
+}
+
+// The operands are checked before the operation, as the destination
+// can be one of them.
+unsafe fn any_nan(ops: &[mpfr_srcptr]) -> bool {
+    ops.iter().any(|&op| unsafe { nan_p(op) } != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mpfr::{
+        self,
+        ieee::{Context, Format},
+        Flags,
+    };
+    use core::{f32, f64, mem::MaybeUninit};
+
+    #[test]
+    fn check_format_ranges() {
+        // Rust uses the same exponent convention as MPFR
+        let f = Format::Binary32;
+        assert_eq!(f.prec(), f32::MANTISSA_DIGITS as mpfr::prec_t);
+        assert_eq!(f.emax(), f32::MAX_EXP as mpfr::exp_t);
+        assert_eq!(
+            f.emin(),
+            (f32::MIN_EXP - f32::MANTISSA_DIGITS as i32 + 1) as mpfr::exp_t
+        );
+        let f = Format::Binary64;
+        assert_eq!(f.prec(), f64::MANTISSA_DIGITS as mpfr::prec_t);
+        assert_eq!(f.emax(), f64::MAX_EXP as mpfr::exp_t);
+        assert_eq!(
+            f.emin(),
+            (f64::MIN_EXP - f64::MANTISSA_DIGITS as i32 + 1) as mpfr::exp_t
+        );
+        // smallest subnormals are 2^-24, 2^-133 and 2^-16494, and
+        // largest finite numbers are just below 2^16, 2^128 and 2^16384
+        assert_eq!(Format::Binary16.emin(), -23);
+        assert_eq!(Format::Binary16.emax(), 16);
+        assert_eq!(Format::Bfloat16.emin(), -132);
+        assert_eq!(Format::Bfloat16.emax(), 128);
+        assert_eq!(Format::Binary128.emin(), -16493);
+        assert_eq!(Format::Binary128.emax(), 16384);
+    }
+
+    // Sets x to v in the context and returns the flags and the
+    // result.
+    fn set_d(ctx: &Context, v: f64) -> (Flags, f64) {
+        unsafe {
+            let mut x = MaybeUninit::uninit();
+            ctx.init(x.as_mut_ptr());
+            let mut x = x.assume_init();
+            let (_, flags) = ctx.set_d(&mut x, v);
+            let r = mpfr::get_d(&x, mpfr::rnd_t::RNDN);
+            mpfr::clear(&mut x);
+            (flags, r)
+        }
+    }
+
+    #[test]
+    fn check_subnormal_rounding() {
+        let ctx = Context::new(Format::Binary32);
+        let tiny = f64::from(f32::MIN_POSITIVE) / f64::from(1u32 << 23);
+        for &m in &[0.25, 0.5, 0.75, 1.5, 2.5, 3.25, 1000.5, 4097.75] {
+            let v = m * tiny;
+            let (flags, r) = set_d(&ctx, v);
+            assert_eq!(flags, Flags::UNDERFLOW | Flags::INEXACT, "{}", m);
+            assert_eq!((r as f32).to_bits(), (v as f32).to_bits(), "{}", m);
+        }
+        let (flags, r) = set_d(&ctx, 3.0 * tiny);
+        assert_eq!(flags, Flags::EMPTY);
+        assert_eq!(r, 3.0 * tiny);
+        drop(ctx);
+
+        // 2^-25 is halfway between 0 and 2^-24, and rounds to even
+        let ctx = Context::new(Format::Binary16);
+        let tiny = 1.0 / f64::from(1u32 << 24);
+        let (flags, r) = set_d(&ctx, 0.5 * tiny);
+        assert_eq!(flags, Flags::UNDERFLOW | Flags::INEXACT);
+        assert_eq!(r, 0.0);
+        let (flags, r) = set_d(&ctx, 0.75 * tiny);
+        assert_eq!(flags, Flags::UNDERFLOW | Flags::INEXACT);
+        assert_eq!(r, tiny);
+    }
+
+    #[test]
+    fn check_tiny_after_rounding() {
+        let ctx = Context::new(Format::Binary32);
+        let min = f64::from(f32::MIN_POSITIVE);
+        // 2^-126 - 2^-150 fits in 24 bits, so it is tiny after rounding
+        // with an unbounded exponent range, but the subnormal result is
+        // rounded up to 2^-126
+        let v = min - min / f64::from(1u32 << 24);
+        let (flags, r) = set_d(&ctx, v);
+        assert_eq!(flags, Flags::UNDERFLOW | Flags::INEXACT);
+        assert_eq!(r, min);
+        assert_eq!((v as f32).to_bits(), f32::MIN_POSITIVE.to_bits());
+        // 2^-126 - 2^-151 rounds to 2^-126 even with an unbounded
+        // exponent range, so it is not tiny after rounding
+        let v = min - min / f64::from(1u32 << 25);
+        let (flags, r) = set_d(&ctx, v);
+        assert_eq!(flags, Flags::INEXACT);
+        assert_eq!(r, min);
+        assert_eq!((v as f32).to_bits(), f32::MIN_POSITIVE.to_bits());
+    }
+
+    #[test]
+    fn check_nan_flag() {
+        let ctx = Context::new(Format::Binary64);
+        let (flags, r) = set_d(&ctx, f64::NAN);
+        assert_eq!(flags, Flags::EMPTY);
+        assert!(r.is_nan());
+        unsafe {
+            let mut x = MaybeUninit::uninit();
+            ctx.init(x.as_mut_ptr());
+            let mut x = x.assume_init();
+            let mut y = MaybeUninit::uninit();
+            ctx.init(y.as_mut_ptr());
+            let mut y = y.assume_init();
+
+            // quiet NaN operands do not raise the invalid flag
+            ctx.set_d(&mut x, f64::NAN);
+            ctx.set_d(&mut y, 1.0);
+            assert_eq!(ctx.add(&mut y, &x, &y).1, Flags::EMPTY);
+            assert_ne!(mpfr::nan_p(&y), 0);
+            assert_eq!(ctx.sqrt(&mut x, &x).1, Flags::EMPTY);
+
+            // invalid operations raise it
+            ctx.set_d(&mut x, -1.0);
+            assert_eq!(ctx.sqrt(&mut y, &x).1, Flags::NAN);
+            ctx.set_d(&mut x, 0.0);
+            assert_eq!(ctx.div(&mut y, &x, &x).1, Flags::NAN);
+            assert_ne!(mpfr::nan_p(&y), 0);
+
+            mpfr::clear(&mut x);
+            mpfr::clear(&mut y);
+        }
+    }
+
+    #[test]
+    fn check_binary64_overflow() {
+        let emax = unsafe { mpfr::get_emax() };
+        {
+            let ctx = Context::new(Format::Binary64);
+            unsafe {
+                let mut x = MaybeUninit::uninit();
+                ctx.init(x.as_mut_ptr());
+                let mut x = x.assume_init();
+                ctx.set_d(&mut x, f64::MAX);
+                let (dir, flags) = ctx.add(&mut x, &x, &x);
+                assert!(dir > 0);
+                assert_eq!(flags, Flags::OVERFLOW | Flags::INEXACT);
+                assert_eq!(mpfr::get_d(&x, mpfr::rnd_t::RNDN), f64::INFINITY);
+                mpfr::clear(&mut x);
+            }
+        }
+        assert_eq!(unsafe { mpfr::get_emax() }, emax);
+    }
+}
@@ -0,0 +1,715 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+/*!
+Interval arithmetic using MPFR directed rounding.
+
+An [`Interval`] is a closed interval of real numbers between two MPFR
+endpoints. Its operations round the lower endpoint of the result down
+with [`RNDD`] and the upper endpoint up with [`RNDU`], so that the
+result contains every value the operation can take for any values of
+the operands inside their intervals.
+
+Intervals can be unbounded, with infinite endpoints, and can be empty,
+for example the result of [`log`] of an interval of
+negative numbers. An operation with an empty operand gives an empty
+result. Functions that are only defined for part of the real numbers
+operate on the part of the operand inside their domain.
+
+# Examples
+
+```rust
+use gmp_mpfr_sys::mpfr::{self, interval::Interval, rnd_t};
+let x = Interval::from_f64(64, 1.0, 2.0);
+let y = x.sin().mul(&x.exp());
+assert!(y.contains_f64(1.5f64.sin() * 1.5f64.exp()));
+// sin is not monotonic in [1, 2], as sin(π/2) = 1
+let s = x.sin();
+unsafe {
+    assert_eq!(mpfr::get_d(s.hi(), rnd_t::RNDU), 1.0);
+}
+assert!(Interval::from_f64(64, -2.0, -1.0).log().is_empty());
+```
+
+[`Interval`]: struct.Interval.html
+[`RNDD`]: ../enum.rnd_t.html#variant.RNDD
+[`RNDU`]: ../enum.rnd_t.html#variant.RNDU
+[`log`]: struct.Interval.html#method.log
+*/
+
+use crate::mpfr::{self, exp_t, mpfr_t, prec_t, rnd_t, TempVar};
+use core::{
+    cmp,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    mem::MaybeUninit,
+};
+use libc::c_int;
+
+/// A closed interval with MPFR endpoints.
+///
+/// The endpoints have the same precision. A non-empty interval has a
+/// lower endpoint that is not +∞ and an upper endpoint that is not −∞,
+/// and the lower endpoint is not greater than the upper endpoint. An
+/// empty interval has NaN endpoints.
+///
+/// Binary operations give results with the larger of the two
+/// precisions.
+///
+/// See the [module documentation](index.html) for an example.
+pub struct Interval {
+    lo: mpfr_t,
+    hi: mpfr_t,
+}
+
+impl Interval {
+    /// Creates an empty interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prec` is not in the range
+    /// <code>[PREC_MIN](../constant.PREC_MIN.html)..=[PREC_MAX](../constant.PREC_MAX.html)</code>.
+    pub fn empty(prec: prec_t) -> Interval {
+        assert!(
+            (mpfr::PREC_MIN..=mpfr::PREC_MAX).contains(&prec),
+            "precision out of range"
+        );
+        unsafe {
+            let mut lo = MaybeUninit::uninit();
+            mpfr::init2(lo.as_mut_ptr(), prec);
+            let mut hi = MaybeUninit::uninit();
+            mpfr::init2(hi.as_mut_ptr(), prec);
+            Interval {
+                lo: lo.assume_init(),
+                hi: hi.assume_init(),
+            }
+        }
+    }
+
+    /// Creates the interval [−∞, +∞].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prec` is not in the range
+    /// <code>[PREC_MIN](../constant.PREC_MIN.html)..=[PREC_MAX](../constant.PREC_MAX.html)</code>.
+    pub fn whole(prec: prec_t) -> Interval {
+        let mut ret = Interval::empty(prec);
+        ret.set_whole();
+        ret
+    }
+
+    /// Creates the smallest interval with precision `prec` that
+    /// contains [`lo`, `hi`].
+    ///
+    /// The interval is empty if `lo` > `hi`, if any of them is NaN, if
+    /// `lo` is +∞ or if `hi` is −∞.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prec` is not in the range
+    /// <code>[PREC_MIN](../constant.PREC_MIN.html)..=[PREC_MAX](../constant.PREC_MAX.html)</code>.
+    pub fn from_f64(prec: prec_t, lo: f64, hi: f64) -> Interval {
+        let mut ret = Interval::empty(prec);
+        unsafe {
+            mpfr::set_d(&mut ret.lo, lo, rnd_t::RNDD);
+            mpfr::set_d(&mut ret.hi, hi, rnd_t::RNDU);
+        }
+        ret.normalize();
+        ret
+    }
+
+    /// Creates the smallest interval with precision `prec` that
+    /// contains [`lo`, `hi`].
+    ///
+    /// The interval is empty if `lo` > `hi`, if any of them is NaN, if
+    /// `lo` is +∞ or if `hi` is −∞.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prec` is not in the range
+    /// <code>[PREC_MIN](../constant.PREC_MIN.html)..=[PREC_MAX](../constant.PREC_MAX.html)</code>.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must point to initialized values.
+    pub unsafe fn from_mpfr(prec: prec_t, lo: *const mpfr_t, hi: *const mpfr_t) -> Interval {
+        let mut ret = Interval::empty(prec);
+        unsafe {
+            mpfr::set(&mut ret.lo, lo, rnd_t::RNDD);
+            mpfr::set(&mut ret.hi, hi, rnd_t::RNDU);
+        }
+        ret.normalize();
+        ret
+    }
+
+    /// Returns the precision of the endpoints.
+    #[inline]
+    pub fn prec(&self) -> prec_t {
+        unsafe { mpfr::get_prec(&self.lo) }
+    }
+
+    /// Returns the lower endpoint, which is NaN if the interval is
+    /// empty.
+    #[inline]
+    pub fn lo(&self) -> &mpfr_t {
+        &self.lo
+    }
+
+    /// Returns the upper endpoint, which is NaN if the interval is
+    /// empty.
+    #[inline]
+    pub fn hi(&self) -> &mpfr_t {
+        &self.hi
+    }
+
+    /// Returns `true` if the interval is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        unsafe { mpfr::nan_p(&self.lo) != 0 }
+    }
+
+    /// Returns `true` if the interval is not empty and both its
+    /// endpoints are finite.
+    #[inline]
+    pub fn is_bounded(&self) -> bool {
+        unsafe { mpfr::number_p(&self.lo) != 0 && mpfr::number_p(&self.hi) != 0 }
+    }
+
+    /// Returns `true` if the interval contains `x`.
+    pub fn contains_f64(&self, x: f64) -> bool {
+        if self.is_empty() || x.is_nan() {
+            return false;
+        }
+        unsafe { mpfr::cmp_d(&self.lo, x) <= 0 && mpfr::cmp_d(&self.hi, x) >= 0 }
+    }
+
+    /// Returns `true` if the interval contains `x`.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to an initialized value.
+    pub unsafe fn contains(&self, x: *const mpfr_t) -> bool {
+        unsafe {
+            !self.is_empty()
+                && mpfr::nan_p(x) == 0
+                && mpfr::lessequal_p(&self.lo, x) != 0
+                && mpfr::lessequal_p(x, &self.hi) != 0
+        }
+    }
+
+    /// Returns −`self`.
+    pub fn neg(&self) -> Interval {
+        let mut ret = Interval::empty(self.prec());
+        if !self.is_empty() {
+            unsafe {
+                mpfr::neg(&mut ret.lo, &self.hi, rnd_t::RNDD);
+                mpfr::neg(&mut ret.hi, &self.lo, rnd_t::RNDU);
+            }
+        }
+        ret
+    }
+
+    /// Returns |`self`|.
+    pub fn abs(&self) -> Interval {
+        let mut ret = Interval::empty(self.prec());
+        if self.is_empty() {
+            return ret;
+        }
+        unsafe {
+            if mpfr::sgn(&self.lo) >= 0 {
+                mpfr::set(&mut ret.lo, &self.lo, rnd_t::RNDD);
+                mpfr::set(&mut ret.hi, &self.hi, rnd_t::RNDU);
+            } else if mpfr::sgn(&self.hi) <= 0 {
+                mpfr::neg(&mut ret.lo, &self.hi, rnd_t::RNDD);
+                mpfr::neg(&mut ret.hi, &self.lo, rnd_t::RNDU);
+            } else {
+                mpfr::set_zero(&mut ret.lo, 1);
+                mpfr::neg(&mut ret.hi, &self.lo, rnd_t::RNDU);
+                mpfr::max(&mut ret.hi, &ret.hi, &self.hi, rnd_t::RNDU);
+            }
+        }
+        ret
+    }
+
+    /// Returns `self` + `other`.
+    pub fn add(&self, other: &Interval) -> Interval {
+        let mut ret = Interval::empty(max_prec(self, other));
+        if !self.is_empty() && !other.is_empty() {
+            unsafe {
+                mpfr::add(&mut ret.lo, &self.lo, &other.lo, rnd_t::RNDD);
+                mpfr::add(&mut ret.hi, &self.hi, &other.hi, rnd_t::RNDU);
+            }
+        }
+        ret
+    }
+
+    /// Returns `self` − `other`.
+    pub fn sub(&self, other: &Interval) -> Interval {
+        let mut ret = Interval::empty(max_prec(self, other));
+        if !self.is_empty() && !other.is_empty() {
+            unsafe {
+                mpfr::sub(&mut ret.lo, &self.lo, &other.hi, rnd_t::RNDD);
+                mpfr::sub(&mut ret.hi, &self.hi, &other.lo, rnd_t::RNDU);
+            }
+        }
+        ret
+    }
+
+    /// Returns `self` × `other`.
+    pub fn mul(&self, other: &Interval) -> Interval {
+        let mut ret = Interval::empty(max_prec(self, other));
+        if self.is_empty() || other.is_empty() {
+            return ret;
+        }
+        // The extremes are at the corners. A zero endpoint times an
+        // infinite endpoint is taken as zero: the infinite endpoint is
+        // a limit, and zero times any real number is zero.
+        let corners = [
+            (&self.lo, &other.lo),
+            (&self.lo, &other.hi),
+            (&self.hi, &other.lo),
+            (&self.hi, &other.hi),
+        ];
+        let mut t = TempVar::new(ret.prec());
+        unsafe {
+            mpfr::set_inf(&mut ret.lo, 1);
+            mpfr::set_inf(&mut ret.hi, -1);
+            for &(a, b) in corners.iter() {
+                mul_corner(&mut t.0, a, b, rnd_t::RNDD);
+                mpfr::min(&mut ret.lo, &ret.lo, &t.0, rnd_t::RNDD);
+                mul_corner(&mut t.0, a, b, rnd_t::RNDU);
+                mpfr::max(&mut ret.hi, &ret.hi, &t.0, rnd_t::RNDU);
+            }
+        }
+        ret.normalize();
+        ret
+    }
+
+    /// Returns `self` ÷ `other`.
+    ///
+    /// If `other` contains zero, the result is [−∞, +∞], or empty if
+    /// `other` is [0, 0].
+    pub fn div(&self, other: &Interval) -> Interval {
+        let prec = max_prec(self, other);
+        if self.is_empty() || other.is_empty() {
+            return Interval::empty(prec);
+        }
+        unsafe {
+            if mpfr::sgn(&other.lo) <= 0 && mpfr::sgn(&other.hi) >= 0 {
+                if mpfr::zero_p(&other.lo) != 0 && mpfr::zero_p(&other.hi) != 0 {
+                    return Interval::empty(prec);
+                }
+                return Interval::whole(prec);
+            }
+            // 1/other does not contain zero, so it is monotonic
+            let mut recip = Interval::empty(other.prec());
+            mpfr::ui_div(&mut recip.lo, 1, &other.hi, rnd_t::RNDD);
+            mpfr::ui_div(&mut recip.hi, 1, &other.lo, rnd_t::RNDU);
+            let ret = self.mul(&recip);
+            if ret.prec() == prec {
+                ret
+            } else {
+                ret.with_prec(prec)
+            }
+        }
+    }
+
+    /// Returns the square root of `self`.
+    ///
+    /// Negative numbers are outside the domain.
+    pub fn sqrt(&self) -> Interval {
+        self.monotonic_nonnegative(|r, x, rnd| unsafe { mpfr::sqrt(r, x, rnd) })
+    }
+
+    /// Returns *e* raised to the power of `self`.
+    pub fn exp(&self) -> Interval {
+        self.monotonic(|r, x, rnd| unsafe { mpfr::exp(r, x, rnd) })
+    }
+
+    /// Returns the natural logarithm of `self`.
+    ///
+    /// Negative numbers are outside the domain, and log 0 is −∞.
+    pub fn log(&self) -> Interval {
+        self.monotonic_nonnegative(|r, x, rnd| unsafe { mpfr::log(r, x, rnd) })
+    }
+
+    /// Returns the arc-tangent of `self`.
+    pub fn atan(&self) -> Interval {
+        self.monotonic(|r, x, rnd| unsafe { mpfr::atan(r, x, rnd) })
+    }
+
+    /// Returns the sine of `self`.
+    pub fn sin(&self) -> Interval {
+        // the maximum is at (2k + 1/2)π and the minimum at (2k − 1/2)π
+        self.periodic(|r, x, rnd| unsafe { mpfr::sin(r, x, rnd) }, true)
+    }
+
+    /// Returns the cosine of `self`.
+    pub fn cos(&self) -> Interval {
+        // the maximum is at 2kπ and the minimum at (2k + 1)π
+        self.periodic(|r, x, rnd| unsafe { mpfr::cos(r, x, rnd) }, false)
+    }
+
+    /// Returns `self` raised to the power of `other`.
+    ///
+    /// Negative numbers are outside the domain of the base. As with
+    /// [`mpfr::pow`](../fn.pow.html), 0<sup>0</sup> = 1 and 0 raised to a negative
+    /// power is +∞.
+    pub fn pow(&self, other: &Interval) -> Interval {
+        let mut ret = Interval::empty(max_prec(self, other));
+        let base = self.nonnegative_part();
+        if base.is_empty() || other.is_empty() {
+            return ret;
+        }
+        // y log x is monotonic in both x and y in each quadrant, so
+        // the extremes of x^y are at the corners
+        let corners = [
+            (&base.lo, &other.lo),
+            (&base.lo, &other.hi),
+            (&base.hi, &other.lo),
+            (&base.hi, &other.hi),
+        ];
+        let mut t = TempVar::new(ret.prec());
+        unsafe {
+            mpfr::set_inf(&mut ret.lo, 1);
+            mpfr::set_inf(&mut ret.hi, -1);
+            for &(x, y) in corners.iter() {
+                mpfr::pow(&mut t.0, x, y, rnd_t::RNDD);
+                mpfr::min(&mut ret.lo, &ret.lo, &t.0, rnd_t::RNDD);
+                mpfr::pow(&mut t.0, x, y, rnd_t::RNDU);
+                mpfr::max(&mut ret.hi, &ret.hi, &t.0, rnd_t::RNDU);
+            }
+        }
+        ret.normalize();
+        ret
+    }
+
+    fn set_whole(&mut self) {
+        unsafe {
+            mpfr::set_inf(&mut self.lo, -1);
+            mpfr::set_inf(&mut self.hi, 1);
+        }
+    }
+
+    // Makes the interval empty if the endpoints do not describe a
+    // non-empty interval.
+    fn normalize(&mut self) {
+        unsafe {
+            let ok = mpfr::lessequal_p(&self.lo, &self.hi) != 0
+                && !(mpfr::inf_p(&self.lo) != 0 && mpfr::sgn(&self.lo) > 0)
+                && !(mpfr::inf_p(&self.hi) != 0 && mpfr::sgn(&self.hi) < 0);
+            if !ok {
+                mpfr::set_nan(&mut self.lo);
+                mpfr::set_nan(&mut self.hi);
+            }
+        }
+    }
+
+    fn with_prec(&self, prec: prec_t) -> Interval {
+        let mut ret = Interval::empty(prec);
+        if !self.is_empty() {
+            unsafe {
+                mpfr::set(&mut ret.lo, &self.lo, rnd_t::RNDD);
+                mpfr::set(&mut ret.hi, &self.hi, rnd_t::RNDU);
+            }
+        }
+        ret
+    }
+
+    // The intersection with [0, +∞]. Zero endpoints are set to +0, as
+    // functions such as pow treat −0 differently.
+    fn nonnegative_part(&self) -> Interval {
+        let mut ret = self.clone();
+        unsafe {
+            if !ret.is_empty() && mpfr::sgn(&ret.lo) <= 0 {
+                mpfr::set_zero(&mut ret.lo, 1);
+            }
+            if !ret.is_empty() && mpfr::zero_p(&ret.hi) != 0 {
+                mpfr::set_zero(&mut ret.hi, 1);
+            }
+        }
+        ret.normalize();
+        ret
+    }
+
+    fn monotonic<F: Fn1>(&self, f: F) -> Interval {
+        let mut ret = Interval::empty(self.prec());
+        if !self.is_empty() {
+            f(&mut ret.lo, &self.lo, rnd_t::RNDD);
+            f(&mut ret.hi, &self.hi, rnd_t::RNDU);
+            ret.normalize();
+        }
+        ret
+    }
+
+    fn monotonic_nonnegative<F: Fn1>(&self, f: F) -> Interval {
+        self.nonnegative_part().monotonic(f)
+    }
+
+    // Checks whether a bounded interval is at least 2π wide, so that
+    // it contains a whole period of sin and cos.
+    fn spans_period(&self) -> bool {
+        unsafe {
+            let mut width = TempVar::new(64);
+            mpfr::sub(&mut width.0, &self.hi, &self.lo, rnd_t::RNDD);
+            let mut two_pi = TempVar::new(64);
+            mpfr::const_pi(&mut two_pi.0, rnd_t::RNDU);
+            mpfr::mul_2ui(&mut two_pi.0, &two_pi.0, 1, rnd_t::RNDU);
+            mpfr::greaterequal_p(&width.0, &two_pi.0) != 0
+        }
+    }
+
+    // For sin and cos, which have period 2π. With shift, the extremes
+    // are at (k + 1/2)π, otherwise they are at kπ; the maximum is at
+    // even k and the minimum at odd k.
+    fn periodic<F: Fn1>(&self, f: F, shift: bool) -> Interval {
+        let prec = self.prec();
+        let mut ret = Interval::empty(prec);
+        if self.is_empty() {
+            return ret;
+        }
+        // Return early before sizing the working precision from the
+        // magnitude of the endpoints, which can be huge.
+        if !self.is_bounded() || self.spans_period() {
+            unsafe {
+                mpfr::set_si(&mut ret.lo, -1, rnd_t::RNDD);
+                mpfr::set_si(&mut ret.hi, 1, rnd_t::RNDU);
+            }
+            return ret;
+        }
+        unsafe {
+            f(&mut ret.lo, &self.lo, rnd_t::RNDD);
+            let mut t = TempVar::new(prec);
+            f(&mut t.0, &self.hi, rnd_t::RNDD);
+            mpfr::min(&mut ret.lo, &ret.lo, &t.0, rnd_t::RNDD);
+            f(&mut ret.hi, &self.lo, rnd_t::RNDU);
+            f(&mut t.0, &self.hi, rnd_t::RNDU);
+            mpfr::max(&mut ret.hi, &ret.hi, &t.0, rnd_t::RNDU);
+
+            // Enclose [lo, hi]/π in [u1, u2] with enough precision that
+            // the integers in it can be enumerated.
+            let magnitude = cmp::max(exponent(&self.lo), exponent(&self.hi));
+            let wprec = cmp::max(prec, 0) + cmp::max(magnitude, 0) + 64;
+            let mut pi_lo = TempVar::new(wprec);
+            let mut pi_hi = TempVar::new(wprec);
+            mpfr::const_pi(&mut pi_lo.0, rnd_t::RNDD);
+            mpfr::const_pi(&mut pi_hi.0, rnd_t::RNDU);
+            let mut u1 = TempVar::new(wprec);
+            let mut u2 = TempVar::new(wprec);
+            let div_pi = |u: *mut mpfr_t, x: &mpfr_t, down: bool| {
+                let rnd = if down { rnd_t::RNDD } else { rnd_t::RNDU };
+                // the smaller quotient has the larger divisor for a
+                // non-negative dividend
+                let larger = (mpfr::sgn(x) >= 0) == down;
+                let pi = if larger { &pi_hi.0 } else { &pi_lo.0 };
+                mpfr::div(u, x, pi, rnd);
+                if shift {
+                    mpfr::sub_d(u, u, 0.5, rnd);
+                }
+            };
+            div_pi(&mut u1.0, &self.lo, true);
+            div_pi(&mut u2.0, &self.hi, false);
+
+            // at most two integers matter, as they give both extremes
+            let mut k = TempVar::new(wprec);
+            let mut half = TempVar::new(wprec);
+            mpfr::rint_ceil(&mut k.0, &u1.0, rnd_t::RNDU);
+            for _ in 0..2 {
+                if mpfr::lessequal_p(&k.0, &u2.0) == 0 {
+                    break;
+                }
+                mpfr::div_2ui(&mut half.0, &k.0, 1, rnd_t::RNDN);
+                if mpfr::integer_p(&half.0) != 0 {
+                    mpfr::set_si(&mut ret.hi, 1, rnd_t::RNDU);
+                } else {
+                    mpfr::set_si(&mut ret.lo, -1, rnd_t::RNDD);
+                }
+                mpfr::add_ui(&mut k.0, &k.0, 1, rnd_t::RNDN);
+            }
+        }
+        ret
+    }
+}
+
+// A function such as mpfr::exp, wrapped in a closure as its ABI depends
+// on the compiler.
+trait Fn1: Fn(*mut mpfr_t, *const mpfr_t, rnd_t) -> c_int {}
+
+impl<F: Fn(*mut mpfr_t, *const mpfr_t, rnd_t) -> c_int> Fn1 for F {}
+
+fn max_prec(a: &Interval, b: &Interval) -> prec_t {
+    cmp::max(a.prec(), b.prec())
+}
+
+// The exponent of a regular number, or zero.
+fn exponent(x: &mpfr_t) -> exp_t {
+    unsafe {
+        if mpfr::regular_p(x) != 0 {
+            mpfr::get_exp(x)
+        } else {
+            0
+        }
+    }
+}
+
+unsafe fn mul_corner(rop: *mut mpfr_t, a: &mpfr_t, b: &mpfr_t, rnd: rnd_t) {
+    unsafe {
+        if mpfr::zero_p(a) != 0 || mpfr::zero_p(b) != 0 {
+            mpfr::set_zero(rop, 1);
+        } else {
+            mpfr::mul(rop, a, b, rnd);
+        }
+    }
+}
+
+impl Clone for Interval {
+    fn clone(&self) -> Interval {
+        self.with_prec(self.prec())
+    }
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        unsafe {
+            mpfr::clear(&mut self.lo);
+            mpfr::clear(&mut self.hi);
+        }
+    }
+}
+
+impl Debug for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if self.is_empty() {
+            return f.write_str("Interval(empty)");
+        }
+        let (lo, hi) = unsafe {
+            (
+                mpfr::get_d(&self.lo, rnd_t::RNDD),
+                mpfr::get_d(&self.hi, rnd_t::RNDU),
+            )
+        };
+        write!(f, "Interval([{:?}, {:?}])", lo, hi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mpfr::{self, interval::Interval, rnd_t};
+    use core::mem;
+
+    #[test]
+    fn check_arith_encloses() {
+        let x = Interval::from_f64(53, -1.0, 2.0);
+        let y = Interval::from_f64(53, 3.0, 5.0);
+        let p = x.mul(&y);
+        unsafe {
+            assert_eq!(mpfr::get_d(p.lo(), rnd_t::RNDD), -5.0);
+            assert_eq!(mpfr::get_d(p.hi(), rnd_t::RNDU), 10.0);
+        }
+        // 1/3 is not exact, so the endpoints must be rounded outwards
+        let q = Interval::from_f64(53, 1.0, 1.0).div(&Interval::from_f64(53, 3.0, 3.0));
+        unsafe {
+            assert!(mpfr::less_p(q.lo(), q.hi()) != 0);
+        }
+        assert!(q.contains_f64(1.0 / 3.0));
+        assert!(x.div(&x).lo_is_neg_inf());
+        let zero = Interval::from_f64(53, 0.0, 0.0);
+        assert!(y.div(&zero).is_empty());
+        let whole = Interval::whole(53);
+        let w = zero.mul(&whole);
+        assert!(w.contains_f64(0.0) && w.is_bounded());
+        assert!(Interval::empty(53).add(&y).is_empty());
+        assert!(Interval::from_f64(53, 2.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn check_periodic() {
+        // sin is decreasing in [3, 4]
+        let s = Interval::from_f64(64, 3.0, 4.0).sin();
+        assert!(s.contains_f64(3.5f64.sin()));
+        unsafe {
+            let hi = mpfr::get_d(s.hi(), rnd_t::RNDU);
+            assert!((hi - 3f64.sin()).abs() < 1e-15);
+            let lo = mpfr::get_d(s.lo(), rnd_t::RNDD);
+            assert!((lo - 4f64.sin()).abs() < 1e-15);
+        }
+        // contains π, where cos is −1
+        let c = Interval::from_f64(64, 3.0, 3.5).cos();
+        unsafe {
+            assert_eq!(mpfr::get_d(c.lo(), rnd_t::RNDD), -1.0);
+        }
+        // contains both 2π and 3π
+        let c = Interval::from_f64(64, 6.0, 9.5).cos();
+        unsafe {
+            assert_eq!(mpfr::get_d(c.lo(), rnd_t::RNDD), -1.0);
+            assert_eq!(mpfr::get_d(c.hi(), rnd_t::RNDU), 1.0);
+        }
+        let big = Interval::from_f64(53, 1e22, 1e22).sin();
+        assert!(big.contains_f64(-0.852_200_849_767_188_8));
+        let whole = Interval::whole(64).cos();
+        assert!(whole.is_bounded() && whole.contains_f64(0.5));
+        let wide = Interval::from_f64(64, 1.0, 7.5).sin();
+        unsafe {
+            assert_eq!(mpfr::get_d(wide.lo(), rnd_t::RNDD), -1.0);
+            assert_eq!(mpfr::get_d(wide.hi(), rnd_t::RNDU), 1.0);
+        }
+        // endpoints of magnitude 2^(2^29) would need a working
+        // precision of 2^29 bits if the width were not checked first
+        unsafe {
+            let mut lo = mem::MaybeUninit::uninit();
+            mpfr::init2(lo.as_mut_ptr(), 64);
+            let mut lo = lo.assume_init();
+            mpfr::set_si_2exp(&mut lo, -1, 1 << 29, rnd_t::RNDN);
+            let mut hi = mem::MaybeUninit::uninit();
+            mpfr::init2(hi.as_mut_ptr(), 64);
+            let mut hi = hi.assume_init();
+            mpfr::neg(&mut hi, &lo, rnd_t::RNDN);
+            let huge = Interval::from_mpfr(64, &lo, &hi).cos();
+            assert_eq!(mpfr::get_d(huge.lo(), rnd_t::RNDD), -1.0);
+            assert_eq!(mpfr::get_d(huge.hi(), rnd_t::RNDU), 1.0);
+            mpfr::clear(&mut lo);
+            mpfr::clear(&mut hi);
+        }
+    }
+
+    impl Interval {
+        fn lo_is_neg_inf(&self) -> bool {
+            unsafe { mpfr::inf_p(self.lo()) != 0 && mpfr::sgn(self.lo()) < 0 }
+        }
+    }
+
+    #[test]
+    fn check_domain() {
+        let x = Interval::from_f64(53, -4.0, 4.0);
+        let r = x.sqrt();
+        unsafe {
+            assert_eq!(mpfr::get_d(r.lo(), rnd_t::RNDD), 0.0);
+            assert_eq!(mpfr::get_d(r.hi(), rnd_t::RNDU), 2.0);
+        }
+        assert!(x.log().lo_is_neg_inf());
+        let p = Interval::from_f64(53, 2.0, 3.0).pow(&Interval::from_f64(53, -1.0, 2.0));
+        assert!(p.contains_f64(1.0 / 3.0) && !p.contains_f64(0.333));
+        unsafe {
+            assert_eq!(mpfr::get_d(p.hi(), rnd_t::RNDU), 9.0);
+        }
+        // a −0 endpoint is treated as +0, so 0^−1 is +∞, not −∞
+        let minus_one = Interval::from_f64(53, -1.0, -1.0);
+        let p = Interval::from_f64(53, -0.0, 1.0).pow(&minus_one);
+        assert!(p.contains_f64(2.0) && !p.lo_is_neg_inf());
+        // like division by [0, 0], [+∞, +∞] is empty
+        let p = Interval::from_f64(53, -0.0, -0.0).pow(&minus_one);
+        assert!(p.is_empty());
+    }
+}
@@ -0,0 +1,530 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// This program is free software: you can redistribute it and/or
+// modify it under the terms of the GNU Lesser General Public License
+// as published by the Free Software Foundation, either version 3 of
+// the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public
+// License and a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+
+/*!
+Owned wrappers that initialize the C types on construction and clear
+them on drop.
+
+This module is only available when the experimental `owned` feature is
+enabled.
+
+Each type is a transparent wrapper around the corresponding C type, so
+it has the same size and layout. [`as_raw`] and [`as_raw_mut`] return
+pointers that can be passed to the functions of the [`gmp`], [`mpfr`]
+and [`mpc`] modules, and [`into_raw`] and [`from_raw`] transfer
+ownership to and from the C types.
+
+Unlike the C types, the owned types do not implement `Copy`, and
+`Clone` makes a deep copy, so a value cannot be cleared twice.
+
+# Examples
+
+```rust
+use gmp_mpfr_sys::{gmp, owned::Mpz};
+let mut z = Mpz::new();
+unsafe {
+    gmp::mpz_set_ui(z.as_raw_mut(), 15);
+}
+let mut w = z.clone();
+unsafe {
+    gmp::mpz_mul_ui(w.as_raw_mut(), z.as_raw(), 2);
+    assert_eq!(gmp::mpz_get_ui(z.as_raw()), 15);
+    assert_eq!(gmp::mpz_get_ui(w.as_raw()), 30);
+}
+// z and w are cleared when dropped
+```
+
+[`as_raw_mut`]: struct.Mpz.html#method.as_raw_mut
+[`as_raw`]: struct.Mpz.html#method.as_raw
+[`from_raw`]: struct.Mpz.html#method.from_raw
+[`gmp`]: ../gmp/index.html
+[`into_raw`]: struct.Mpz.html#method.into_raw
+[`mpc`]: ../mpc/index.html
+[`mpfr`]: ../mpfr/index.html
+*/
+
+use crate::gmp::{self, bitcnt_t, mpf_t, mpq_t, mpz_t, randstate_t};
+#[cfg(feature = "mpc")]
+use crate::mpc::{self, mpc_t};
+#[cfg(feature = "mpfr")]
+use crate::mpfr::{self, mpfr_t, prec_t};
+use core::mem::{self, MaybeUninit};
+
+macro_rules! raw_methods {
+    ($Owned:ident, $raw:ident) => {
+        impl $Owned {
+            /// Returns a pointer to the inner C value.
+            #[inline]
+            pub fn as_raw(&self) -> *const $raw {
+                &self.inner
+            }
+
+            /// Returns a mutable pointer to the inner C value.
+            #[inline]
+            pub fn as_raw_mut(&mut self) -> *mut $raw {
+                &mut self.inner
+            }
+
+            /// Converts the value into the inner C value without
+            /// clearing it.
+            ///
+            /// The caller becomes responsible for clearing the returned
+            /// value.
+            #[inline]
+            pub fn into_raw(self) -> $raw {
+                let inner = self.inner;
+                mem::forget(self);
+                inner
+            }
+
+            /// Creates a value from an initialized C value, taking
+            /// ownership of it.
+            ///
+            /// # Safety
+            ///
+            /// `raw` must be initialized, and it must not be cleared or
+            /// used anywhere else afterwards, as the returned value
+            /// will clear it when dropped.
+            #[inline]
+            pub unsafe fn from_raw(raw: $raw) -> Self {
+                $Owned { inner: raw }
+            }
+        }
+
+        // The C libraries are reentrant, and values are only modified
+        // through mutable references.
+        unsafe impl Send for $Owned {}
+        unsafe impl Sync for $Owned {}
+    };
+}
+
+/// An owned [`mpz_t`](../gmp/struct.mpz_t.html), which is initialized
+/// using [`mpz_init`](../gmp/fn.mpz_init.html) and cleared using
+/// [`mpz_clear`](../gmp/fn.mpz_clear.html).
+///
+/// See the [module documentation](index.html) for an example.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct Mpz {
+    inner: mpz_t,
+}
+
+impl Mpz {
+    /// Creates an integer with the value 0.
+    #[inline]
+    pub fn new() -> Mpz {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init(z.as_mut_ptr());
+            Mpz {
+                inner: z.assume_init(),
+            }
+        }
+    }
+}
+
+raw_methods! { Mpz, mpz_t }
+
+impl Default for Mpz {
+    #[inline]
+    fn default() -> Mpz {
+        Mpz::new()
+    }
+}
+
+impl Clone for Mpz {
+    #[inline]
+    fn clone(&self) -> Mpz {
+        unsafe {
+            let mut z = MaybeUninit::uninit();
+            gmp::mpz_init_set(z.as_mut_ptr(), &self.inner);
+            Mpz {
+                inner: z.assume_init(),
+            }
+        }
+    }
+
+    #[inline]
+    fn clone_from(&mut self, source: &Mpz) {
+        unsafe { gmp::mpz_set(&mut self.inner, &source.inner) }
+    }
+}
+
+impl Drop for Mpz {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { gmp::mpz_clear(&mut self.inner) }
+    }
+}
+
+/// An owned [`mpq_t`](../gmp/struct.mpq_t.html), which is initialized
+/// using [`mpq_init`](../gmp/fn.mpq_init.html) and cleared using
+/// [`mpq_clear`](../gmp/fn.mpq_clear.html).
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct Mpq {
+    inner: mpq_t,
+}
+
+impl Mpq {
+    /// Creates a rational number with the value 0/1.
+    #[inline]
+    pub fn new() -> Mpq {
+        unsafe {
+            let mut q = MaybeUninit::uninit();
+            gmp::mpq_init(q.as_mut_ptr());
+            Mpq {
+                inner: q.assume_init(),
+            }
+        }
+    }
+}
+
+raw_methods! { Mpq, mpq_t }
+
+impl Default for Mpq {
+    #[inline]
+    fn default() -> Mpq {
+        Mpq::new()
+    }
+}
+
+impl Clone for Mpq {
+    #[inline]
+    fn clone(&self) -> Mpq {
+        let mut q = Mpq::new();
+        unsafe { gmp::mpq_set(&mut q.inner, &self.inner) }
+        q
+    }
+
+    #[inline]
+    fn clone_from(&mut self, source: &Mpq) {
+        unsafe { gmp::mpq_set(&mut self.inner, &source.inner) }
+    }
+}
+
+impl Drop for Mpq {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { gmp::mpq_clear(&mut self.inner) }
+    }
+}
+
+/// An owned [`mpf_t`](../gmp/struct.mpf_t.html), which is initialized
+/// using [`mpf_init2`](../gmp/fn.mpf_init2.html) and cleared using
+/// [`mpf_clear`](../gmp/fn.mpf_clear.html).
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct Mpf {
+    inner: mpf_t,
+}
+
+impl Mpf {
+    /// Creates a floating-point number with the value 0 and a precision
+    /// of at least `prec` bits.
+    #[inline]
+    pub fn new(prec: bitcnt_t) -> Mpf {
+        unsafe {
+            let mut f = MaybeUninit::uninit();
+            gmp::mpf_init2(f.as_mut_ptr(), prec);
+            Mpf {
+                inner: f.assume_init(),
+            }
+        }
+    }
+}
+
+raw_methods! { Mpf, mpf_t }
+
+impl Clone for Mpf {
+    /// Clones the value, keeping its precision.
+    #[inline]
+    fn clone(&self) -> Mpf {
+        let mut f = Mpf::new(unsafe { gmp::mpf_get_prec(&self.inner) });
+        unsafe { gmp::mpf_set(&mut f.inner, &self.inner) }
+        f
+    }
+}
+
+impl Drop for Mpf {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { gmp::mpf_clear(&mut self.inner) }
+    }
+}
+
+/// An owned [`mpfr_t`](../mpfr/struct.mpfr_t.html), which is
+/// initialized using [`init2`](../mpfr/fn.init2.html) and cleared using
+/// [`clear`](../mpfr/fn.clear.html).
+///
+/// This type is only available when the `mpfr` feature is enabled.
+#[cfg(feature = "mpfr")]
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct Mpfr {
+    inner: mpfr_t,
+}
+
+#[cfg(feature = "mpfr")]
+impl Mpfr {
+    /// Creates a floating-point number with the value NaN and a
+    /// precision of `prec` bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prec` is not in the range
+    /// <code>[PREC_MIN](../mpfr/constant.PREC_MIN.html)..=[PREC_MAX](../mpfr/constant.PREC_MAX.html)</code>.
+    #[inline]
+    pub fn new(prec: prec_t) -> Mpfr {
+        assert!(
+            (mpfr::PREC_MIN..=mpfr::PREC_MAX).contains(&prec),
+            "precision out of range"
+        );
+        unsafe {
+            let mut f = MaybeUninit::uninit();
+            mpfr::init2(f.as_mut_ptr(), prec);
+            Mpfr {
+                inner: f.assume_init(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "mpfr")]
+raw_methods! { Mpfr, mpfr_t }
+
+#[cfg(feature = "mpfr")]
+impl Clone for Mpfr {
+    /// Clones the value, keeping its precision.
+    #[inline]
+    fn clone(&self) -> Mpfr {
+        let mut f = Mpfr::new(unsafe { mpfr::get_prec(&self.inner) });
+        unsafe {
+            mpfr::set(&mut f.inner, &self.inner, mpfr::rnd_t::RNDN);
+        }
+        f
+    }
+}
+
+#[cfg(feature = "mpfr")]
+impl Drop for Mpfr {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { mpfr::clear(&mut self.inner) }
+    }
+}
+
+/// An owned [`mpc_t`](../mpc/struct.mpc_t.html), which is initialized
+/// using [`init3`](../mpc/fn.init3.html) and cleared using
+/// [`clear`](../mpc/fn.clear.html).
+///
+/// This type is only available when the `mpc` feature is enabled.
+#[cfg(feature = "mpc")]
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct Mpc {
+    inner: mpc_t,
+}
+
+#[cfg(feature = "mpc")]
+impl Mpc {
+    /// Creates a complex number with NaN parts, and precisions of
+    /// `prec_re` and `prec_im` bits for the real and imaginary parts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a precision is not in the range
+    /// <code>[PREC_MIN](../mpfr/constant.PREC_MIN.html)..=[PREC_MAX](../mpfr/constant.PREC_MAX.html)</code>.
+    #[inline]
+    pub fn new(prec_re: prec_t, prec_im: prec_t) -> Mpc {
+        assert!(
+            (mpfr::PREC_MIN..=mpfr::PREC_MAX).contains(&prec_re)
+                && (mpfr::PREC_MIN..=mpfr::PREC_MAX).contains(&prec_im),
+            "precision out of range"
+        );
+        unsafe {
+            let mut c = MaybeUninit::uninit();
+            mpc::init3(c.as_mut_ptr(), prec_re, prec_im);
+            Mpc {
+                inner: c.assume_init(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "mpc")]
+raw_methods! { Mpc, mpc_t }
+
+#[cfg(feature = "mpc")]
+impl Clone for Mpc {
+    /// Clones the value, keeping the precisions of its parts.
+    #[inline]
+    fn clone(&self) -> Mpc {
+        let (prec_re, prec_im) = unsafe {
+            (
+                mpfr::get_prec(mpc::realref_const(&self.inner)),
+                mpfr::get_prec(mpc::imagref_const(&self.inner)),
+            )
+        };
+        let mut c = Mpc::new(prec_re, prec_im);
+        unsafe {
+            mpc::set(&mut c.inner, &self.inner, mpc::RNDNN);
+        }
+        c
+    }
+}
+
+#[cfg(feature = "mpc")]
+impl Drop for Mpc {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { mpc::clear(&mut self.inner) }
+    }
+}
+
+/// An owned [`randstate_t`](../gmp/struct.randstate_t.html), which is
+/// initialized using for example
+/// [`randinit_default`](../gmp/fn.randinit_default.html) and cleared
+/// using [`randclear`](../gmp/fn.randclear.html).
+///
+/// A value created using [`from_raw`](#method.from_raw) with a
+/// custom algorithm must be safe to send and share between threads.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct RandState {
+    inner: randstate_t,
+}
+
+impl RandState {
+    /// Creates a random number generator with the default algorithm.
+    #[inline]
+    pub fn new() -> RandState {
+        unsafe {
+            let mut state = MaybeUninit::uninit();
+            gmp::randinit_default(state.as_mut_ptr());
+            RandState {
+                inner: state.assume_init(),
+            }
+        }
+    }
+
+    /// Creates a Mersenne Twister random number generator.
+    #[inline]
+    pub fn new_mersenne_twister() -> RandState {
+        unsafe {
+            let mut state = MaybeUninit::uninit();
+            gmp::randinit_mt(state.as_mut_ptr());
+            RandState {
+                inner: state.assume_init(),
+            }
+        }
+    }
+}
+
+raw_methods! { RandState, randstate_t }
+
+impl Default for RandState {
+    #[inline]
+    fn default() -> RandState {
+        RandState::new()
+    }
+}
+
+impl Clone for RandState {
+    /// Clones the generator, including its algorithm and its state.
+    #[inline]
+    fn clone(&self) -> RandState {
+        unsafe {
+            let mut state = MaybeUninit::uninit();
+            gmp::randinit_set(state.as_mut_ptr(), &self.inner);
+            RandState {
+                inner: state.assume_init(),
+            }
+        }
+    }
+}
+
+impl Drop for RandState {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { gmp::randclear(&mut self.inner) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gmp,
+        owned::{Mpq, Mpz, RandState},
+    };
+    use core::mem;
+
+    #[test]
+    fn check_layout() {
+        assert_eq!(mem::size_of::<Mpz>(), mem::size_of::<gmp::mpz_t>());
+        assert_eq!(mem::size_of::<Mpq>(), mem::size_of::<gmp::mpq_t>());
+        assert_eq!(
+            mem::size_of::<RandState>(),
+            mem::size_of::<gmp::randstate_t>()
+        );
+    }
+
+    #[test]
+    fn check_clone_and_raw() {
+        let mut z = Mpz::new();
+        unsafe {
+            gmp::mpz_ui_pow_ui(z.as_raw_mut(), 7, 100);
+        }
+        let mut w = Mpz::new();
+        w.clone_from(&z);
+        unsafe {
+            gmp::mpz_add_ui(z.as_raw_mut(), z.as_raw(), 1);
+            assert!(gmp::mpz_cmp(z.as_raw(), w.as_raw()) > 0);
+        }
+        let mut raw = w.into_raw();
+        unsafe {
+            assert_eq!(gmp::mpz_sizeinbase(&raw, 7), 101);
+            gmp::mpz_sub_ui(&mut raw, &raw, 1);
+            let mut w = Mpz::from_raw(raw);
+            gmp::mpz_add_ui(w.as_raw_mut(), w.as_raw(), 2);
+            assert_eq!(gmp::mpz_cmp(z.as_raw(), w.as_raw()), 0);
+        }
+
+        let mut state = RandState::new();
+        let mut copy = state.clone();
+        unsafe {
+            let a = gmp::urandomb_ui(state.as_raw_mut(), 32);
+            let b = gmp::urandomb_ui(copy.as_raw_mut(), 32);
+            assert_eq!(a, b);
+        }
+    }
+
+    #[cfg(feature = "mpc")]
+    #[test]
+    fn check_mpc_clone() {
+        use crate::{mpc, mpfr, owned::Mpc};
+        let mut c = Mpc::new(20, 30);
+        unsafe {
+            mpc::set_si_si(c.as_raw_mut(), 1, -1, mpc::RNDNN);
+        }
+        let d = c.clone();
+        unsafe {
+            assert_eq!(mpfr::get_prec(mpc::realref_const(d.as_raw())), 20);
+            assert_eq!(mpfr::get_prec(mpc::imagref_const(d.as_raw())), 30);
+            assert_eq!(mpc::cmp_si_si(d.as_raw(), 1, -1), 0);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// Copying and distribution of this file, with or without modification,
+// are permitted in any medium without royalty provided the copyright
+// notice and this notice are preserved. This file is offered as-is,
+// without any warranty.
+
+// The memory functions are global, so they are installed in this
+// separate test binary, and everything is checked in a single test so
+// that the counters are not affected by other tests.
+
+#![cfg(feature = "accounting")]
+
+use core::mem::MaybeUninit;
+use gmp_mpfr_sys::gmp::{
+    self,
+    accounting::{self, Action},
+};
+use std::panic::{self, AssertUnwindSafe};
+
+#[test]
+fn check_accounting() {
+    unsafe {
+        accounting::install();
+    }
+    let start = accounting::global_usage();
+
+    let (mut z, usage) = accounting::measure(|| unsafe {
+        let mut z = MaybeUninit::uninit();
+        gmp::mpz_init(z.as_mut_ptr());
+        let mut z = z.assume_init();
+        gmp::mpz_ui_pow_ui(&mut z, 2, 80_000);
+        z
+    });
+    // 2^80000 needs 10001 bytes
+    assert!(usage.live >= 10_001);
+    assert!(usage.peak >= usage.live);
+    assert!(usage.count >= 1);
+    let global = accounting::global_usage();
+    assert!(global.live >= start.live + usage.live);
+    assert!(global.count >= start.count + usage.count);
+
+    let (_, usage) = accounting::measure(|| unsafe { gmp::mpz_clear(&mut z) });
+    assert_eq!(usage.live, 0);
+    assert_eq!(usage.peak, 0);
+    assert_eq!(usage.count, 0);
+    assert!(accounting::thread_usage().peak >= 10_001);
+
+    if !Action::Panic.is_supported() {
+        return;
+    }
+    accounting::set_action(Action::Panic);
+    accounting::set_limit(Some(accounting::global_usage().live + 100_000));
+    unsafe {
+        let mut tmp = MaybeUninit::uninit();
+        gmp::mpz_init(tmp.as_mut_ptr());
+        let mut tmp = tmp.assume_init();
+        let tmp_ptr: *mut gmp::mpz_t = &mut tmp;
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            gmp::mpz_ui_pow_ui(tmp_ptr, 2, 10_000_000);
+        }));
+        let msg = res.expect_err("limit not enforced");
+        let msg = msg.downcast_ref::<String>().expect("message not a String");
+        assert!(msg.contains("exceeds the memory limit"));
+        // tmp can be invalid, so it is abandoned, but new allocations
+        // still work
+        accounting::set_limit(None);
+        let mut z = MaybeUninit::uninit();
+        gmp::mpz_init(z.as_mut_ptr());
+        let mut z = z.assume_init();
+        gmp::mpz_ui_pow_ui(&mut z, 3, 100);
+        assert_eq!(gmp::mpz_sizeinbase(&z, 3), 101);
+        gmp::mpz_clear(&mut z);
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright © 2017–2021 Trevor Spiteri
+
+// Copying and distribution of this file, with or without modification,
+// are permitted in any medium without royalty provided the copyright
+// notice and this notice are preserved. This file is offered as-is,
+// without any warranty.
+
+// The memory functions are global, so they are installed in this
+// separate test binary, and everything is checked in a single test so
+// that no objects allocated with other memory functions are around.
+
+#![cfg(feature = "rust-alloc")]
+
+use core::{
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use gmp_mpfr_sys::gmp;
+use std::alloc::{GlobalAlloc, Layout, System};
+
+// A global allocator that counts allocations and deallocations, so
+// that the test can check that the C libraries go through it.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(1, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        DEALLOCATED.fetch_add(1, Ordering::SeqCst);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(1, Ordering::SeqCst);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Returns the number of allocations, including reallocations, and of
+// deallocations.
+fn counts() -> (usize, usize) {
+    (
+        ALLOCATED.load(Ordering::SeqCst),
+        DEALLOCATED.load(Ordering::SeqCst),
+    )
+}
+
+#[test]
+fn check_rust_memory_functions() {
+    unsafe {
+        gmp::set_rust_memory_functions();
+        let mut alloc = None;
+        let mut realloc = None;
+        let mut free = None;
+        gmp::get_memory_functions(&mut alloc, &mut realloc, &mut free);
+        let rust_alloc: gmp::allocate_function = Some(gmp::rust_allocate);
+        let rust_realloc: gmp::reallocate_function = Some(gmp::rust_reallocate);
+        let rust_free: gmp::free_function = Some(gmp::rust_free);
+        assert_eq!(alloc.map(|f| f as usize), rust_alloc.map(|f| f as usize));
+        assert_eq!(
+            realloc.map(|f| f as usize),
+            rust_realloc.map(|f| f as usize)
+        );
+        assert_eq!(free.map(|f| f as usize), rust_free.map(|f| f as usize));
+
+        // reallocation while growing and shrinking
+        let mut z = MaybeUninit::uninit();
+        gmp::mpz_init(z.as_mut_ptr());
+        let mut z = z.assume_init();
+        let before = counts();
+        gmp::mpz_ui_pow_ui(&mut z, 3, 10_000);
+        assert!(counts().0 > before.0);
+        gmp::mpz_fdiv_q_2exp(&mut z, &z, 15_000);
+        gmp::mpz_realloc2(&mut z, 1);
+        gmp::mpz_set_ui(&mut z, 7);
+        assert_eq!(gmp::mpz_get_ui(&z), 7);
+        let before = counts();
+        gmp::mpz_clear(&mut z);
+        assert!(counts().1 > before.1);
+    }
+
+    #[cfg(feature = "mpfr")]
+    unsafe {
+        use gmp_mpfr_sys::mpfr;
+        let before = counts();
+        let mut f = MaybeUninit::uninit();
+        mpfr::init2(f.as_mut_ptr(), 1000);
+        let mut f = f.assume_init();
+        assert!(counts().0 > before.0);
+        let before = counts();
+        mpfr::const_pi(&mut f, mpfr::rnd_t::RNDN);
+        assert!(counts().0 > before.0);
+        mpfr::set_prec(&mut f, 10_000);
+        mpfr::set_ui(&mut f, 2, mpfr::rnd_t::RNDN);
+        mpfr::sqrt(&mut f, &f, mpfr::rnd_t::RNDN);
+        assert!(mpfr::cmp_d(&f, 1.4) > 0 && mpfr::cmp_d(&f, 1.5) < 0);
+        mpfr::clear(&mut f);
+        // frees the constant cache allocated by const_pi
+        let before = counts();
+        mpfr::free_cache();
+        assert!(counts().1 > before.1);
+    }
+
+    #[cfg(feature = "mpc")]
+    unsafe {
+        use gmp_mpfr_sys::{mpc, mpfr};
+        let before = counts();
+        let mut c = MaybeUninit::uninit();
+        mpc::init2(c.as_mut_ptr(), 500);
+        let mut c = c.assume_init();
+        assert!(counts().0 > before.0);
+        mpc::set_si_si(&mut c, 3, -4, mpc::RNDNN);
+        mpc::sqr(&mut c, &c, mpc::RNDNN);
+        assert_eq!(mpfr::cmp_si(mpc::realref_const(&c), -7), 0);
+        assert_eq!(mpfr::cmp_si(mpc::imagref_const(&c), -24), 0);
+        // the modulus of -7 - 24i is 25
+        let mut f = MaybeUninit::uninit();
+        mpfr::init2(f.as_mut_ptr(), 500);
+        let mut f = f.assume_init();
+        mpc::abs(&mut f, &c, mpfr::rnd_t::RNDN);
+        assert_eq!(mpfr::cmp_si(&f, 25), 0);
+        mpfr::clear(&mut f);
+        // allocates the constant cache again
+        mpc::exp(&mut c, &c, mpc::RNDNN);
+        let before = counts();
+        mpc::clear(&mut c);
+        assert!(counts().1 > before.1);
+        let before = counts();
+        mpfr::free_cache();
+        assert!(counts().1 > before.1);
+    }
+}